@@ -4,10 +4,16 @@ use angi_utils::read_byte::{
     read_u32_from_end_of_file,
 };
 use std::fs;
+use std::io;
 use std::path::Path;
 use std::sync::Arc;
 use std::{collections::HashMap, fmt::Debug, fs::File};
 
+/// Entries under this prefix are served as static files.
+pub const STATIC_PREFIX: &str = "static";
+/// Entries under this prefix are templates, keyed by the path the program renders.
+pub const TEMPLATE_PREFIX: &str = "template";
+
 type EntryName = String;
 type Manifest = HashMap<EntryName, Entry>;
 
@@ -93,27 +99,28 @@ impl Archiver {
         manifest_byte
     }
 
-    #[allow(unused)]
-    fn archive_dir(&mut self, dir: &str) {
-        let base = Path::new(dir);
-
-        if !base.exists() {
-            return;
-        }
-
-        for entry in walkdir::WalkDir::new(base) {
-            let entry = entry.unwrap();
+    /// Add every file under `dir` as `<prefix>/<relative path>`, with `/`
+    /// separators on every platform.
+    pub fn archive_dir(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry?;
             let path = entry.path();
 
             if path.is_file() {
-                let content = fs::read(path).unwrap();
+                let content = fs::read(path)?;
 
-                let relative = path.strip_prefix(base).unwrap();
-                let key = format!("{}/{}", dir, relative.to_string_lossy());
+                let relative = path.strip_prefix(dir).expect("Entry is inside the dir");
+                let name: Vec<String> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                let key = format!("{}/{}", prefix, name.join("/"));
 
                 self.archive(content, &key);
             }
         }
+
+        Ok(())
     }
 }
 
@@ -253,17 +260,40 @@ pub enum ExtractorError {
 }
 
 pub struct StaticStore {
-    extractor: Arc<Extractor>,
+    extractor: Option<Arc<Extractor>>,
 }
 
 impl StaticStore {
     pub fn new(extractor: Extractor) -> Self {
         Self {
-            extractor: Arc::new(extractor),
+            extractor: Some(Arc::new(extractor)),
         }
     }
 
+    /// A store without entries, for programs that were not archived.
+    pub fn empty() -> Self {
+        Self { extractor: None }
+    }
+
     pub fn get(&self, path: &str) -> Option<Vec<u8>> {
-        self.extractor.extract_blob(path.to_string())
+        self.extractor.as_ref()?.extract_blob(path.to_string())
+    }
+
+    /// The static file served at `path`, relative to the static dir.
+    pub fn static_file(&self, path: &str) -> Option<Vec<u8>> {
+        self.get(&format!("{STATIC_PREFIX}/{path}"))
+    }
+
+    /// The template the program renders as `path`.
+    pub fn template(&self, path: &str) -> Option<String> {
+        String::from_utf8(self.get(&format!("{TEMPLATE_PREFIX}/{path}"))?).ok()
+    }
+
+    /// Whether any static file was bundled.
+    pub fn has_static_files(&self) -> bool {
+        let prefix = format!("{STATIC_PREFIX}/");
+        self.extractor
+            .as_ref()
+            .is_some_and(|extractor| extractor.manifest.keys().any(|name| name.starts_with(&prefix)))
     }
 }
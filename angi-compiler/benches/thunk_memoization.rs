@@ -0,0 +1,58 @@
+//! Compare forcing the same lazy config with and without the thunk cache.
+//!
+//! Run with `cargo bench -p angi --bench thunk_memoization`.
+
+use std::time::{Duration, Instant};
+
+use angi::compiler::compile;
+use angi_runtime::value::{List, Table};
+use angi_runtime::vm::VM;
+
+const ITERATIONS: u32 = 200;
+const ROUTES: usize = 200;
+
+fn source() -> String {
+    let routes = (0..ROUTES)
+        .map(|i| {
+            format!(
+                r#"{{ path = "/route" .. "/{i}"; title = "Route " .. "{i}" .. " of the app"; weight = {i} * 3 + 7; }},"#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{{\n    port = 3000;\n    routes = [\n{routes}\n    ];\n}}\n")
+}
+
+fn force_routes(vm: &mut VM) {
+    let mut routes = vm.eval::<List<Table>>("routes").unwrap();
+    routes.force(vm);
+    assert_eq!(routes.iter().unwrap().count(), ROUTES);
+}
+
+fn run(vm: &mut VM, memoized: bool) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        if !memoized {
+            vm.clear_thunk_cache();
+        }
+        force_routes(vm);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let bytecode = compile(&source(), "bench.ag").unwrap();
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    let without_cache = run(&mut vm, false);
+    let with_cache = run(&mut vm, true);
+
+    println!("forcing {ROUTES} routes x {ITERATIONS}");
+    println!("  without cache: {:>10.2?}", without_cache);
+    println!("  with cache:    {:>10.2?}", with_cache);
+    println!(
+        "  speedup:       {:>10.1}x",
+        without_cache.as_secs_f64() / with_cache.as_secs_f64()
+    );
+}
@@ -50,4 +50,5 @@ fn main() {
     fs::copy(&bin_path, &dst).expect("failed to copy server");
 
     println!("cargo:rustc-env=SERVER_PATH={}", dst.display());
+    println!("cargo:rustc-env=ANGI_HOST_TARGET={}", env::var("TARGET").unwrap());
 }
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use angi_ins::{CONST_MASK, OpCode, Operand, REG_MASK, VERSION, format_version};
+use angi_runtime::modules::get_default_foreign_function;
+
+use crate::disassembler::{Const, FunctionEntry, GlobalEntry, Image};
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[AssemblyError] line {}: {}", self.line, self.message)
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> AssemblyError {
+    AssemblyError { line, message: message.into() }
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Header,
+    Const,
+    Thunk,
+    Function,
+    Global,
+    Code,
+}
+
+/// Assemble `.avm` text, the format `Image::disassemble` prints:
+///
+/// ```text
+/// .format 0.1
+/// .const
+///     #1 str "port"
+///     #2 int 3030
+///     #3 float 1.5        ; also `bool true` and `none`
+/// .thunk
+///     %1 body            ; entry label
+/// .function
+///     @1 body, 1         ; entry label, argument count
+/// .global
+///     #1 @1              ; name constant, function
+/// .code
+///     MAKETABLE r0
+///     LOADCONST r1, #1
+///     ...
+/// body:
+///     JMPIFNOT r0, done
+/// ```
+///
+/// Operands are `rN` registers, `#N` constants, `%N` thunks, `@N` functions,
+/// `$N` or `$name` foreign functions and labels for jump targets. Table
+/// entries must be numbered from 1 in order. `;` starts a comment and
+/// `.word 0x...` emits a raw instruction word.
+pub fn assemble(src: &str) -> Result<Image, AssemblyError> {
+    let mut image = Image {
+        version: VERSION,
+        consts: vec![],
+        thunks: vec![],
+        functions: vec![],
+        globals: vec![],
+        code: vec![],
+    };
+
+    let mut section = Section::Header;
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut thunk_targets = vec![];
+    let mut function_targets = vec![];
+    let mut instructions = vec![];
+
+    for (line_idx, raw) in src.lines().enumerate() {
+        let line = line_idx + 1;
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(directive) = text.strip_prefix('.')
+            && !directive.starts_with("word")
+        {
+            let mut parts = directive.split_whitespace();
+            section = match parts.next().unwrap_or_default() {
+                "format" => {
+                    image.version = parse_version(line, parts.next())?;
+                    Section::Header
+                }
+                "const" => Section::Const,
+                "thunk" => Section::Thunk,
+                "function" => Section::Function,
+                "global" => Section::Global,
+                "code" => Section::Code,
+                other => return Err(error(line, format!("unknown directive .{other}"))),
+            };
+            continue;
+        }
+
+        match section {
+            Section::Header => return Err(error(line, "expected a section directive")),
+            Section::Const => {
+                let rest = entry(line, text, '#', image.consts.len())?;
+                let (kind, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let value = value.trim();
+                let constant = match kind {
+                    "int" => Const::Int(parse_number(line, value)?),
+                    "str" => Const::Str(parse_string(line, value)?),
+                    "bool" => Const::Bool(match value {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(error(line, format!("invalid bool {value}"))),
+                    }),
+                    "none" => Const::None,
+                    "float" => Const::Float(
+                        value.parse().map_err(|_| error(line, format!("invalid float {value}")))?,
+                    ),
+                    other => return Err(error(line, format!("unknown constant type {other}"))),
+                };
+                image.consts.push(constant);
+            }
+            Section::Thunk => {
+                let target = entry(line, text, '%', image.thunks.len())?;
+                thunk_targets.push((line, target.to_string()));
+                image.thunks.push(0);
+            }
+            Section::Function => {
+                let rest = entry(line, text, '@', image.functions.len())?;
+                let (target, nargs) = rest
+                    .split_once(',')
+                    .ok_or_else(|| error(line, "expected `label, argument count`"))?;
+                function_targets.push((line, target.trim().to_string()));
+                image.functions.push(FunctionEntry {
+                    nargs: parse_number(line, nargs.trim())?,
+                    ins: 0,
+                });
+            }
+            Section::Global => {
+                let (name, function) = text
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| error(line, "expected `#name @function`"))?;
+                image.globals.push(GlobalEntry {
+                    name: prefixed(line, name, '#')?,
+                    function: prefixed(line, function.trim(), '@')?,
+                });
+            }
+            Section::Code => {
+                if let Some(label) = text.strip_suffix(':') {
+                    let label = label.trim();
+                    if labels.insert(label.to_string(), instructions.len() as u32).is_some() {
+                        return Err(error(line, format!("label {label} is defined twice")));
+                    }
+                } else {
+                    instructions.push((line, text));
+                }
+            }
+        }
+    }
+
+    let resolve = |line: usize, label: &str| {
+        labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| error(line, format!("unknown label {label}")))
+    };
+
+    for (thunk, (line, target)) in image.thunks.iter_mut().zip(&thunk_targets) {
+        *thunk = resolve(*line, target)?;
+    }
+    for (function, (line, target)) in image.functions.iter_mut().zip(&function_targets) {
+        function.ins = resolve(*line, target)?;
+    }
+
+    let foreign = get_default_foreign_function().name_to_idx_map();
+    for (line, text) in instructions {
+        let word = match text.strip_prefix(".word") {
+            Some(word) => parse_number(line, word.trim())?,
+            None => encode(line, text, &resolve, &foreign)?,
+        };
+        image.code.push(word);
+    }
+
+    Ok(image)
+}
+
+fn encode(
+    line: usize,
+    text: &str,
+    resolve: &dyn Fn(usize, &str) -> Result<u32, AssemblyError>,
+    foreign: &HashMap<String, u32>,
+) -> Result<u32, AssemblyError> {
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let opcode =
+        OpCode::from_name(mnemonic).ok_or_else(|| error(line, format!("unknown opcode {mnemonic}")))?;
+
+    let operands: Vec<&str> = operands
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect();
+    let layout = opcode.layout();
+    if operands.len() != layout.len() {
+        return Err(error(
+            line,
+            format!("{mnemonic} takes {} operands, found {}", layout.len(), operands.len()),
+        ));
+    }
+
+    let mut params = vec![];
+    for (operand, kind) in operands.into_iter().zip(layout) {
+        let param = match kind {
+            Operand::RegAddr => {
+                let reg = prefixed(line, operand, 'r')?;
+                if reg > REG_MASK {
+                    return Err(error(line, format!("register {operand} does not exist")));
+                }
+                reg
+            }
+            Operand::ConstIdx => {
+                let param = match opcode {
+                    OpCode::JMP | OpCode::JMPSOME | OpCode::JMPIFNOT => resolve(line, operand)?,
+                    OpCode::LOADCONST => prefixed(line, operand, '#')?,
+                    OpCode::MAKETHUNK => prefixed(line, operand, '%')?,
+                    OpCode::MAKEFUNC => prefixed(line, operand, '@')?,
+                    OpCode::CFOREIGN => {
+                        match operand.strip_prefix('$').and_then(|name| foreign.get(name)) {
+                            Some(idx) => *idx,
+                            None => prefixed(line, operand, '$')?,
+                        }
+                    }
+                    _ => parse_number(line, operand)?,
+                };
+                if param > CONST_MASK {
+                    return Err(error(line, format!("operand {operand} does not fit in 20 bits")));
+                }
+                param
+            }
+        };
+        params.push(param);
+    }
+
+    Ok(u32::from_be_bytes(opcode.encode(params)))
+}
+
+/// Strip the `<prefix><idx>` numbering of a table entry and check it is the next one.
+fn entry(line: usize, text: &str, prefix: char, len: usize) -> Result<&str, AssemblyError> {
+    let (idx, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let idx = prefixed(line, idx, prefix)?;
+    if idx as usize != len + 1 {
+        return Err(error(line, format!("expected entry {prefix}{}, found {prefix}{idx}", len + 1)));
+    }
+    Ok(rest.trim())
+}
+
+fn prefixed(line: usize, operand: &str, prefix: char) -> Result<u32, AssemblyError> {
+    let number = operand
+        .strip_prefix(prefix)
+        .ok_or_else(|| error(line, format!("expected {prefix}N, found {operand}")))?;
+    parse_number(line, number)
+}
+
+fn parse_number<T: TryFrom<i128>>(line: usize, text: &str) -> Result<T, AssemblyError> {
+    let invalid = || error(line, format!("invalid number {text}"));
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let number = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16),
+        None => digits.parse::<i128>(),
+    }
+    .map_err(|_| invalid())?;
+
+    T::try_from(if negative { -number } else { number }).map_err(|_| invalid())
+}
+
+fn parse_version(line: usize, text: Option<&str>) -> Result<u32, AssemblyError> {
+    let invalid = || error(line, "expected .format MAJOR.MINOR");
+    let (major, minor) = text.and_then(|text| text.split_once('.')).ok_or_else(invalid)?;
+    Ok(format_version(parse_number(line, major)?, parse_number(line, minor)?))
+}
+
+/// Parse a double quoted string with the escapes Rust's `{:?}` produces.
+fn parse_string(line: usize, text: &str) -> Result<String, AssemblyError> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or_else(|| error(line, format!("expected a quoted string, found {text}")))?;
+
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            result.push(char);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(char @ ('\\' | '"' | '\'')) => char,
+            Some('u') => {
+                let code: String = chars.by_ref().take_while(|char| *char != '}').collect();
+                code.strip_prefix('{')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error(line, format!("invalid escape \\u{code}}}")))?
+            }
+            other => return Err(error(line, format!("invalid escape \\{}", other.unwrap_or(' ')))),
+        };
+        result.push(escaped);
+    }
+
+    Ok(result)
+}
+
+/// Cut a `;` comment off a line, ignoring `;` inside string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, char) in line.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::Path;
+
+use clap::Args;
+
+use angi_runtime::program::Program;
+
+use crate::assembler::assemble;
+use crate::compiler::error::CompilationError;
+
+#[derive(Args)]
+pub struct AsmArgs {
+    /// The `.avm` source file
+    source: String,
+
+    /// Where to write the image, defaults to the source path with a `.bc` extension
+    #[arg(short, long, value_name = "PATH")]
+    out: Option<String>,
+}
+
+/// Assemble into a raw bytecode image.
+pub fn index(args: AsmArgs) -> Result<(), CompilationError> {
+    let source_file_path = &args.source;
+    let dist_file_path = match &args.out {
+        Some(path) => path.clone(),
+        None => Path::new(source_file_path).with_extension("bc").display().to_string(),
+    };
+
+    let source = super::read_source(source_file_path)?;
+
+    let image = assemble(&source).map_err(|err| {
+        eprintln!("{source_file_path}: {err}");
+        CompilationError::UnexpectedError
+    })?;
+    let bytecode = image.to_bytes();
+
+    // Refuse to write an image the VM would not load
+    if let Err(err) = Program::load(bytecode.clone()) {
+        eprintln!("{source_file_path}: {err}");
+        return Err(CompilationError::UnexpectedError);
+    }
+
+    fs::write(&dist_file_path, bytecode).map_err(|err| CompilationError::IOError {
+        message: format!("Err in write file {err}"),
+    })
+}
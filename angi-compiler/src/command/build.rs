@@ -2,49 +2,288 @@ static SERVER: &[u8] =
     include_bytes!(concat!(env!("OUT_DIR"), "/server"));
 // static SERVER: &[u8] = include_bytes!(env!("RUNTIME_PATH"));
 
+/// The target triple the embedded `SERVER` was built for.
+const HOST_TARGET: &str = env!("ANGI_HOST_TARGET");
+
+use std::borrow::Cow;
 use std::fs::{self, File, set_permissions};
 use std::io::{self, Write};
-use std::path::Path;
-use angi_archive::Archiver;
-use crate::compiler::compile;
+use std::path::{Path, PathBuf};
+use angi_archive::{Archiver, Extractor, STATIC_PREFIX, TEMPLATE_PREFIX};
+use angi_runtime::debug_info::DEBUG_INFO_ENTRY;
+use angi_runtime::value::Table;
+use angi_runtime::vm::VM;
+use clap::Args;
+use crate::compiler::{compile_with_options, referenced_templates, CompileOptions};
 use crate::compiler::error::CompilationError;
+use crate::compiler::optimization::OptLevel;
 
-pub fn index(args: &[String]) -> Result<(), CompilationError>{
-    let source_file_path = &args[2];
-    let dist_file_path = &args[3];
+#[derive(Args)]
+pub struct BuildArgs {
+    /// The `.ag` source file
+    source: String,
 
-    let source = fs::read_to_string(source_file_path).map_err(|err|
-        CompilationError::IOError {
-            message: format!("Error in open source file, {err:?}"),
-        }
-    )?;
-
-    let bytecode = compile(&source, source_file_path)?;
-
-    let mut file = File::options()
-        .create(true)
-        .append(true)
-        .open(dist_file_path)
-        .map_err(|err| {
-            CompilationError::IOError {
-                message: format!("Err in open file {err}"),
-            }
-        })?;
+    /// Where to write the binary, defaults to the source file name without extension
+    #[arg(short, long, value_name = "PATH")]
+    out: Option<String>,
+
+    /// Optimize with -O2 unless a level is given and leave out debug info
+    #[arg(long)]
+    release: bool,
+
+    /// Optimization level: 0, 1 or 2 [default: 1]
+    #[arg(short = 'O', long, value_name = "LEVEL", value_parser = super::parse_opt_level)]
+    opt_level: Option<OptLevel>,
+
+    /// Overwrite the output even when it is not a previous build
+    #[arg(long)]
+    force: bool,
+
+    /// Directory whose files are bundled and served by the binary, defaults to `static.dir` from the config
+    #[arg(long, value_name = "DIR")]
+    static_dir: Option<PathBuf>,
+
+    /// Target triple of the binary, defaults to the host
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Directory of prebuilt servers, one `<triple>/server` per target
+    #[arg(long, value_name = "DIR", env = "ANGI_RUNTIME_DIR")]
+    runtime_dir: Option<PathBuf>,
+}
+
+pub fn index(args: BuildArgs) -> Result<(), CompilationError>{
+    let default_level = if args.release { OptLevel::O2 } else { OptLevel::O1 };
+    let options = CompileOptions {
+        opt_level: args.opt_level.unwrap_or(default_level),
+    };
+
+    let target = args.target.as_deref().unwrap_or(HOST_TARGET);
+    let windows = target.contains("windows");
+
+    let source_file_path = &args.source;
+    let dist_file_path = match &args.out {
+        Some(out) => out.clone(),
+        None => Path::new(source_file_path)
+            .with_extension(if windows { "exe" } else { "" })
+            .display()
+            .to_string(),
+    };
+    if dist_file_path == *source_file_path {
+        return Err(CompilationError::InvalidArgument {
+            message: format!("The output would overwrite {source_file_path}, pass --out"),
+        });
+    }
+    check_destination(Path::new(&dist_file_path), args.force)?;
+
+    if windows && Path::new(&dist_file_path).extension().is_none() {
+        eprintln!("⚠️  On Windows, consider naming the file with `.exe`");
+    }
+
+    let server = server_for_target(target, args.runtime_dir.as_deref())?;
+
+    let source = super::read_source(source_file_path)?;
+
+    let (bytecode, debug_info) = compile_with_options(&source, source_file_path, options)?;
 
     let mut archiver = Archiver::new();
 
+    archive_static_dir(&mut archiver, args.static_dir.as_deref(), &bytecode)?;
+    archive_templates(&mut archiver, &source)?;
+
     archiver.archive(bytecode, "bytecode");
+    if !args.release {
+        archiver.archive(debug_info.to_bytes(), DEBUG_INFO_ENTRY);
+    }
 
     let payload = archiver.get_bytes().map_err(|_| {
         CompilationError::ArchiveError
     })?;
 
-    file.write_all(SERVER).expect("Fail to write file");
-    file.write_all(&payload).expect("Fail to write file");
-    file.flush().expect("Fail to flush");
+    write_atomically(Path::new(&dist_file_path), &[&server, &payload])
+}
+
+/// The server the payload is appended to: the embedded one for the host,
+/// otherwise the prebuilt `<runtime dir>/<triple>/server`.
+fn server_for_target(
+    target: &str,
+    runtime_dir: Option<&Path>,
+) -> Result<Cow<'static, [u8]>, CompilationError> {
+    if target == HOST_TARGET {
+        return Ok(Cow::Borrowed(SERVER));
+    }
+
+    let runtime_dir = runtime_dir.ok_or_else(|| CompilationError::InvalidArgument {
+        message: format!(
+            "Building for {target} needs prebuilt servers, pass --runtime-dir or set ANGI_RUNTIME_DIR"
+        ),
+    })?;
+
+    let bin_name = if target.contains("windows") { "server.exe" } else { "server" };
+    let path = runtime_dir.join(target).join(bin_name);
 
-    let path = Path::new(&dist_file_path);
-    make_file_executable(path).expect("Fail to make file executable");
+    match fs::read(&path) {
+        Ok(server) if !server.is_empty() => Ok(Cow::Owned(server)),
+        Ok(_) => Err(CompilationError::InvalidArgument {
+            message: format!("The prebuilt server {} is empty", path.display()),
+        }),
+        Err(err) => Err(CompilationError::InvalidArgument {
+            message: format!(
+                "No prebuilt server for {target} at {}: {err}{}",
+                path.display(),
+                available_targets(runtime_dir)
+            ),
+        }),
+    }
+}
+
+/// `, available: a, b` listing the targets found in `runtime_dir`, if any.
+fn available_targets(runtime_dir: &Path) -> String {
+    let mut targets: Vec<String> = fs::read_dir(runtime_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    targets.sort();
+
+    if targets.is_empty() {
+        String::new()
+    } else {
+        format!(", available: {}", targets.join(", "))
+    }
+}
+
+/// Refuse to replace a directory, or a file that is not a previous build
+/// unless `force` is set.
+fn check_destination(path: &Path, force: bool) -> Result<(), CompilationError> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+
+    if metadata.is_dir() {
+        return Err(CompilationError::InvalidArgument {
+            message: format!("The output {} is a directory", path.display()),
+        });
+    }
+
+    let previous_build = File::open(path)
+        .ok()
+        .is_some_and(|file| Extractor::init_from_file(file).is_ok());
+    if !previous_build && !force {
+        return Err(CompilationError::InvalidArgument {
+            message: format!(
+                "{} exists and is not an angi build, pass --force to overwrite it",
+                path.display()
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Write `parts` to a temporary file next to `path`, sync it and rename it
+/// over `path`, so the output is either the old file or the complete new one.
+fn write_atomically(path: &Path, parts: &[&[u8]]) -> Result<(), CompilationError> {
+    let io_error = |err: io::Error| CompilationError::IOError {
+        message: format!("Cannot write {}: {err}", path.display()),
+    };
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path.file_name().ok_or_else(|| CompilationError::InvalidArgument {
+        message: format!("The output {} is not a file path", path.display()),
+    })?;
+    let temp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        for part in parts {
+            file.write_all(part)?;
+        }
+        file.sync_all()?;
+        drop(file);
+
+        make_file_executable(&temp_path)?;
+        fs::rename(&temp_path, path)?;
+        sync_dir(dir)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(io_error)
+}
+
+/// Persist the rename itself.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        File::open(dir)?.sync_all()?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+/// Add every file under the static dir as `static/<relative path>`, the
+/// keys `StaticStore` looks up. `--static-dir` wins over `static.dir` from
+/// the config, which is relative to the working directory like when the
+/// program is served from the disk.
+fn archive_static_dir(
+    archiver: &mut Archiver,
+    static_dir: Option<&Path>,
+    bytecode: &[u8],
+) -> Result<(), CompilationError> {
+    let dir = match static_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => match configured_static_dir(bytecode) {
+            Some(dir) => PathBuf::from(dir),
+            None => return Ok(()),
+        },
+    };
+
+    if !dir.is_dir() {
+        return Err(CompilationError::InvalidArgument {
+            message: format!("The static dir {} does not exist", dir.display()),
+        });
+    }
+
+    archiver
+        .archive_dir(&dir, STATIC_PREFIX)
+        .map_err(|err| CompilationError::IOError {
+            message: format!("Cannot read static dir {}: {err}", dir.display()),
+        })
+}
+
+/// `static.dir` from the config of the compiled program, if it sets one.
+fn configured_static_dir(bytecode: &[u8]) -> Option<String> {
+    let mut vm = VM::new_from_bytes(bytecode.to_vec()).ok()?;
+    vm.eval::<Table>("static").ok()?.get::<String>("dir")
+}
+
+/// Add every template the source renders as `template/<path>`, read
+/// relative to the working directory like `htmlTemplate` does without a
+/// bundle.
+fn archive_templates(archiver: &mut Archiver, source: &str) -> Result<(), CompilationError> {
+    for template in referenced_templates(source) {
+        let content = fs::read(&template).map_err(|err| CompilationError::IOError {
+            message: format!("Cannot read template {template}: {err}"),
+        })?;
+        archiver.archive(content, &format!("{TEMPLATE_PREFIX}/{template}"));
+    }
 
     Ok(())
 }
@@ -59,12 +298,8 @@ fn make_file_executable(path: &Path) -> io::Result<()> {
         set_permissions(path, perms)?;
     }
 
-    #[cfg(windows)]
-    {
-        if path.extension().is_none() {
-            eprintln!("⚠️  On Windows, consider naming the file with `.exe`");
-        }
-    }
+    #[cfg(not(unix))]
+    let _ = path;
 
     Ok(())
 }
@@ -0,0 +1,40 @@
+use clap::Args;
+
+use crate::compiler::check;
+use crate::compiler::error::CompilationError;
+use crate::diagnostic::Severity;
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// The `.ag` source file
+    source: String,
+
+    /// Print diagnostics as a JSON object instead of a report
+    #[arg(long)]
+    json: bool,
+}
+
+/// Report every diagnostic of the source without building it.
+/// Returns whether the source is free of errors.
+pub fn index(args: CheckArgs) -> Result<bool, CompilationError> {
+    let source_file_path = &args.source;
+    let source = super::read_source(source_file_path)?;
+
+    let engine = check(&source);
+
+    if args.json {
+        println!("{}", engine.to_json(source_file_path));
+    } else {
+        engine.emit(&source, source_file_path);
+
+        let errors = engine.count(Severity::Error);
+        let warnings = engine.count(Severity::Warning);
+        if errors == 0 && warnings == 0 {
+            println!("{source_file_path}: no problems found");
+        } else {
+            println!("{source_file_path}: {errors} error(s), {warnings} warning(s)");
+        }
+    }
+
+    Ok(!engine.has_error())
+}
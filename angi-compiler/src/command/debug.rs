@@ -2,124 +2,153 @@ use std::fs::{self, File};
 use std::io::{BufReader, Write};
 
 use angi_archive::{Archiver, Extractor};
-use angi_ins::{OpCode, OperandWithNum, extract_opcode};
+use angi_ins::{MAGIC_NUMBER, OpCode, OperandWithNum, extract_opcode, version_string};
 use angi_utils::read_from_buf_reader::{read_i64, read_u32, read_u8};
-use angi_runtime::value::{Function, List, Table};
+use angi_runtime::value::{List, Table};
+use angi_runtime::debug_info::{DEBUG_INFO_ENTRY, DebugInfo};
 use angi_runtime::vm::VM;
 
+use clap::Subcommand;
+
 use crate::compiler::bytecode::load_global;
-use crate::compiler::compile;
+use crate::compiler::error::CompilationError;
+use crate::compiler::{compile, compile_with_debug_info};
+use crate::disassembler::Image;
 use crate::compiler::{bytecode::BytecodeGen, lexer::Lexer, parser::parse};
 use crate::compiler::optimization::optimization;
 use crate::macro_function::MacroRegistry;
 const PADDING: usize = 16;
 
 
-pub fn index(args: &[String]) {
-    match args[2].as_str() {
-        "lex" => {
-            let source_file_path = &args[3];
+#[derive(Subcommand)]
+pub enum DebugCommand {
+    /// Print the tokens of a source file
+    Lex {
+        /// The `.ag` source file
+        source: String,
+    },
+    /// Print the syntax tree of a source file after macro expansion
+    Ast {
+        /// The `.ag` source file
+        source: String,
+    },
+    /// Load a raw bytecode image and evaluate its `port` and `routes`
+    Run {
+        /// A bytecode image written by `debug writebc`
+        bytecode: String,
+    },
+    /// Compile a source file into a raw bytecode image
+    Writebc {
+        /// The `.ag` source file
+        source: String,
+        /// Where to write the image
+        dist: String,
+    },
+    /// Disassemble a source file, a raw bytecode image or a built binary
+    Readbc {
+        /// A `.ag` source, a bytecode image or an archive
+        file: String,
+        /// Dump the image field by field instead of disassembling it
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Compile a source file into an archive holding only its bytecode
+    Writear {
+        /// The `.ag` source file
+        source: String,
+        /// Where to write the archive
+        dist: String,
+    },
+    /// List the entries of an archive or a built binary
+    Readar {
+        /// An archive or a built binary
+        archive: String,
+    },
+}
 
-            let content = match fs::read_to_string(source_file_path) {
-                Ok(content) => content,
-                Err(err) => panic!("Cannot open file {err:?}"),
-            };
+pub fn index(command: DebugCommand) -> Result<(), CompilationError> {
+    match command {
+        DebugCommand::Lex { source } => {
+            let content = super::read_source(&source)?;
 
             for lex in Lexer::new(content.chars()) {
                 println!("{:?}", lex);
             }
         }
-        "ast" => {
-            let source_file_path = &args[3];
-
-            let content = match fs::read_to_string(source_file_path) {
-                Ok(content) => content,
-                Err(err) => panic!("Cannot open file {err:?}"),
-            };
+        DebugCommand::Ast { source } => {
+            let content = super::read_source(&source)?;
 
             let mut lexer = Lexer::new(content.chars());
 
             let mut ast = match parse(&mut lexer) {
                 Ok(ast) => ast,
-                Err(err) => panic!("Err in parse {err:?}"),
+                Err(err) => return Err(CompilationError::ParseError(err)),
             };
 
 
             let mut macro_registry = MacroRegistry::new();
             match macro_registry.expand_expr_inplace(&mut ast) {
                 Ok(_) => {},
-                Err(err) => panic!("Err in parse {err:?}"),
+                Err(_) => return Err(CompilationError::MacroCheckingError),
             };
 
             print!("{:?}", ast);
         }
-        "run" => {
-            let source_file_path = &args[3];
-
-            let bytes = fs::read(source_file_path).expect("Cant open file");
-
-            let mut vm = match VM::new_from_bytes(bytes) {
-                Ok(vm) => vm,
-                Err(err) => panic!("{}", err)
-            };
-
+        DebugCommand::Run { bytecode } => {
+            let bytes = fs::read(&bytecode).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot open {bytecode}: {err}"),
+            })?;
 
-            let handler = vm.eval::<Function>("port").expect("ckdsn");
-            let result : String = handler.call(&mut vm, ()).expect("dnejd");
+            let mut vm = VM::new_from_bytes(bytes).map_err(runtime_error)?;
 
-            println!("{}",result);
-
-            let mut list_routes = vm.eval::<List<Table>>("routes").expect("ckdsn");
-            list_routes.force(&mut vm);
-
-
-            // let list_routes_iter = list_routes.iter().unwrap();
-
-            // let mut function = first.get::<Function>("handler").unwrap();
-
-            // let html: String = function.eval(&mut vm, (String::from("Hello"),)).unwrap();
+            let port = vm.eval::<i64>("port").map_err(runtime_error)?;
+            println!("port: {port}");
 
+            let mut routes = vm.eval::<List<Table>>("routes").map_err(runtime_error)?;
+            routes.try_force(&mut vm).map_err(runtime_error)?;
 
+            println!("routes:");
+            for route in &routes {
+                println!("  {}", route.get::<String>("path").unwrap_or_default());
+            }
         }
-        "writebc" => {
-            let source_file_path = &args[3];
-            let dist_file_path = &args[4];
+        DebugCommand::Writebc { source, dist } => {
+            let content = super::read_source(&source)?;
 
-            let content = match fs::read_to_string(source_file_path) {
-                Ok(content) => content,
-                Err(err) => panic!("Cannot open file {err:?}"),
-            };
+            let bytecode = compile(&content, &source)?;
 
-            let rs = compile(&content, source_file_path);
+            let mut file = File::create(&dist).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot create {dist}: {err}"),
+            })?;
 
-            if let Ok(bytecode) = rs {
-                let mut file = File::create(dist_file_path).unwrap_or_else(|err| {
-                    panic!("Cannot create file {err:?}");
-                 });
+            file.write_all(&bytecode).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot write {dist}: {err}"),
+            })?;
+        }
+        DebugCommand::Readbc { file, raw } => {
+            if raw {
+                let f = File::open(&file).map_err(|err| CompilationError::IOError {
+                    message: format!("Cannot open {file}: {err}"),
+                })?;
+                let mut r = BufReader::new(f);
+                print_bytecode(&mut r);
+                return Ok(());
+            }
 
-                let _ = file.write_all(&bytecode);
+            let (bytes, debug_info) = load_bytecode(&file);
+            match Image::from_bytes(&bytes) {
+                Ok(image) => print!("{}", image.disassemble(debug_info.as_ref())),
+                Err(err) => println!("{err}"),
             }
         }
-        "readbc" => {
-            let source_file_path = &args[3];
-            let f = File::open(source_file_path).expect("Cant open file");
-            let mut r = BufReader::new(f);
-            print_bytecode(&mut r);
-        }
-        "writear" => {
-            let source_file_path = &args[3];
-            let dist_file_path = &args[4];
-
-            let content = match fs::read_to_string(source_file_path) {
-                Ok(content) => content,
-                Err(err) => panic!("Cannot open file {err:?}"),
-            };
+        DebugCommand::Writear { source, dist } => {
+            let content = super::read_source(&source)?;
 
             let mut lexer = Lexer::new(content.chars());
 
             let mut ast = match parse(&mut lexer) {
                 Ok(ast) => ast,
-                Err(err) => panic!("Err in parse {err:?}"),
+                Err(err) => return Err(CompilationError::ParseError(err)),
             };
 
             let global_func = load_global();
@@ -129,37 +158,65 @@ pub fn index(args: &[String]) {
 
             optimization(&mut ast);
 
-            let content = bytecode_genaration.get_binary(ast).unwrap_or_else(|err| {
-                panic!("{err:?}");
-            });
+            let content = bytecode_genaration
+                .get_binary(ast)
+                .map_err(CompilationError::BytecodeGenerationError)?;
 
             let mut archiver = Archiver::new();
 
             archiver.archive(content, "bytecode");
 
-            let payload = archiver.get_bytes().unwrap_or_else(|err| {
-                panic!("{err:?}");
-            });
+            let payload = archiver.get_bytes().map_err(|_| CompilationError::ArchiveError)?;
 
-            let mut file = File::create(dist_file_path).unwrap_or_else(|err| {
-                panic!("Cannot create file {err:?}");
-             });
+            let mut file = File::create(&dist).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot create {dist}: {err}"),
+            })?;
 
-            let _ = file.write_all(&payload);
+            file.write_all(&payload).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot write {dist}: {err}"),
+            })?;
         }
-        "readar" => {
-            let source_file_path = &args[3];
-            let f = File::open(source_file_path).expect("Cant open file");
+        DebugCommand::Readar { archive } => {
+            let f = File::open(&archive).map_err(|err| CompilationError::IOError {
+                message: format!("Cannot open {archive}: {err}"),
+            })?;
             let extractor = Extractor::init_from_file(f).unwrap_or_else(|err| {
                 panic!("Cannot extractor {err:?}");
              });
 
             extractor.print_debug();
         }
-        _ => {
-            println!("Command not exist");
-        }
     }
+
+    Ok(())
+}
+
+/// Bytecode and debug info from a source file, a raw bytecode file or a
+/// built archive.
+fn load_bytecode(path: &str) -> (Vec<u8>, Option<DebugInfo>) {
+    if path.ends_with(".ag") {
+        let content = fs::read_to_string(path).expect("Cant open file");
+        let (bytes, debug_info) = compile_with_debug_info(&content, path).unwrap_or_else(|err| {
+            panic!("Cannot compile {err:?}");
+        });
+        return (bytes, Some(debug_info));
+    }
+
+    let bytes = fs::read(path).expect("Cant open file");
+    if bytes.starts_with(&MAGIC_NUMBER.to_be_bytes()) {
+        return (bytes, None);
+    }
+
+    let f = File::open(path).expect("Cant open file");
+    let extractor = Extractor::init_from_file(f).unwrap_or_else(|err| {
+        panic!("Cannot extractor {err:?}");
+    });
+    let bytes = extractor.extract_blob("bytecode".into()).expect("No bytecode in archive");
+    let debug_info = extractor
+        .extract_blob(DEBUG_INFO_ENTRY.into())
+        .and_then(|bytes| DebugInfo::from_bytes(&bytes).ok());
+
+    (bytes, debug_info)
 }
 
 pub fn print_bytecode(r: &mut BufReader<File>) {
@@ -167,8 +224,8 @@ pub fn print_bytecode(r: &mut BufReader<File>) {
     let (magic_code,_) = read_u32(r);
     println!("{:<PADDING$}{} : ANGI", "MAGIC CODE", magic_code);
 
-    let ( version, _) = read_u32(r);
-    println!("{:<PADDING$}{}", "VERSION", version);
+    let (_, version) = read_u32(r);
+    println!("{:<PADDING$}{}", "VERSION", version_string(version));
 
     let ( const_offset, _) = read_u32(r);
     println!("{:<PADDING$}{}", "CONST OFFSET", const_offset);
@@ -309,3 +366,9 @@ fn print_arg_ins(ins: u32, op: OpCode) -> String {
 
     str
 }
+
+fn runtime_error(err: angi_runtime::error::VmError) -> CompilationError {
+    CompilationError::RuntimeError {
+        message: err.to_string(),
+    }
+}
@@ -1,38 +1,78 @@
+mod asm;
+mod check;
 mod debug;
 mod build;
+mod run;
 
-use std::env;
+use std::process::ExitCode;
 
-pub fn handle_command() {
-    let args: Vec<String> = env::args().collect();
+use clap::{Parser, Subcommand};
 
-    if args.contains(&String::from("--help")) || args.contains(&String::from("-h")) {
-        print_help();
-        return;
-    };
+use crate::compiler::error::CompilationError;
+use crate::compiler::optimization::OptLevel;
 
-    match args[1].as_str() {
-        "build" => {
-            let _ = build::index(&args);
-        }
-        "debug" => {
-            debug::index(&args);
-        }
-        _ => {
-            println!("Command not exist");
-        }
-    };
+/// Exit codes shared by every subcommand. Invalid usage exits with 2,
+/// reported by clap.
+const EXIT_FAILURE: u8 = 1;
+
+#[derive(Parser)]
+#[command(
+    name = "angi",
+    version,
+    about = "Build and inspect Angi web applications",
+    after_help = "Exit codes: 0 on success, 1 when the source has errors or a step fails, 2 on invalid usage."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a source file into a standalone server binary
+    Build(build::BuildArgs),
+    /// Compile a source file in memory and serve it without building a binary
+    Run(run::RunArgs),
+    /// Parse, expand macros and type-check a source file without building it
+    Check(check::CheckArgs),
+    /// Assemble `.avm` text into a raw bytecode image
+    Asm(asm::AsmArgs),
+    /// Inspect each stage of the compiler and the files it writes
+    #[command(subcommand)]
+    Debug(debug::DebugCommand),
+}
 
-fn print_help() {
-    println!(
-        r#"
-    angi help page
-    USAGE:
-    COMMAND:
-    "#
-    );
+pub fn handle_command() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Build(args) => build::index(args),
+        Command::Run(args) => run::index(args),
+        Command::Check(args) => match check::index(args) {
+            Ok(true) => Ok(()),
+            Ok(false) => return ExitCode::from(EXIT_FAILURE),
+            Err(err) => Err(err),
+        },
+        Command::Asm(args) => asm::index(args),
+        Command::Debug(command) => debug::index(command),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
 }
 
+/// Value parser for `--opt-level`, also reachable as `-O<level>`.
+fn parse_opt_level(level: &str) -> Result<OptLevel, String> {
+    OptLevel::from_level(level).ok_or_else(|| format!("expected 0, 1 or 2, found {level}"))
+}
 
+fn read_source(path: &str) -> Result<String, CompilationError> {
+    std::fs::read_to_string(path).map_err(|err| CompilationError::IOError {
+        message: format!("Cannot open source file {path}: {err}"),
+    })
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use angi_archive::StaticStore;
+use angi_runtime::vm::VM;
+use angi_server::{Reloader, ServeError, logger};
+use clap::Args;
+
+use crate::compiler::{compile_with_engine, compile_with_options, CompileOptions};
+use crate::compiler::error::CompilationError;
+use crate::compiler::optimization::OptLevel;
+use crate::diagnostic::DiagnosticEngine;
+
+/// How often `--watch` looks for changed files.
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Extensions of the files `--watch` reloads on: sources and templates.
+const WATCHED_EXTENSIONS: [&str; 4] = ["ag", "html", "jinja", "j2"];
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// The `.ag` source file
+    source: String,
+
+    /// Optimization level: 0, 1 or 2 [default: 1]
+    #[arg(short = 'O', long, value_name = "LEVEL", value_parser = super::parse_opt_level)]
+    opt_level: Option<OptLevel>,
+
+    /// Recompile and swap the program when a source or template next to it changes
+    #[arg(long)]
+    watch: bool,
+}
+
+/// Compile the source in memory and serve it the way a built binary would,
+/// without writing anything to disk.
+pub fn index(args: RunArgs) -> Result<(), CompilationError> {
+    let options = CompileOptions {
+        opt_level: args.opt_level.unwrap_or(OptLevel::O1),
+    };
+
+    let source_file_path = &args.source;
+    let source = super::read_source(source_file_path)?;
+
+    let (bytecode, debug_info) = compile_with_options(&source, source_file_path, options)?;
+
+    let vm = VM::new_from_bytes_with_debug_info(bytecode, debug_info).map_err(runtime_error)?;
+
+    let runtime = tokio::runtime::Runtime::new().map_err(|err| CompilationError::IOError {
+        message: format!("Cannot start the async runtime: {err}"),
+    })?;
+
+    let static_store = Arc::new(StaticStore::empty());
+
+    if !args.watch {
+        return runtime
+            .block_on(angi_server::serve(vm, static_store))
+            .map_err(serve_error);
+    }
+
+    let reloader = Reloader::new(static_store);
+    let watcher = reloader.clone();
+    let source_file_path = source_file_path.clone();
+    thread::spawn(move || watch(&source_file_path, options, &watcher));
+
+    runtime
+        .block_on(angi_server::serve_reloadable(vm, reloader))
+        .map_err(serve_error)
+}
+
+/// Poll the files next to the source and reload the program whenever one of
+/// them changes. Runs until the process exits.
+fn watch(source_file_path: &str, options: CompileOptions, reloader: &Reloader) {
+    let root = match Path::new(source_file_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    logger::info(format!("Watching {} for changes", root.display()));
+
+    let mut snapshot = watched_files(&root);
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+
+        let current = watched_files(&root);
+        if current == snapshot {
+            continue;
+        }
+        snapshot = current;
+
+        match recompile(source_file_path, options) {
+            Ok(vm) => {
+                if let Err(err) = reloader.reload(vm) {
+                    reloader.fail(err.to_string());
+                }
+            }
+            Err(message) => reloader.fail(message),
+        }
+    }
+}
+
+/// Compile the source again, printing diagnostics to the terminal. On error
+/// returns the uncolored report for the error overlay.
+fn recompile(source_file_path: &str, options: CompileOptions) -> Result<VM, String> {
+    let source = super::read_source(source_file_path).map_err(|err| err.to_string())?;
+
+    let mut engine = DiagnosticEngine::new();
+    let result = compile_with_engine(&source, source_file_path, options, &mut engine);
+    engine.emit(&source, source_file_path);
+
+    let (bytecode, debug_info) = result.map_err(|err| {
+        let report = engine.render(&source, source_file_path);
+        if report.is_empty() { err.to_string() } else { report }
+    })?;
+
+    VM::new_from_bytes_with_debug_info(bytecode, debug_info).map_err(|err| err.to_string())
+}
+
+/// Modification time of every watched file under `dir`, skipping hidden
+/// directories and build output.
+fn watched_files(dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = BTreeMap::new();
+
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "target" {
+                    pending.push(path);
+                }
+                continue;
+            }
+
+            let watched = path
+                .extension()
+                .is_some_and(|ext| WATCHED_EXTENSIONS.iter().any(|watched| ext == *watched));
+            if let (true, Ok(modified)) = (watched, entry.metadata().and_then(|meta| meta.modified())) {
+                files.insert(path, modified);
+            }
+        }
+    }
+
+    files
+}
+
+fn runtime_error(err: angi_runtime::error::VmError) -> CompilationError {
+    CompilationError::RuntimeError {
+        message: err.to_string(),
+    }
+}
+
+fn serve_error(err: ServeError) -> CompilationError {
+    match err {
+        ServeError::Runtime { err } => runtime_error(err),
+        err => CompilationError::IOError {
+            message: err.to_string(),
+        },
+    }
+}
@@ -26,15 +26,15 @@ pub enum Expr {
         items: Vec<Expr>
     },
     LetIn {
-        let_part: HashMap<Indentifier, Expr>,
+        let_part: Vec<(Pattern, Expr)>,
         in_part: Box<Expr>,
     },
     FunctionDeclare {
-        params: Vec<String>,
+        params: Vec<Pattern>,
         body: Box<Expr>
     },
     FunctionCall {
-        name: String,
+        callee: Box<Expr>,
         args: Vec<Expr>
     },
     Var(String),
@@ -42,6 +42,71 @@ pub enum Expr {
     AccessField {
         parent: Box<Expr>,
         child: String
+    },
+    OptionalAccessField {
+        parent: Box<Expr>,
+        child: String
+    },
+    WithDefault {
+        expr: Box<Expr>,
+        default: Box<Expr>
+    },
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>
+    },
+    /// Source position of the wrapped node, kept for debug info
+    Located {
+        span: SourceSpan,
+        expr: Box<Expr>
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Left-hand side of a `let` binding or a function parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Name(Indentifier),
+    /// `{ field, other: pattern, port ? 3000 }`
+    Table(Vec<(Indentifier, Pattern, Option<Expr>)>),
+    /// `[first, second ? 0]`
+    List(Vec<(Pattern, Option<Expr>)>),
+}
+
+impl Pattern {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Pattern::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn strip_locations(self) -> Pattern {
+        match self {
+            Pattern::Name(name) => Pattern::Name(name),
+            Pattern::Table(fields) => Pattern::Table(
+                fields
+                    .into_iter()
+                    .map(|(key, pattern, default)| {
+                        (key, pattern.strip_locations(), default.map(Expr::strip_locations))
+                    })
+                    .collect(),
+            ),
+            Pattern::List(items) => Pattern::List(
+                items
+                    .into_iter()
+                    .map(|(pattern, default)| {
+                        (pattern.strip_locations(), default.map(Expr::strip_locations))
+                    })
+                    .collect(),
+            ),
+        }
     }
 }
 
@@ -59,6 +124,100 @@ pub enum Operator {
     Mul,
     ConcatString,
     Pipe,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
 }
 
 type Indentifier = String;
+
+impl Expr {
+    /// Name of the called function when the callee is a plain variable.
+    pub fn callee_name(&self) -> Option<&str> {
+        match self {
+            Expr::FunctionCall { callee, .. } => match callee.as_ref() {
+                Expr::Var(name) => Some(name),
+                _ => None,
+            },
+            Expr::Located { expr, .. } => expr.callee_name(),
+            _ => None,
+        }
+    }
+
+    /// The node under any `Located` wrappers.
+    pub fn unlocated(&self) -> &Expr {
+        match self {
+            Expr::Located { expr, .. } => expr.unlocated(),
+            expr => expr,
+        }
+    }
+
+    pub fn into_unlocated(self) -> Expr {
+        match self {
+            Expr::Located { expr, .. } => expr.into_unlocated(),
+            expr => expr,
+        }
+    }
+
+    /// Drop every `Located` wrapper in the tree.
+    pub fn strip_locations(self) -> Expr {
+        let strip = |expr: Box<Expr>| Box::new(expr.strip_locations());
+
+        match self {
+            Expr::Located { expr, .. } => expr.strip_locations(),
+            Expr::Unary { op, rhs } => Expr::Unary { op, rhs: strip(rhs) },
+            Expr::Binary { op, lhs, rhs } => Expr::Binary { op, lhs: strip(lhs), rhs: strip(rhs) },
+            Expr::Pipe { lhs, rhs } => Expr::Pipe { lhs: strip(lhs), rhs: strip(rhs) },
+            Expr::Table { fields } => Expr::Table {
+                fields: fields.into_iter().map(|(k, v)| (k, v.strip_locations())).collect(),
+            },
+            Expr::List { items } => Expr::List {
+                items: items.into_iter().map(Expr::strip_locations).collect(),
+            },
+            Expr::LetIn { let_part, in_part } => Expr::LetIn {
+                let_part: let_part
+                    .into_iter()
+                    .map(|(pattern, value)| (pattern.strip_locations(), value.strip_locations()))
+                    .collect(),
+                in_part: strip(in_part),
+            },
+            Expr::FunctionDeclare { params, body } => Expr::FunctionDeclare {
+                params: params.into_iter().map(Pattern::strip_locations).collect(),
+                body: strip(body),
+            },
+            Expr::FunctionCall { callee, args } => Expr::FunctionCall {
+                callee: strip(callee),
+                args: args.into_iter().map(Expr::strip_locations).collect(),
+            },
+            Expr::InterpolatedString(parts) => Expr::InterpolatedString(
+                parts
+                    .into_iter()
+                    .map(|part| match part {
+                        InterpolatedPart::Expr(expr) => InterpolatedPart::Expr(expr.strip_locations()),
+                        part => part,
+                    })
+                    .collect(),
+            ),
+            Expr::AccessField { parent, child } => Expr::AccessField { parent: strip(parent), child },
+            Expr::OptionalAccessField { parent, child } => {
+                Expr::OptionalAccessField { parent: strip(parent), child }
+            }
+            Expr::WithDefault { expr, default } => {
+                Expr::WithDefault { expr: strip(expr), default: strip(default) }
+            }
+            Expr::If { cond, then_branch, else_branch } => Expr::If {
+                cond: strip(cond),
+                then_branch: strip(then_branch),
+                else_branch: strip(else_branch),
+            },
+            expr @ (Expr::Number(_)
+            | Expr::LiteralString(_)
+            | Expr::LiteralStringMultiline(_)
+            | Expr::Boolean(_)
+            | Expr::Var(_)) => expr,
+        }
+    }
+}
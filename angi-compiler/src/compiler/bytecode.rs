@@ -1,9 +1,11 @@
 mod constant;
 mod function;
 mod load_global;
+mod peephole;
 mod thunk;
 
-use crate::compiler::ast::InterpolatedPart;
+use crate::compiler::ast::{InterpolatedPart, Pattern, SourceSpan};
+use angi_runtime::debug_info::{DebugInfo, LineEntry, ScopeEntry};
 use angi_runtime::modules::get_default_foreign_function;
 pub use load_global::load_global;
 
@@ -12,9 +14,10 @@ use super::{
     error::BytecodeGenerationError,
 };
 use angi_ins::{MAGIC_NUMBER, METADATA_BYTES, OpCode, VERSION};
-use constant::Constant;
+use constant::{ConstPool, Constant};
 use core::panic;
 use function::Function;
+use peephole::peephole;
 use std::{collections::HashMap, vec};
 use thunk::Thunk;
 
@@ -22,7 +25,7 @@ type EnvironmentVariableFrame = HashMap<String, u8>;
 
 #[derive(Default)]
 pub struct BytecodeGen {
-    pub constants: HashMap<Constant, usize>,
+    pub constants: ConstPool,
     pub thunks: Vec<Thunk>,
     pub functions: Vec<Function>,
     pub thunk_pointer: usize,
@@ -35,7 +38,13 @@ pub struct BytecodeGen {
     context_var: Vec<EnvironmentVariableFrame>,
     foreign_fn_map: HashMap<String, u32>,
 
-    is_in_func: bool
+    is_in_func: bool,
+    /// Run the peephole pass over the code before writing it
+    peephole: bool,
+    /// Span of the innermost `Located` node being emitted
+    current_span: Option<SourceSpan>,
+    line_table: Vec<LineEntry>,
+    scopes: Vec<ScopeEntry>,
 }
 
 impl BytecodeGen {
@@ -43,7 +52,7 @@ impl BytecodeGen {
         let registry = get_default_foreign_function();
 
         BytecodeGen {
-            constants: HashMap::new(),
+            constants: ConstPool::new(),
             thunks: vec![],
             functions: vec![],
             thunk_pointer: 0,
@@ -55,7 +64,11 @@ impl BytecodeGen {
             ins_code: vec![],
             context_var: vec![],
             is_in_func: false,
-            foreign_fn_map: registry.name_to_idx_map()
+            peephole: false,
+            foreign_fn_map: registry.name_to_idx_map(),
+            current_span: None,
+            line_table: vec![],
+            scopes: vec![],
         }
     }
 
@@ -64,6 +77,11 @@ impl BytecodeGen {
         self
     }
 
+    pub fn with_peephole(mut self, peephole: bool) -> Self {
+        self.peephole = peephole;
+        self
+    }
+
     pub fn get_binary(&mut self, expr: Expr) -> Result<Vec<u8>, BytecodeGenerationError> {
         let reg = self.visit_expr(&expr, false)?;
 
@@ -76,6 +94,10 @@ impl BytecodeGen {
             self.visit_remain_thunk()?;
         }
 
+        if self.peephole {
+            self.run_peephole();
+        }
+
         let mut bytes = vec![];
         self.add_header_to_binary(&mut bytes);
         self.add_const_to_binary(&mut bytes);
@@ -88,12 +110,90 @@ impl BytecodeGen {
         Ok(bytes)
     }
 
+    /// Source mapping for the code emitted by `get_binary`.
+    pub fn debug_info(&self, file: &str) -> DebugInfo {
+        DebugInfo {
+            file: file.to_string(),
+            lines: self.line_table.clone(),
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    /// Rewrite the emitted code with `peephole`, moving every instruction
+    /// index that points into it: offsets, jumps, lines and scopes.
+    fn run_peephole(&mut self) {
+        let code: Vec<u32> = self
+            .ins_code
+            .chunks_exact(4)
+            .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        let entries: Vec<u32> = self
+            .thunks
+            .iter()
+            .map(|thunk| thunk.offset)
+            .chain(self.functions.iter().map(|function| function.offset))
+            .collect();
+
+        let (code, new_index) = peephole(&code, &entries, &mut self.constants);
+        let moved = |ins: u32| new_index[ins as usize];
+
+        for thunk in &mut self.thunks {
+            thunk.offset = moved(thunk.offset);
+        }
+        for function in &mut self.functions {
+            function.offset = moved(function.offset);
+        }
+        for scope in &mut self.scopes {
+            scope.start = moved(scope.start);
+            scope.end = moved(scope.end);
+        }
+
+        // An entry whose instructions were all removed is shadowed by the next one
+        let mut lines: Vec<LineEntry> = vec![];
+        for entry in &self.line_table {
+            let ins = moved(entry.ins);
+            if lines.last().is_some_and(|last| last.ins == ins) {
+                lines.pop();
+            }
+            if lines.last().map(|last| (last.line, last.column)) != Some((entry.line, entry.column)) {
+                lines.push(LineEntry { ins, ..*entry });
+            }
+        }
+        self.line_table = lines;
+
+        self.ins_count = code.len() as u32;
+        self.ins_code = code.iter().flat_map(|word| word.to_be_bytes()).collect();
+    }
+
+    /// Emit `visit` with `span` as the source of its instructions.
+    fn visit_located<T, F>(&mut self, span: SourceSpan, visit: F) -> Result<T, BytecodeGenerationError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, BytecodeGenerationError>,
+    {
+        let outer = self.current_span.replace(span);
+        let result = visit(self);
+        self.current_span = outer;
+        result
+    }
+
     pub fn visit_expr(
         &mut self,
         expr: &Expr,
         is_make_thunk: bool,
     ) -> Result<u8, BytecodeGenerationError> {
         match expr {
+            Expr::Boolean(bool) => {
+                let idx_const = self.make_const(Constant::Bool(*bool));
+                let reg_value = self
+                    .get_register()
+                    .expect("Error in get register: the value");
+                self.emit_ins(OpCode::LOADCONST.encode(vec![
+                    reg_value as u32,
+                    idx_const.try_into().expect("Error when convert idx_const to u32"),
+                ]));
+                Ok(reg_value)
+            }
             Expr::Number(num) => {
                 let idx_const = self.make_const(Constant::Number(*num));
                 let reg_value = self
@@ -118,6 +218,7 @@ impl BytecodeGen {
             }
             Expr::Binary { op, lhs, rhs } => {
                 let lhs_reg = self.visit_expr(lhs, false)?;
+                self.hold_register(lhs_reg);
 
                 let rhs_reg = self.visit_expr(rhs, false)?;
 
@@ -131,9 +232,19 @@ impl BytecodeGen {
                     Operator::Div => OpCode::DIV,
                     Operator::Mul => OpCode::MUL,
                     Operator::ConcatString => OpCode::CONCAT,
+                    Operator::Equal => OpCode::EQ,
+                    Operator::NotEqual => OpCode::NE,
+                    Operator::Less | Operator::Greater => OpCode::LT,
+                    Operator::LessEqual | Operator::GreaterEqual => OpCode::LE,
                     _ => panic!("panic because pipe")
                 };
 
+                // `a > b` is emitted as `b < a`
+                let (lhs_reg, rhs_reg) = match op {
+                    Operator::Greater | Operator::GreaterEqual => (rhs_reg, lhs_reg),
+                    _ => (lhs_reg, rhs_reg),
+                };
+
                 self.emit_ins(opcode.encode(vec![
                     reg_value as u32,
                     lhs_reg as u32,
@@ -181,118 +292,11 @@ impl BytecodeGen {
                     self.visit_list(expr.clone())
                 }
             }
-            Expr::FunctionDeclare { body, params } => {
-                let idx_func = self.make_function(body.clone(), params.clone());
-
-                let reg_value = self
-                    .get_register()
-                    .expect("Error in get register: the value");
-
-                self.emit_ins(OpCode::MAKEFUNC.encode(vec![
-                    reg_value as u32,
-                    idx_func.try_into().expect("Error when convert idx_func to u32"),
-                ]));
-
-                Ok(reg_value)
-            }
-            Expr::FunctionCall { name, args } => {
-                if let Some(function_ref) = self.global_functions.get(name) {
-                    let function = function_ref.clone();
-
-                    if !self.global_function_in_used.contains_key(name) {
-                        let idx_func =
-                            self.make_function(function.body.clone(), function.params.clone());
-
-                        self.global_function_in_used.insert(name.clone(), idx_func);
-                    }
-
-                    if args.len() != function.params.len() {
-                        panic!(
-                            "Function {} have {} params, but call it with {} arg",
-                            name,
-                            function.params.len(),
-                            args.len(),
-                        )
-                    };
-
-                    for arg in args {
-                        let reg_arg = &self.visit_expr(arg, false)?;
-
-                        self.emit_ins(OpCode::PUSHARG.encode(vec![*reg_arg as u32]));
-
-                        self.free_register(*reg_arg as usize);
-                    }
-
-                    let reg_func_name = self
-                        .get_register()
-                        .expect("Error in get register: function name");
-
-                    let idx_reg_func_name = self.make_const(Constant::String(name.clone()));
-
-                    self.emit_ins(OpCode::LOADCONST.encode(vec![
-                            reg_func_name as u32,
-                            idx_reg_func_name
-                                .try_into()
-                                .expect("Error when convert idx_const to u32"),
-                        ]));
-
-                    let reg_dist_result = self
-                        .get_register()
-                        .expect("Error in get register: dist result");
-
-                    self.emit_ins(
-                        OpCode::CALL.encode(vec![reg_dist_result as u32, reg_func_name as u32]),
-                    );
-
-                    self.free_register(reg_func_name as usize);
-                    self.free_register(reg_dist_result as usize);
-                    return Ok(reg_dist_result)
-                }
-
-                if let Some(function_ref) = self.foreign_fn_map.get(name) {
-                    let r = *function_ref;
-
-                    // if args.len() != function.params.len() {
-                    //     panic!(
-                    //         "Function {} have {} params, but call it with {} arg",
-                    //         name,
-                    //         function.params.len(),
-                    //         args.len(),
-                    //     )
-                    // };
-
-                    self.emit_ins(OpCode::RESETPAR.encode(vec![]));
-
-                    for arg in args {
-                        let reg_arg = &self.visit_expr(arg, false)?;
-
-                        self.emit_ins(OpCode::PUSHARG.encode(vec![*reg_arg as u32]));
-
-                        self.free_register(*reg_arg as usize);
-                    }
-
-                    let reg_dist_result = self
-                        .get_register()
-                        .expect("Error in get register: dist result");
-
-                    self.emit_ins(
-                        OpCode::CFOREIGN.encode(vec![reg_dist_result as u32, r]),
-                    );
-
-                    self.free_register(reg_dist_result as usize);
-                    return Ok(reg_dist_result)
-                }
-
-                Err(BytecodeGenerationError::NotFoundFunction {})
-            }
-            Expr::LetIn { let_part, in_part } => {
-                self.new_frame_in_context();
-                for (k, v) in let_part {
-                    let value_reg = &self.visit_expr(v, false)?;
-                    self.insert_variable_in_current_context(k.to_string(), *value_reg);
-                }
-                let reg_in_part = &self.visit_expr(in_part, false)?;
-                Ok(*reg_in_part)
+            Expr::FunctionDeclare { body, params } => self.visit_function_declare(body, params, None),
+            Expr::FunctionCall { callee, args } => self.visit_function_call(callee, args, false),
+            Expr::LetIn { let_part, in_part } => self.visit_let_in(let_part, in_part, false),
+            Expr::If { cond, then_branch, else_branch } => {
+                self.visit_if(cond, then_branch, else_branch, false)
             }
             Expr::InterpolatedString(parts) => {
                 let reg_value = self
@@ -345,33 +349,394 @@ impl BytecodeGen {
                 }
                 Ok(reg_value)
             }
-            Expr::AccessField { parent, child } => {
-                let reg_parent = self.visit_expr(parent, false)?;
-                let idx_const = self.make_const(Constant::String(child.clone()));
-                let reg_const = self
-                    .get_register()
-                    .expect("Error in get register: the value");
-
+            Expr::AccessField { .. } | Expr::OptionalAccessField { .. } => {
+                let (reg_value, _) = self.visit_access(expr, false)?;
+                Ok(reg_value)
+            }
+            Expr::WithDefault { expr, default } => {
+                let reg_value = match expr.as_ref() {
+                    Expr::Var(_) => {
+                        // Never overwrite the variable's own register
+                        let reg_var = self.visit_expr(expr, false)?;
+                        let reg_value = self
+                            .get_register()
+                            .expect("Error in get register: the value");
+                        self.emit_ins(OpCode::MOVE.encode(vec![reg_value as u32, reg_var as u32]));
+                        reg_value
+                    }
+                    _ => self.visit_access(expr, true)?.0,
+                };
 
-                self.emit_ins(OpCode::LOADCONST.encode(vec![
-                    reg_const as u32,
-                    idx_const.try_into().expect("Error when convert idx_const to u32"),
-                ]));
+                let jump_idx = self.emit_ins(OpCode::JMPSOME.encode(vec![reg_value as u32, 0])) - 1;
 
-                let reg_value = self
-                    .get_register()
-                    .expect("Error in get register: the value");
+                let reg_default = self.visit_expr(default, false)?;
+                self.emit_ins(OpCode::MOVE.encode(vec![reg_value as u32, reg_default as u32]));
+                self.free_register(reg_default as usize);
 
-                self.emit_ins(OpCode::GETFIELD.encode(vec![reg_value as u32, reg_parent as u32, reg_const as u32]));
-                self.free_register(reg_parent as usize);
-                self.free_register(reg_const as usize);
+                self.patch_ins(jump_idx, OpCode::JMPSOME.encode(vec![reg_value as u32, self.ins_count]));
 
                 Ok(reg_value)
             }
+            Expr::Located { span, expr } => {
+                self.visit_located(*span, |this| this.visit_expr(expr, is_make_thunk))
+            }
             expr => panic!("Error: emit_expr, not implement yet {:?}", expr),
         }
     }
 
+    /// Emit a field access chain. Once a `?.` (or a forced optional access)
+    /// appears, the rest of the chain yields None instead of failing.
+    fn visit_access(
+        &mut self,
+        expr: &Expr,
+        is_optional: bool,
+    ) -> Result<(u8, bool), BytecodeGenerationError> {
+        let (parent, child, is_optional_step) = match expr {
+            Expr::AccessField { parent, child } => (parent, child, is_optional),
+            Expr::OptionalAccessField { parent, child } => (parent, child, true),
+            Expr::Located { span, expr } => {
+                return self.visit_located(*span, |this| this.visit_access(expr, is_optional));
+            }
+            _ => return Ok((self.visit_expr(expr, false)?, false)),
+        };
+
+        let (reg_parent, is_optional_parent) = self.visit_access(parent, is_optional)?;
+        self.hold_register(reg_parent);
+        let is_optional_chain = is_optional_step || is_optional_parent;
+
+        let idx_const = self.make_const(Constant::String(child.clone()));
+        let reg_const = self
+            .get_register()
+            .expect("Error in get register: the value");
+
+        self.emit_ins(OpCode::LOADCONST.encode(vec![
+            reg_const as u32,
+            idx_const.try_into().expect("Error when convert idx_const to u32"),
+        ]));
+
+        let reg_value = self
+            .get_register()
+            .expect("Error in get register: the value");
+
+        let opcode = if is_optional_chain { OpCode::OPTFIELD } else { OpCode::GETFIELD };
+
+        self.emit_ins(opcode.encode(vec![reg_value as u32, reg_parent as u32, reg_const as u32]));
+        self.free_register(reg_parent as usize);
+        self.free_register(reg_const as usize);
+
+        Ok((reg_value, is_optional_chain))
+    }
+
+    /// Bind the names of `pattern` against the value in `reg`. Destructuring
+    /// is lowered to GETFIELD, or OPTFIELD + JMPSOME when a default is given.
+    /// Every register taken for a bound value is pushed to `reg_bindings`.
+    fn bind_pattern(
+        &mut self,
+        pattern: &Pattern,
+        reg: u8,
+        reg_bindings: &mut Vec<usize>,
+    ) -> Result<(), BytecodeGenerationError> {
+        let items: Vec<(Constant, &Pattern, &Option<Expr>)> = match pattern {
+            Pattern::Name(name) => {
+                self.insert_variable_in_current_context(name.clone(), reg);
+                return Ok(());
+            }
+            Pattern::Table(fields) => fields
+                .iter()
+                .map(|(field, pattern, default)| (Constant::String(field.clone()), pattern, default))
+                .collect(),
+            Pattern::List(items) => items
+                .iter()
+                .enumerate()
+                .map(|(idx, (pattern, default))| (Constant::Number(idx as i32), pattern, default))
+                .collect(),
+        };
+
+        for (key, pattern, default) in items {
+            let idx_const = self.make_const(key);
+            let reg_key = self
+                .get_register()
+                .expect("Error in get register: pattern key");
+
+            self.emit_ins(OpCode::LOADCONST.encode(vec![
+                reg_key as u32,
+                idx_const.try_into().expect("Error when convert idx_const to u32"),
+            ]));
+
+            let reg_value = self
+                .get_register()
+                .expect("Error in get register: pattern value");
+            reg_bindings.push(reg_value as usize);
+
+            let opcode = if default.is_some() { OpCode::OPTFIELD } else { OpCode::GETFIELD };
+            self.emit_ins(opcode.encode(vec![reg_value as u32, reg as u32, reg_key as u32]));
+            self.free_register(reg_key as usize);
+
+            if let Some(default) = default {
+                let jump_idx = self.emit_ins(OpCode::JMPSOME.encode(vec![reg_value as u32, 0])) - 1;
+
+                let reg_default = self.visit_expr(default, false)?;
+                self.emit_ins(OpCode::MOVE.encode(vec![reg_value as u32, reg_default as u32]));
+                self.free_register(reg_default as usize);
+
+                self.patch_ins(jump_idx, OpCode::JMPSOME.encode(vec![reg_value as u32, self.ins_count]));
+            }
+
+            self.bind_pattern(pattern, reg_value, reg_bindings)?;
+        }
+
+        Ok(())
+    }
+
+    /// Emit a call. With `is_tail`, calls to Angi functions become TAILCALL,
+    /// which reuses the current frame instead of growing the native stack.
+    fn visit_function_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        is_tail: bool,
+    ) -> Result<u8, BytecodeGenerationError> {
+        // Local bindings shadow global and foreign functions
+        let name = match callee {
+            Expr::Var(name) if self.get_variable_from_context(name).is_none() => name,
+            _ => return self.visit_call(callee, args, is_tail),
+        };
+
+        if let Some(function_ref) = self.global_functions.get(name) {
+            let function = function_ref.clone();
+
+            if !self.global_function_in_used.contains_key(name) {
+                let idx_func =
+                    self.make_function(function.body.clone(), function.params.clone());
+
+                // Intern the name now, the pool is written before the global table
+                self.make_const(Constant::String(name.clone()));
+                self.global_function_in_used.insert(name.clone(), idx_func);
+            }
+
+            if args.len() != function.params.len() {
+                panic!(
+                    "Function {} have {} params, but call it with {} arg",
+                    name,
+                    function.params.len(),
+                    args.len(),
+                )
+            };
+
+            self.push_args(args)?;
+
+            let reg_func_name = self
+                .get_register()
+                .expect("Error in get register: function name");
+
+            let idx_reg_func_name = self.make_const(Constant::String(name.clone()));
+
+            self.emit_ins(OpCode::LOADCONST.encode(vec![
+                    reg_func_name as u32,
+                    idx_reg_func_name
+                        .try_into()
+                        .expect("Error when convert idx_const to u32"),
+                ]));
+
+            let reg_dist_result = self
+                .get_register()
+                .expect("Error in get register: dist result");
+
+            if is_tail {
+                self.emit_ins(OpCode::TAILCALL.encode(vec![reg_func_name as u32]));
+            } else {
+                self.emit_ins(
+                    OpCode::CALL.encode(vec![reg_dist_result as u32, reg_func_name as u32]),
+                );
+            }
+
+            self.free_register(reg_func_name as usize);
+            self.free_register(reg_dist_result as usize);
+            return Ok(reg_dist_result)
+        }
+
+        if let Some(function_ref) = self.foreign_fn_map.get(name) {
+            let r = *function_ref;
+
+            // if args.len() != function.params.len() {
+            //     panic!(
+            //         "Function {} have {} params, but call it with {} arg",
+            //         name,
+            //         function.params.len(),
+            //         args.len(),
+            //     )
+            // };
+
+            self.emit_ins(OpCode::RESETPAR.encode(vec![]));
+
+            self.push_args(args)?;
+
+            let reg_dist_result = self
+                .get_register()
+                .expect("Error in get register: dist result");
+
+            self.emit_ins(
+                OpCode::CFOREIGN.encode(vec![reg_dist_result as u32, r]),
+            );
+
+            self.free_register(reg_dist_result as usize);
+            return Ok(reg_dist_result)
+        }
+
+        Err(BytecodeGenerationError::NotFoundFunction {})
+    }
+
+    fn visit_let_in(
+        &mut self,
+        let_part: &[(Pattern, Expr)],
+        in_part: &Expr,
+        is_tail: bool,
+    ) -> Result<u8, BytecodeGenerationError> {
+        self.new_frame_in_context();
+        let mut reg_bindings = vec![];
+        for (pattern, v) in let_part {
+            let value_reg = match (pattern, v) {
+                // A let-bound function can call itself by its binding name
+                (Pattern::Name(name), Expr::FunctionDeclare { params, body }) => {
+                    self.visit_function_declare(body, params, Some(name.clone()))?
+                }
+                _ => self.visit_expr(v, false)?,
+            };
+            // Keep the binding alive until the `in` part is done
+            if self.hold_register(value_reg) {
+                reg_bindings.push(value_reg as usize);
+            }
+            self.bind_pattern(pattern, value_reg, &mut reg_bindings)?;
+        }
+        let reg_in_part = self.visit_branch(in_part, is_tail)?;
+        self.clear_bottom_context();
+        reg_bindings.retain(|reg| *reg != reg_in_part as usize);
+        self.free_registers(reg_bindings);
+        Ok(reg_in_part)
+    }
+
+    fn visit_if(
+        &mut self,
+        cond: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+        is_tail: bool,
+    ) -> Result<u8, BytecodeGenerationError> {
+        let reg_cond = self.visit_expr(cond, false)?;
+        let jump_else_idx = self.emit_ins(OpCode::JMPIFNOT.encode(vec![reg_cond as u32, 0])) - 1;
+        self.free_register(reg_cond as usize);
+
+        let reg_value = self
+            .get_register()
+            .expect("Error in get register: the value");
+
+        let reg_then = self.visit_branch(then_branch, is_tail)?;
+        self.emit_ins(OpCode::MOVE.encode(vec![reg_value as u32, reg_then as u32]));
+        self.free_register(reg_then as usize);
+        let jump_end_idx = self.emit_ins(OpCode::JMP.encode(vec![0])) - 1;
+
+        self.patch_ins(jump_else_idx, OpCode::JMPIFNOT.encode(vec![reg_cond as u32, self.ins_count]));
+
+        let reg_else = self.visit_branch(else_branch, is_tail)?;
+        self.emit_ins(OpCode::MOVE.encode(vec![reg_value as u32, reg_else as u32]));
+        self.free_register(reg_else as usize);
+
+        self.patch_ins(jump_end_idx, OpCode::JMP.encode(vec![self.ins_count]));
+
+        self.free_register(reg_value as usize);
+        Ok(reg_value)
+    }
+
+    fn visit_branch(&mut self, expr: &Expr, is_tail: bool) -> Result<u8, BytecodeGenerationError> {
+        if is_tail {
+            self.visit_tail_expr(expr)
+        } else {
+            self.visit_expr(expr, false)
+        }
+    }
+
+    /// Visit an expression whose value is returned as-is by the enclosing
+    /// function, so calls found there can be emitted as tail calls.
+    fn visit_tail_expr(&mut self, expr: &Expr) -> Result<u8, BytecodeGenerationError> {
+        match expr {
+            Expr::FunctionCall { callee, args } => self.visit_function_call(callee, args, true),
+            Expr::LetIn { let_part, in_part } => self.visit_let_in(let_part, in_part, true),
+            Expr::If { cond, then_branch, else_branch } => {
+                self.visit_if(cond, then_branch, else_branch, true)
+            }
+            Expr::Located { span, expr } => self.visit_located(*span, |this| this.visit_tail_expr(expr)),
+            _ => self.visit_expr(expr, false),
+        }
+    }
+
+    fn visit_function_declare(
+        &mut self,
+        body: &Expr,
+        params: &[Pattern],
+        name: Option<String>,
+    ) -> Result<u8, BytecodeGenerationError> {
+        let idx_func = self.make_function(Box::new(body.clone()), params.to_vec());
+        self.functions[idx_func - 1].label = name.clone();
+        self.functions[idx_func - 1].name = name;
+
+        let reg_value = self
+            .get_register()
+            .expect("Error in get register: the value");
+
+        self.emit_ins(OpCode::MAKEFUNC.encode(vec![
+            reg_value as u32,
+            idx_func.try_into().expect("Error when convert idx_func to u32"),
+        ]));
+
+        Ok(reg_value)
+    }
+
+    /// Call a function value produced by an arbitrary expression,
+    /// e.g. a let-bound function, `obj.method(..)`, `(f)(x)` or `f(x)(y)`.
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        is_tail: bool,
+    ) -> Result<u8, BytecodeGenerationError> {
+        let reg_callee = self.visit_expr(callee, false)?;
+        self.hold_register(reg_callee);
+
+        self.push_args(args)?;
+
+        let reg_dist_result = self
+            .get_register()
+            .expect("Error in get register: dist result");
+
+        if is_tail {
+            self.emit_ins(OpCode::TAILCALL.encode(vec![reg_callee as u32]));
+        } else {
+            self.emit_ins(OpCode::CALL.encode(vec![reg_dist_result as u32, reg_callee as u32]));
+        }
+
+        self.free_register(reg_callee as usize);
+        self.free_register(reg_dist_result as usize);
+        Ok(reg_dist_result)
+    }
+
+    /// Evaluate every argument before pushing any of them, so calls nested
+    /// inside the arguments cannot interleave with this call's queue.
+    fn push_args(&mut self, args: &[Expr]) -> Result<(), BytecodeGenerationError> {
+        let mut reg_args = vec![];
+        for arg in args {
+            let reg_arg = self.visit_expr(arg, false)?;
+            self.hold_register(reg_arg);
+            reg_args.push(reg_arg as usize);
+        }
+
+        for reg_arg in &reg_args {
+            self.emit_ins(OpCode::PUSHARG.encode(vec![*reg_arg as u32]));
+        }
+
+        self.free_registers(reg_args);
+        Ok(())
+    }
+
     fn visit_table(&mut self, expr: Expr) -> Result<u8, BytecodeGenerationError> {
         if let Expr::Table { fields } = expr {
             let reg_table = self.get_register().expect("Error in get register: table");
@@ -389,6 +754,12 @@ impl BytecodeGen {
 
                 let reg_value = self.visit_expr(value, true)?;
 
+                if let Expr::FunctionDeclare { .. } = value
+                    && let Some(function) = self.functions.last_mut()
+                {
+                    function.label = Some(key.clone());
+                }
+
                 self.emit_ins(OpCode::SETATTR.encode(vec![
                     reg_table as u32,
                     reg_key as u32,
@@ -434,6 +805,7 @@ impl BytecodeGen {
         while self.function_pointer < self.functions.len() {
             self.set_offset_func(self.function_pointer, self.ins_count);
             let function = self.functions[self.function_pointer].clone();
+            let start = self.ins_count;
             let mut reg_params: Vec<usize> = vec![];
 
             self.new_frame_in_context();
@@ -442,25 +814,43 @@ impl BytecodeGen {
             //     panic!("Context var not empty");
             // }
 
-            for param in function.params {
+            if let Some(name) = &function.name {
+                let reg_self = self
+                    .get_register()
+                    .expect("Error in get register: function itself");
+                self.emit_ins(OpCode::MAKEFUNC.encode(vec![
+                    reg_self as u32,
+                    (self.function_pointer + 1) as u32,
+                ]));
+                self.insert_variable_in_current_context(name.clone(), reg_self);
+                reg_params.push(reg_self as usize);
+            }
+
+            for param in function.params.iter() {
                 let reg_param = self
                     .get_register()
                     .expect("Error in get register: params function");
-                self.insert_variable_in_current_context(param.clone(), reg_param);
                 self.emit_ins(OpCode::LOADARG.encode(vec![reg_param as u32]));
                 reg_params.push(reg_param as usize);
+                self.bind_pattern(param, reg_param, &mut reg_params)?;
             }
 
             self.is_in_func = true;
 
-            let reg_value = self.visit_expr(&function.body, false)?;
+            let reg_value = self.visit_tail_expr(&function.body)?;
 
-            self.free_registers(reg_params);
             self.emit_ins(OpCode::RETURN.encode(vec![reg_value as u32]));
-            self.free_register(reg_value as usize);
             // self.context_var.clear();
             self.is_in_func = false;
             self.clear_bottom_context();
+            self.free_registers(reg_params);
+            self.free_register(reg_value as usize);
+
+            self.scopes.push(ScopeEntry {
+                start,
+                end: self.ins_count,
+                name: function.label.unwrap_or_else(|| "<anonymous>".into()),
+            });
 
             self.function_pointer += 1;
         }
@@ -468,23 +858,7 @@ impl BytecodeGen {
     }
 
     pub fn add_const_to_binary(&mut self, bytes: &mut Vec<u8>) {
-        let mut sort_const: Vec<(&Constant, &usize)> = self.constants.iter().collect();
-
-        sort_const.sort_by(|a, b| a.1.cmp(b.1));
-
-        for (constant, _) in sort_const {
-            match constant {
-                Constant::Number(num) => {
-                    bytes.extend_from_slice(&0_u8.to_be_bytes());
-                    bytes.extend_from_slice(&(*num as i64).to_be_bytes());
-                }
-                Constant::String(str) => {
-                    bytes.extend_from_slice(&1_u8.to_be_bytes());
-                    bytes.extend_from_slice(&(str.len() as u32).to_be_bytes());
-                    bytes.extend_from_slice(&str.clone().into_bytes());
-                }
-            }
-        }
+        self.constants.write(bytes);
     }
 
     pub fn add_header_to_binary(&self, bytes: &mut Vec<u8>) {
@@ -582,12 +956,14 @@ impl BytecodeGen {
         idx
     }
 
-    pub fn make_function(&mut self, body: Box<Expr>, params: Vec<String>) -> usize {
+    pub fn make_function(&mut self, body: Box<Expr>, params: Vec<Pattern>) -> usize {
         let idx = self.functions.len() + 1;
         self.functions.push(Function {
             params,
             body,
             offset: 0,
+            name: None,
+            label: None,
         });
         idx
     }
@@ -609,9 +985,12 @@ impl BytecodeGen {
     }
 
     pub fn make_const(&mut self, constant: Constant) -> usize {
-        let idx = self.constants.len() + 1;
-        let result = self.constants.entry(constant).or_insert(idx);
-        *result
+        self.constants.intern(constant)
+    }
+
+    pub fn patch_ins(&mut self, idx: usize, bytes: [u8; 4]) {
+        let start = idx * 4;
+        self.ins_code[start..start + 4].copy_from_slice(&bytes);
     }
 
     pub fn get_register(&mut self) -> Option<u8> {
@@ -624,36 +1003,37 @@ impl BytecodeGen {
         None
     }
 
+    /// Release a register, unless it still holds a variable in scope.
     pub fn free_register(&mut self, idx: usize) {
+        if self.is_variable_register(idx as u8) {
+            return;
+        }
         self.register_in_used[idx] = false;
     }
 
     pub fn free_registers(&mut self, idx_vec: Vec<usize>) {
         for idx in idx_vec {
-            self.register_in_used[idx] = false;
+            self.free_register(idx);
         }
     }
 
     pub fn emit_ins(&mut self, bytes: [u8; 4]) -> usize {
+        let (line, column) = self
+            .current_span
+            .map(|span| (span.line, span.column))
+            .unwrap_or((0, 0));
+
+        if self.line_table.last().map(|entry| (entry.line, entry.column)) != Some((line, column)) {
+            self.line_table.push(LineEntry { ins: self.ins_count, line, column });
+        }
+
         self.ins_code.extend_from_slice(&bytes);
         self.ins_count += 1;
         self.ins_count as usize
     }
 
     pub fn get_constant_len_in_bytes(&self) -> u32 {
-        let mut const_len = 0;
-        for constant in self.constants.keys() {
-            match constant {
-                Constant::Number(_) => {
-                    const_len += 9; // 1 byte type + 8 byte num
-                }
-                Constant::String(str) => {
-                    const_len += 5 + str.len() as u32
-                    // 1 byte type + 4 byte len + len of str
-                }
-            }
-        }
-        const_len
+        self.constants.len_in_bytes()
     }
 
     fn insert_variable_in_current_context(&mut self, name: String, reg: u8) {
@@ -673,6 +1053,22 @@ impl BytecodeGen {
         None
     }
 
+    /// Mark the result register of a visited expression as busy again,
+    /// unless it belongs to a variable. Returns whether it was taken.
+    fn hold_register(&mut self, reg: u8) -> bool {
+        if self.is_variable_register(reg) {
+            return false;
+        }
+        self.register_in_used[reg as usize] = true;
+        true
+    }
+
+    fn is_variable_register(&self, reg: u8) -> bool {
+        self.context_var
+            .iter()
+            .any(|frame| frame.values().any(|var| *var == reg))
+    }
+
     fn new_frame_in_context(&mut self) {
         self.context_var.push(HashMap::new());
     }
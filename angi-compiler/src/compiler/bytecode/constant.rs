@@ -1,6 +1,105 @@
-#[derive(Eq, Hash, PartialEq, Debug)]
+use std::collections::HashMap;
+
+use angi_ins::{CONST_BOOL, CONST_FLOAT, CONST_INT, CONST_NONE, CONST_STRING};
+
+/// A constant pool entry. Floats are kept as their bits so equal
+/// literals hash to the same entry.
+#[derive(Eq, Hash, PartialEq, Debug, Clone)]
 pub enum Constant {
     Number(i32),
     String(String),
+    Bool(bool),
+    None,
+    Float(u64),
 }
 
+impl Constant {
+    pub fn float(float: f64) -> Self {
+        Constant::Float(float.to_bits())
+    }
+
+    /// Encoded size: the type tag plus the payload.
+    pub fn len_in_bytes(&self) -> u32 {
+        match self {
+            Constant::Number(_) | Constant::Float(_) => 1 + 8,
+            Constant::String(str) => 1 + 4 + str.len() as u32,
+            Constant::Bool(_) => 1 + 1,
+            Constant::None => 1,
+        }
+    }
+
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Constant::Number(num) => {
+                bytes.push(CONST_INT);
+                bytes.extend_from_slice(&(*num as i64).to_be_bytes());
+            }
+            Constant::String(str) => {
+                bytes.push(CONST_STRING);
+                bytes.extend_from_slice(&(str.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(str.as_bytes());
+            }
+            Constant::Bool(bool) => {
+                bytes.push(CONST_BOOL);
+                bytes.push(*bool as u8);
+            }
+            Constant::None => bytes.push(CONST_NONE),
+            Constant::Float(bits) => {
+                bytes.push(CONST_FLOAT);
+                bytes.extend_from_slice(&bits.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Interned constants in index order. Adding a constant that is already
+/// in the pool returns the existing index, so every literal is stored once.
+#[derive(Debug, Default)]
+pub struct ConstPool {
+    entries: Vec<Constant>,
+    index: HashMap<Constant, usize>,
+}
+
+impl ConstPool {
+    pub fn new() -> Self {
+        ConstPool::default()
+    }
+
+    /// 1-based index of `constant`, adding it if needed.
+    pub fn intern(&mut self, constant: Constant) -> usize {
+        if let Some(idx) = self.index.get(&constant) {
+            return *idx;
+        }
+
+        self.entries.push(constant.clone());
+        let idx = self.entries.len();
+        self.index.insert(constant, idx);
+        idx
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Constant> {
+        self.entries.get(idx.checked_sub(1)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Constant> {
+        self.entries.iter()
+    }
+
+    pub fn len_in_bytes(&self) -> u32 {
+        self.entries.iter().map(Constant::len_in_bytes).sum()
+    }
+
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        for constant in &self.entries {
+            constant.write(bytes);
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use crate::compiler::ast::Expr;
+use crate::compiler::ast::{Expr, Pattern};
 
 #[derive(Clone, Debug)]
 pub struct Function {
     pub offset: u32,
-    pub params: Vec<String>,
-    pub body: Box<Expr>
+    pub params: Vec<Pattern>,
+    pub body: Box<Expr>,
+    /// Binding name a let-bound function uses to call itself
+    pub name: Option<String>,
+    /// Name shown in stack traces
+    pub label: Option<String>
 }
 
 
@@ -15,7 +19,9 @@ impl Function {
             Some(Function {
                 offset: 0,
                 params,
-                body
+                body,
+                name: None,
+                label: None
             })
         } else {
             None
@@ -0,0 +1,397 @@
+use angi_ins::{OpCode, Operand, extract_opcode};
+
+use super::constant::{ConstPool, Constant};
+
+/// Registers as a bit set, one bit per register.
+type RegSet = u16;
+
+/// Rewrite the emitted instruction stream:
+///
+/// - `CONCAT` of two known constants becomes a single `LOADCONST`, and a
+///   constant appended right after another one is merged into it
+/// - `MOVE rA, rB` right after the instruction that produced `rB` is folded
+///   into it, self moves are dropped
+/// - loads whose register is never read again are removed
+/// - registers are renumbered from `r0` in order of first use
+///
+/// `entries` are the instruction indices code can start running from (the
+/// root, every thunk and every function). Each of them starts a region that
+/// runs in its own register frame. Returns the new code and, for every old
+/// instruction index up to `code.len()`, the index it moved to.
+pub fn peephole(code: &[u32], entries: &[u32], constants: &mut ConstPool) -> (Vec<u32>, Vec<u32>) {
+    let mut slots: Vec<Option<u32>> = code.iter().copied().map(Some).collect();
+    let leaders = leaders(code, entries);
+
+    let mut regions: Vec<usize> = entries.iter().map(|entry| *entry as usize).collect();
+    regions.push(0);
+    regions.push(code.len());
+    regions.sort_unstable();
+    regions.dedup();
+
+    for region in regions.windows(2) {
+        let (start, end) = (region[0], region[1]);
+        // One rewrite at a time, so each one sees up to date liveness
+        loop {
+            let live_out = liveness(&slots, start, end);
+            if !rewrite_once(&mut slots, start, end, &live_out, &leaders, constants) {
+                break;
+            }
+        }
+        compact_registers(&mut slots[start..end]);
+    }
+
+    let mut new_index = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+    for slot in &slots {
+        new_index.push(kept);
+        if slot.is_some() {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let code = slots
+        .into_iter()
+        .flatten()
+        .map(|word| retarget(word, &new_index))
+        .collect();
+
+    (code, new_index)
+}
+
+/// Instructions control can reach other than by falling through.
+fn leaders(code: &[u32], entries: &[u32]) -> Vec<bool> {
+    let mut leaders = vec![false; code.len() + 1];
+    for entry in entries {
+        leaders[*entry as usize] = true;
+    }
+    for word in code {
+        if let Some(target) = jump_target(*word) {
+            leaders[target as usize] = true;
+        }
+    }
+    leaders
+}
+
+fn jump_target(word: u32) -> Option<u32> {
+    match extract_opcode(word)? {
+        OpCode::JMP => Some(OpCode::JMP.decode(word)[0]),
+        opcode @ (OpCode::JMPSOME | OpCode::JMPIFNOT) => Some(opcode.decode(word)[1]),
+        _ => None,
+    }
+}
+
+fn retarget(word: u32, new_index: &[u32]) -> u32 {
+    let Some(opcode) = extract_opcode(word) else {
+        return word;
+    };
+    let mut params = opcode.decode(word);
+    match opcode {
+        OpCode::JMP => params[0] = new_index[params[0] as usize],
+        OpCode::JMPSOME | OpCode::JMPIFNOT => params[1] = new_index[params[1] as usize],
+        _ => return word,
+    }
+    u32::from_be_bytes(opcode.encode(params))
+}
+
+/// Registers an instruction writes and reads. `SETATTR` and `ADDLIST`
+/// update their collection in place, so it only counts as read.
+fn defs_uses(word: u32) -> (RegSet, RegSet) {
+    let Some(opcode) = extract_opcode(word) else {
+        return (0, 0);
+    };
+    let params = opcode.decode(word);
+    let reg = |idx: usize| 1 << params[idx];
+
+    match opcode {
+        OpCode::LOADCONST
+        | OpCode::MAKETABLE
+        | OpCode::MAKELIST
+        | OpCode::MAKEFUNC
+        | OpCode::MAKETHUNK
+        | OpCode::LOADARG
+        | OpCode::CFOREIGN => (reg(0), 0),
+        OpCode::ADD
+        | OpCode::SUB
+        | OpCode::MUL
+        | OpCode::DIV
+        | OpCode::CONCAT
+        | OpCode::GETFIELD
+        | OpCode::OPTFIELD
+        | OpCode::EQ
+        | OpCode::NE
+        | OpCode::LT
+        | OpCode::LE => (reg(0), reg(1) | reg(2)),
+        OpCode::MOVE | OpCode::CALL => (reg(0), reg(1)),
+        OpCode::SETATTR => (0, reg(0) | reg(1) | reg(2)),
+        OpCode::ADDLIST => (0, reg(0) | reg(1)),
+        OpCode::PUSHARG
+        | OpCode::RETURN
+        | OpCode::TAILCALL
+        | OpCode::JMPSOME
+        | OpCode::JMPIFNOT => (0, reg(0)),
+        OpCode::JMP | OpCode::RESETPAR | OpCode::LOADIM => (0, 0),
+    }
+}
+
+/// Whether removing the instruction is unobservable once its result is dead.
+fn is_pure_load(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::LOADCONST
+            | OpCode::MOVE
+            | OpCode::MAKETABLE
+            | OpCode::MAKELIST
+            | OpCode::MAKEFUNC
+            | OpCode::MAKETHUNK
+    )
+}
+
+fn is_terminator(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::RETURN | OpCode::TAILCALL | OpCode::JMP | OpCode::JMPSOME | OpCode::JMPIFNOT
+    )
+}
+
+/// Registers live after each slot of `start..end`. Removed slots fall through.
+fn liveness(slots: &[Option<u32>], start: usize, end: usize) -> Vec<RegSet> {
+    let mut live_in: Vec<RegSet> = vec![0; end - start + 1];
+    let mut live_out: Vec<RegSet> = vec![0; end - start];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in (start..end).rev() {
+            let local = idx - start;
+            let Some(word) = slots[idx] else {
+                live_out[local] = live_in[local + 1];
+                live_in[local] = live_out[local];
+                continue;
+            };
+
+            let successor = |target: u32| live_in[target as usize - start];
+            let out = match extract_opcode(word) {
+                Some(OpCode::RETURN | OpCode::TAILCALL) => 0,
+                Some(OpCode::JMP) => successor(OpCode::JMP.decode(word)[0]),
+                Some(opcode @ (OpCode::JMPSOME | OpCode::JMPIFNOT)) => {
+                    live_in[local + 1] | successor(opcode.decode(word)[1])
+                }
+                _ => live_in[local + 1],
+            };
+            let (defs, uses) = defs_uses(word);
+            let in_ = uses | (out & !defs);
+
+            if out != live_out[local] || in_ != live_in[local] {
+                live_out[local] = out;
+                live_in[local] = in_;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+/// Apply the first rewrite found in `start..end`. Returns whether one was.
+fn rewrite_once(
+    slots: &mut [Option<u32>],
+    start: usize,
+    end: usize,
+    live_out: &[RegSet],
+    leaders: &[bool],
+    constants: &mut ConstPool,
+) -> bool {
+    let is_dead_after = |idx: usize, reg: u32| live_out[idx - start] & (1 << reg) == 0;
+
+    for idx in start..end {
+        let Some(word) = slots[idx] else { continue };
+        let Some(opcode) = extract_opcode(word) else { continue };
+        let params = opcode.decode(word);
+
+        // A load nobody reads
+        if is_pure_load(opcode) && is_dead_after(idx, params[0]) {
+            slots[idx] = None;
+            return true;
+        }
+
+        if opcode == OpCode::MOVE && params[0] == params[1] {
+            slots[idx] = None;
+            return true;
+        }
+
+        // `op rB, ..; MOVE rA, rB` into `op rA, ..`
+        if let Some(next) = next_in_block(slots, idx, end, leaders)
+            && let Some(next_word) = slots[next]
+            && extract_opcode(next_word) == Some(OpCode::MOVE)
+            && defs_uses(word).0 != 0
+        {
+            let moved = OpCode::MOVE.decode(next_word);
+            if moved[1] == params[0] && is_dead_after(next, moved[1]) {
+                let mut params = params.clone();
+                params[0] = moved[0];
+                slots[idx] = Some(u32::from_be_bytes(opcode.encode(params)));
+                slots[next] = None;
+                return true;
+            }
+        }
+
+        if opcode != OpCode::CONCAT {
+            continue;
+        }
+
+        let (reg_dist, reg_lhs, reg_rhs) = (params[0], params[1], params[2]);
+        let Some(rhs) = known_text(slots, idx, reg_rhs, start, leaders, constants) else {
+            continue;
+        };
+
+        // Both sides known, e.g. `"a" .. "b"`
+        if let Some(lhs) = known_text(slots, idx, reg_lhs, start, leaders, constants) {
+            let idx_const = constants.intern(Constant::String(lhs + &rhs));
+            slots[idx] = Some(load_const(reg_dist, idx_const));
+            return true;
+        }
+
+        // `CONCAT rT, rX, rA; ..; LOADCONST rB, "b"; CONCAT rU, rT, rB` with `rA`
+        // known as "a" becomes `..; LOADCONST rB, "ab"; CONCAT rU, rX, rB`
+        let Some(load) = last_write(slots, idx, reg_rhs, start, leaders) else { continue };
+        let Some(prev) = last_write(slots, idx, reg_lhs, start, leaders) else { continue };
+        let load_word = slots[load].unwrap();
+        let prev_word = slots[prev].unwrap();
+        if extract_opcode(load_word) != Some(OpCode::LOADCONST)
+            || extract_opcode(prev_word) != Some(OpCode::CONCAT)
+            || prev > load
+        {
+            continue;
+        }
+
+        let [_, reg_base, reg_suffix] = OpCode::CONCAT.decode(prev_word)[..] else { continue };
+        let between = |from: usize| (from + 1..idx).filter_map(|idx| slots[idx]).map(defs_uses);
+        if between(prev).any(|(defs, uses)| uses & (1 << reg_lhs) != 0 || defs & (1 << reg_base) != 0)
+            || between(load).any(|(_, uses)| uses & (1 << reg_rhs) != 0)
+            || (reg_lhs != reg_dist && !is_dead_after(idx, reg_lhs))
+            || !is_dead_after(idx, reg_rhs)
+        {
+            continue;
+        }
+        let Some(lhs) = known_text(slots, prev, reg_suffix, start, leaders, constants) else {
+            continue;
+        };
+
+        let idx_const = constants.intern(Constant::String(lhs + &rhs));
+        slots[prev] = None;
+        slots[load] = Some(load_const(reg_rhs, idx_const));
+        slots[idx] = Some(u32::from_be_bytes(
+            OpCode::CONCAT.encode(vec![reg_dist, reg_base, reg_rhs]),
+        ));
+        return true;
+    }
+
+    false
+}
+
+fn load_const(reg: u32, idx_const: usize) -> u32 {
+    u32::from_be_bytes(OpCode::LOADCONST.encode(vec![reg, idx_const as u32]))
+}
+
+/// The next kept slot after `idx`, if control always falls into it from `idx`.
+fn next_in_block(slots: &[Option<u32>], idx: usize, end: usize, leaders: &[bool]) -> Option<usize> {
+    let mut next = idx + 1;
+    while next < end {
+        if leaders[next] {
+            return None;
+        }
+        if slots[next].is_some() {
+            return Some(next);
+        }
+        next += 1;
+    }
+    None
+}
+
+/// Walk back from `idx` within its basic block, returning the first kept
+/// slot for which `stop` gives an answer.
+fn walk_back<T>(
+    slots: &[Option<u32>],
+    idx: usize,
+    start: usize,
+    leaders: &[bool],
+    mut stop: impl FnMut(usize, u32) -> Option<Option<T>>,
+) -> Option<T> {
+    let mut cursor = idx;
+    while cursor > start && !leaders[cursor] {
+        cursor -= 1;
+        let Some(word) = slots[cursor] else { continue };
+        if extract_opcode(word).is_none_or(is_terminator) {
+            return None;
+        }
+        if let Some(answer) = stop(cursor, word) {
+            return answer;
+        }
+    }
+    None
+}
+
+/// The string `reg` holds right before `idx`, when it comes from a constant
+/// that `CONCAT` turns into text the same way at runtime.
+fn known_text(
+    slots: &[Option<u32>],
+    idx: usize,
+    reg: u32,
+    start: usize,
+    leaders: &[bool],
+    constants: &ConstPool,
+) -> Option<String> {
+    walk_back(slots, idx, start, leaders, |cursor, word| {
+        if defs_uses(word).0 & (1 << reg) == 0 {
+            return None;
+        }
+        let opcode = extract_opcode(word)?;
+        let params = opcode.decode(word);
+        Some(match opcode {
+            OpCode::LOADCONST => match constants.get(params[1] as usize) {
+                Some(Constant::String(str)) => Some(str.clone()),
+                Some(Constant::Number(num)) => Some(num.to_string()),
+                Some(Constant::Float(bits)) => Some(f64::from_bits(*bits).to_string()),
+                _ => None,
+            },
+            OpCode::MOVE => known_text(slots, cursor, params[1], start, leaders, constants),
+            _ => None,
+        })
+    })
+}
+
+/// The slot that last wrote `reg` before `idx`, within its basic block.
+fn last_write(
+    slots: &[Option<u32>],
+    idx: usize,
+    reg: u32,
+    start: usize,
+    leaders: &[bool],
+) -> Option<usize> {
+    walk_back(slots, idx, start, leaders, |cursor, word| {
+        (defs_uses(word).0 & (1 << reg) != 0).then_some(Some(cursor))
+    })
+}
+
+/// Renumber the registers of one frame from `r0`, in order of first use.
+fn compact_registers(slots: &mut [Option<u32>]) {
+    let mut mapping: [Option<u32>; 16] = [None; 16];
+    let mut next = 0;
+
+    for slot in slots.iter_mut() {
+        let Some(word) = slot else { continue };
+        let Some(opcode) = extract_opcode(*word) else { continue };
+
+        let mut params = opcode.decode(*word);
+        for (param, operand) in params.iter_mut().zip(opcode.layout()) {
+            if let Operand::RegAddr = operand {
+                *param = *mapping[*param as usize].get_or_insert_with(|| {
+                    next += 1;
+                    next - 1
+                });
+            }
+        }
+        *word = u32::from_be_bytes(opcode.encode(params));
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 
 type LocationError = (u32, u32); // (line, column)
 
@@ -21,6 +23,12 @@ pub enum CompilationError {
     IOError {
         message: String
     },
+    InvalidArgument {
+        message: String
+    },
+    RuntimeError {
+        message: String
+    },
     ParseError(ParseError),
     BytecodeGenerationError(BytecodeGenerationError),
     MacroCheckingError,
@@ -28,6 +36,23 @@ pub enum CompilationError {
     UnexpectedError
 }
 
+impl fmt::Display for CompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompilationError::IOError { message }
+            | CompilationError::InvalidArgument { message }
+            | CompilationError::RuntimeError { message } => {
+                write!(f, "{message}")
+            }
+            CompilationError::ParseError(err) => write!(f, "{}", err.error),
+            CompilationError::BytecodeGenerationError(err) => write!(f, "{err:?}"),
+            CompilationError::MacroCheckingError => write!(f, "macro expansion failed"),
+            CompilationError::ArchiveError => write!(f, "cannot create the archive"),
+            CompilationError::UnexpectedError => write!(f, "compilation failed"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BytecodeGenerationError {
     UnexpectExpr {
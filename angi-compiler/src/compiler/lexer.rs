@@ -8,6 +8,9 @@ pub type LineOfCode = u32;
 pub type Spanned = (LineOfCode, Token, PostionInLine);
 pub type LexResult = Result<Spanned, LexicalError>;
 
+/// Stands in for a `${...}` part while indentation is stripped.
+const INTERP_PLACEHOLDER: char = '\u{E000}';
+
 #[derive(Debug)]
 pub struct Lexer<'a>
 {
@@ -287,6 +290,126 @@ impl<'a> Lexer<'a>
         Ok((line, Token::MultilineString(string), (start_pos, end_pos)))
     }
 
+    /// Lex a Nix-style `''...''` string. The common leading indentation is
+    /// stripped, and `${expr}` parts are lexed into interpolation tokens.
+    fn lex_indented_string(&mut self) -> Result<(), LexicalError> {
+        let line = self.get_line();
+        let start_pos = self.get_pos();
+
+        let mut text = String::new();
+        let mut interps: Vec<(LineOfCode, String)> = vec![];
+
+        self.move_next_char(); // consume the first '
+
+        loop {
+            self.move_next_char();
+            match self.chr0 {
+                None => {
+                    return Err(LexicalError {
+                        error: "Unterminated indented string".into(),
+                        location: (line, start_pos),
+                    });
+                }
+                Some('\'') if self.chr1 == Some('\'') => match self.chr2 {
+                    Some('\'') => {
+                        // ''' is an escaped ''
+                        text.push_str("''");
+                        self.move_next_char();
+                        self.move_next_char();
+                    }
+                    Some('$') => {
+                        // ''$ is an escaped $
+                        text.push('$');
+                        self.move_next_char();
+                        self.move_next_char();
+                    }
+                    _ => {
+                        self.move_next_char(); // chr0 is the closing '
+                        break;
+                    }
+                },
+                Some('$') if self.chr1 == Some('{') => {
+                    let interp_line = self.get_line();
+                    self.move_next_char(); // consume the $
+                    let expr = self.lex_indented_string_interpolation(line, start_pos)?;
+                    text.push(INTERP_PLACEHOLDER);
+                    interps.push((interp_line, expr));
+                }
+                Some('\n') => {
+                    text.push('\n');
+                    self.reset_line();
+                }
+                Some(c) => text.push(c),
+            }
+        }
+
+        let end_pos = self.get_pos();
+        let stripped = strip_indentation(&text);
+
+        if interps.is_empty() {
+            self.emit((line, Token::MultilineString(stripped), (start_pos, end_pos)));
+            return Ok(());
+        }
+
+        self.emit((line, Token::StringStart, (start_pos, start_pos)));
+
+        let mut parts = stripped.split(INTERP_PLACEHOLDER);
+        let first = parts.next().unwrap_or_default();
+        self.emit((line, Token::String(first.to_string()), (start_pos, start_pos)));
+
+        for ((interp_line, expr), part) in interps.into_iter().zip(parts) {
+            self.emit((interp_line, Token::InterpStart, (start_pos, start_pos)));
+            for token in Lexer::new(expr.chars()) {
+                let (sub_line, tok, pos) = token?;
+                if tok == Token::EndOfFile {
+                    break;
+                }
+                self.emit((interp_line + sub_line - 1, tok, pos));
+            }
+            self.emit((interp_line, Token::InterpEnd, (end_pos, end_pos)));
+            self.emit((interp_line, Token::String(part.to_string()), (end_pos, end_pos)));
+        }
+
+        self.emit((line, Token::StringEnd, (end_pos, end_pos)));
+
+        Ok(())
+    }
+
+    /// Collect the source of a `${...}` inside an indented string, stopping at
+    /// the matching `}`. On return chr0 is that closing brace.
+    fn lex_indented_string_interpolation(
+        &mut self,
+        line: LineOfCode,
+        start_pos: u32,
+    ) -> Result<String, LexicalError> {
+        let mut expr = String::new();
+        let mut depth = 1;
+        let mut in_string = false;
+
+        loop {
+            self.move_next_char();
+            match self.chr0 {
+                None => {
+                    return Err(LexicalError {
+                        error: "Unterminated interpolation in indented string".into(),
+                        location: (line, start_pos),
+                    });
+                }
+                Some('"') if !expr.ends_with('\\') => in_string = !in_string,
+                Some('{') if !in_string => depth += 1,
+                Some('}') if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(expr);
+                    }
+                }
+                Some('\n') => self.reset_line(),
+                _ => {}
+            }
+            expr.push(self.chr0.expect("lex_indented_string_interpolation"));
+        }
+    }
+
     fn lex_number(&mut self) -> LexResult {
         let mut string = String::new();
         let line = self.get_line();
@@ -343,16 +466,42 @@ impl<'a> Lexer<'a>
                 if matches!(self.chr1, Some('>')) {
                     self.emit_one_character(Token::EqualRightArrow);
                     self.move_next_char();
+                } else if matches!(self.chr1, Some('=')) {
+                    self.emit_one_character(Token::DoubleEqual);
+                    self.move_next_char();
                 } else {
                     self.emit_one_character(Token::Equal);
                 }
             }
+            '!' if matches!(self.chr1, Some('=')) => {
+                self.emit_one_character(Token::NotEqual);
+                self.move_next_char();
+            }
+            '<' => {
+                if matches!(self.chr1, Some('=')) {
+                    self.emit_one_character(Token::LessEqual);
+                    self.move_next_char();
+                } else {
+                    self.emit_one_character(Token::Less);
+                }
+            }
+            '>' => {
+                if matches!(self.chr1, Some('=')) {
+                    self.emit_one_character(Token::GreaterEqual);
+                    self.move_next_char();
+                } else {
+                    self.emit_one_character(Token::Greater);
+                }
+            }
             ';' => {
                 self.emit_one_character(Token::Semicolon);
             }
             ',' => {
                 self.emit_one_character(Token::Comma);
             }
+            ':' => {
+                self.emit_one_character(Token::Colon);
+            }
             '.' => {
                 if matches!(self.chr1, Some('.')) {
                     self.emit_one_character(Token::DoubleDot);
@@ -361,6 +510,9 @@ impl<'a> Lexer<'a>
                     self.emit_one_character(Token::Dot);
                 }
             }
+            '\'' if self.chr1 == Some('\'') => {
+                self.lex_indented_string()?;
+            }
             '"' => {
                 if self.chr1 == Some('"') && self.chr2 == Some('"') {
                         let string = self.lex_multiline_string()?;
@@ -369,6 +521,13 @@ impl<'a> Lexer<'a>
                         self.lex_string()?;
                     }
             }
+            '?' if matches!(self.chr1, Some('.')) => {
+                self.emit_one_character(Token::QuestionDot);
+                self.move_next_char();
+            }
+            '?' => {
+                self.emit_one_character(Token::Question);
+            }
             '+' => {
                 self.emit_one_character(Token::Plus);
             }
@@ -425,6 +584,29 @@ impl<'a> Lexer<'a>
     }
 }
 
+/// Strip the indentation shared by all non-blank lines, Nix style. A blank
+/// first line is dropped, and blank lines are emptied.
+fn strip_indentation(text: &str) -> String {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| if line.trim().is_empty() { "" } else { &line[indent..] })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl Iterator for Lexer<'_>
 {
     type Item = LexResult;
@@ -1,8 +1,12 @@
+use std::collections::BTreeSet;
+
 use bytecode::{load_global, BytecodeGen};
 use error::{BytecodeGenerationError, CompilationError, ParseError};
 use lexer::Lexer;
+use optimization::OptLevel;
 use parser::parse_with_engine;
 use crate::{diagnostic::DiagnosticEngine, macro_function::MacroRegistry, type_checking};
+use angi_runtime::debug_info::DebugInfo;
 
 pub mod ast;
 pub mod error;
@@ -13,13 +17,45 @@ pub mod token;
 pub mod bytecode;
 
 pub fn compile(src: &str, filename: &str) -> Result<Vec<u8>, CompilationError> {
+    compile_with_debug_info(src, filename).map(|(byte, _)| byte)
+}
+
+/// Settings for a single compilation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompileOptions {
+    pub opt_level: OptLevel,
+}
+
+/// Compile and also return the debug info mapping instructions back to `filename`.
+pub fn compile_with_debug_info(
+    src: &str,
+    filename: &str,
+) -> Result<(Vec<u8>, DebugInfo), CompilationError> {
+    compile_with_options(src, filename, CompileOptions::default())
+}
+
+pub fn compile_with_options(
+    src: &str,
+    filename: &str,
+    options: CompileOptions,
+) -> Result<(Vec<u8>, DebugInfo), CompilationError> {
     let mut engine = DiagnosticEngine::new();
+    let result = compile_with_engine(src, filename, options, &mut engine);
+    engine.emit(src, filename);
+    result
+}
 
+/// Compile, collecting diagnostics into `engine` instead of printing them.
+pub fn compile_with_engine(
+    src: &str,
+    filename: &str,
+    options: CompileOptions,
+    engine: &mut DiagnosticEngine,
+) -> Result<(Vec<u8>, DebugInfo), CompilationError> {
     let mut lexer = Lexer::new(src.chars());
-    let mut ast = match parse_with_engine(&mut lexer, &mut engine) {
+    let mut ast = match parse_with_engine(&mut lexer, engine) {
         Some(ast) => ast,
         None => {
-            engine.emit(src, filename);
             return Err(CompilationError::ParseError(ParseError {
                 error: "Parsing failed with errors".to_string(),
                 location: (0, 0),
@@ -29,63 +65,35 @@ pub fn compile(src: &str, filename: &str) -> Result<Vec<u8>, CompilationError> {
 
     // let global_func = load_global();
 
-    let mut macro_registry = MacroRegistry::new();
-    match macro_registry.expand_expr_inplace(&mut ast) {
-        Ok(_) => {},
-        Err(_) => {
-            engine.report(crate::diagnostic::Diagnostic {
-                severity: crate::diagnostic::Severity::Error,
-                message: "Somthing wrong in macro expand".into(),
-                span: crate::diagnostic::Span { line: 1, column: 0 },
-                span_len: 1,
-                help: Some("Pray to god".into()),
-                notes: vec![],
-            });
-            engine.emit(src, filename);
-            return Err(CompilationError::MacroCheckingError);
-        }
-    };
+    if !expand_macros(&mut ast, engine) {
+        return Err(CompilationError::MacroCheckingError);
+    }
 
-    let mut bytecode_genaration = BytecodeGen::new();
+    let mut bytecode_genaration = BytecodeGen::new()
+          .with_peephole(options.opt_level != OptLevel::O0);
           // .with_global_func(global_func);
 
-    optimization::optimization(&mut ast);
+    if options.opt_level != OptLevel::O0
+        && let Err(err) = resolve_names(&ast)
+    {
+        report_bytecode_error(engine, &err);
+        return Err(CompilationError::BytecodeGenerationError(err));
+    }
+
+    optimization::optimize(&mut ast, options.opt_level);
 
     let byte = match bytecode_genaration.get_binary(ast) {
         Ok(byte) => byte,
         Err(err) => {
-            let (message, help) = match &err {
-                BytecodeGenerationError::UnexpectExpr { message } => {
-                    (format!("Unexpected expression: {}", message), None)
-                }
-                BytecodeGenerationError::NotFoundVariable { message } => {
-                    (format!("Variable '{}' not found in current scope", message),
-                     Some("Make sure the variable is defined before use".to_string()))
-                }
-                BytecodeGenerationError::NotFoundFunction {} => {
-                    ("Function not found".to_string(),
-                     Some("Check function name or import the function".to_string()))
-                }
-            };
-            engine.report(crate::diagnostic::Diagnostic {
-                severity: crate::diagnostic::Severity::Error,
-                message,
-                span: crate::diagnostic::Span { line: 1, column: 0 },
-                span_len: 1,
-                help,
-                notes: vec![],
-            });
-            engine.emit(src, filename);
+            report_bytecode_error(engine, &err);
             return Err(CompilationError::BytecodeGenerationError(err));
         }
     };
 
-    engine.emit(src, filename);
-
     if engine.has_error() {
         Err(CompilationError::UnexpectedError)
     } else {
-        Ok(byte)
+        Ok((byte, bytecode_genaration.debug_info(filename)))
     }
 }
 
@@ -107,7 +115,14 @@ pub fn compile_and_type_checking(src: &str, filename: &str) -> Result<Vec<u8>, C
     let global_func = load_global();
 
     let mut bytecode_genaration = BytecodeGen::new()
-          .with_global_func(global_func);
+          .with_global_func(global_func)
+          .with_peephole(true);
+
+    if let Err(err) = resolve_names(&ast) {
+        report_bytecode_error(&mut engine, &err);
+        engine.emit(src, filename);
+        return Err(CompilationError::BytecodeGenerationError(err));
+    }
 
     optimization::optimization(&mut ast);
 
@@ -116,27 +131,7 @@ pub fn compile_and_type_checking(src: &str, filename: &str) -> Result<Vec<u8>, C
     let byte = match bytecode_genaration.get_binary(ast) {
         Ok(byte) => byte,
         Err(err) => {
-            let (message, help) = match &err {
-                BytecodeGenerationError::UnexpectExpr { message } => {
-                    (format!("Unexpected expression: {}", message), None)
-                }
-                BytecodeGenerationError::NotFoundVariable { message } => {
-                    (format!("Variable '{}' not found in current scope", message),
-                     Some("Make sure the variable is defined before use".to_string()))
-                }
-                BytecodeGenerationError::NotFoundFunction {} => {
-                    ("Function not found".to_string(),
-                     Some("Check function name or import the function".to_string()))
-                }
-            };
-            engine.report(crate::diagnostic::Diagnostic {
-                severity: crate::diagnostic::Severity::Error,
-                message,
-                span: crate::diagnostic::Span { line: 1, column: 0 },
-                span_len: 1,
-                help,
-                notes: vec![],
-            });
+            report_bytecode_error(&mut engine, &err);
             engine.emit(src, filename);
             return Err(CompilationError::BytecodeGenerationError(err));
         }
@@ -150,3 +145,116 @@ pub fn compile_and_type_checking(src: &str, filename: &str) -> Result<Vec<u8>, C
         Ok(byte)
     }
 }
+
+/// Run every check `compile` does, plus the type checker, without producing
+/// bytecode. The returned engine holds all diagnostics, none are printed.
+pub fn check(src: &str) -> DiagnosticEngine {
+    let mut engine = DiagnosticEngine::new();
+
+    let mut lexer = Lexer::new(src.chars());
+    let Some(mut ast) = parse_with_engine(&mut lexer, &mut engine) else {
+        return engine;
+    };
+
+    if !expand_macros(&mut ast, &mut engine) {
+        return engine;
+    }
+
+    if let Err(err) = resolve_names(&ast) {
+        report_bytecode_error(&mut engine, &err);
+    }
+
+    optimization::optimization(&mut ast);
+
+    type_checking::type_checking(&ast, &mut engine);
+
+    engine
+}
+
+/// Resolve every name of the tree as written. Code generation reports unknown
+/// names, its output is dropped. Runs before optimizing, which can drop or
+/// substitute references, so `-O` never changes which programs compile.
+fn resolve_names(ast: &ast::Expr) -> Result<(), BytecodeGenerationError> {
+    BytecodeGen::new().get_binary(ast.clone()).map(|_| ())
+}
+
+/// Paths of the templates `src` renders with a literal path, through
+/// `htmlTemplate` or `render`, as written in the source.
+pub fn referenced_templates(src: &str) -> BTreeSet<String> {
+    let mut engine = DiagnosticEngine::new();
+    let mut templates = BTreeSet::new();
+
+    let mut lexer = Lexer::new(src.chars());
+    if let Some(mut ast) = parse_with_engine(&mut lexer, &mut engine)
+        && expand_macros(&mut ast, &mut engine)
+    {
+        collect_templates(&ast, &mut templates);
+    }
+
+    templates
+}
+
+fn collect_templates(expr: &ast::Expr, templates: &mut BTreeSet<String>) {
+    let literal = |expr: Option<&ast::Expr>| match expr.map(ast::Expr::unlocated) {
+        Some(ast::Expr::LiteralString(text)) => Some(text.clone()),
+        _ => None,
+    };
+
+    match expr.unlocated() {
+        ast::Expr::Table { fields }
+            if literal(fields.get("type")).as_deref() == Some("htmlTemplate") =>
+        {
+            templates.extend(literal(fields.get("path")));
+        }
+        ast::Expr::FunctionCall { callee, args }
+            if matches!(callee.unlocated(), ast::Expr::Var(name) if name == "render") =>
+        {
+            templates.extend(literal(args.get(1)));
+        }
+        _ => {}
+    }
+
+    optimization::for_each_child_ref(expr, &mut |child| collect_templates(child, templates));
+}
+
+fn expand_macros(ast: &mut ast::Expr, engine: &mut DiagnosticEngine) -> bool {
+    let mut macro_registry = MacroRegistry::new();
+    match macro_registry.expand_expr_inplace(ast) {
+        Ok(_) => true,
+        Err(_) => {
+            engine.report(crate::diagnostic::Diagnostic {
+                severity: crate::diagnostic::Severity::Error,
+                message: "Somthing wrong in macro expand".into(),
+                span: crate::diagnostic::Span { line: 1, column: 0 },
+                span_len: 1,
+                help: Some("Pray to god".into()),
+                notes: vec![],
+            });
+            false
+        }
+    }
+}
+
+fn report_bytecode_error(engine: &mut DiagnosticEngine, err: &BytecodeGenerationError) {
+    let (message, help) = match err {
+        BytecodeGenerationError::UnexpectExpr { message } => {
+            (format!("Unexpected expression: {}", message), None)
+        }
+        BytecodeGenerationError::NotFoundVariable { message } => {
+            (format!("Variable '{}' not found in current scope", message),
+             Some("Make sure the variable is defined before use".to_string()))
+        }
+        BytecodeGenerationError::NotFoundFunction {} => {
+            ("Function not found".to_string(),
+             Some("Check function name or import the function".to_string()))
+        }
+    };
+    engine.report(crate::diagnostic::Diagnostic {
+        severity: crate::diagnostic::Severity::Error,
+        message,
+        span: crate::diagnostic::Span { line: 1, column: 0 },
+        span_len: 1,
+        help,
+        notes: vec![],
+    });
+}
@@ -1,53 +1,577 @@
-use super::ast::{Expr, Operator};
+use std::collections::{HashMap, HashSet};
 
+use super::ast::{Expr, InterpolatedPart, Operator, Pattern};
+
+/// How much work `optimize` does, chosen with `-O` on `angi build`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Leave the tree as parsed, what `compile` does
+    #[default]
+    O0,
+    /// Constant folding and propagation, dead `let` binding removal and
+    /// the peephole pass over the emitted code, the CLI default
+    O1,
+    /// Everything in `O1` plus inlining of small functions
+    O2,
+}
+
+impl OptLevel {
+    /// Parse the level of `-O<level>`, e.g. `"2"`.
+    pub fn from_level(level: &str) -> Option<Self> {
+        match level {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+/// Most passes a single `optimize` call runs before giving up on a fixed point.
+const MAX_PASSES: usize = 4;
+
+/// Largest function body, in nodes, that `O2` inlines.
+const INLINE_LIMIT: usize = 24;
+
+/// Only fold literals, which the type checker relies on, e.g. to see that
+/// `port = 3000 + 30` is an int. Never drops a reference to a name.
 pub fn optimization(ast: &mut Expr) {
+    fold(ast);
+}
+
+pub fn optimize(ast: &mut Expr, level: OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+
+    // Each pass can expose work for the others, e.g. an inlined body folds
+    // to a literal that can then be propagated.
+    for _ in 0..MAX_PASSES {
+        let before = ast.clone();
+
+        fold(ast);
+        propagate(ast, &HashMap::new());
+        if level >= OptLevel::O2 {
+            inline(ast, &HashMap::new(), &HashSet::new());
+        }
+        eliminate_dead_bindings(ast);
+
+        if *ast == before {
+            break;
+        }
+    }
+}
+
+/// Fold literal operations bottom-up: arithmetic, concatenation,
+/// comparisons, `if` on a literal condition and literal interpolations.
+fn fold(ast: &mut Expr) {
+    for_each_child(ast, fold);
+
+    let folded = match ast {
+        // A literal can't fail, its location is no longer useful
+        Expr::Located { expr, .. } if is_literal(expr) => Some(expr.as_ref().clone()),
+        Expr::Binary { op, lhs, rhs } => fold_binary(*op, lhs.unlocated(), rhs.unlocated()),
+        Expr::Unary { op, rhs } => match (op, rhs.unlocated()) {
+            (Operator::Add, Expr::Number(num)) => Some(Expr::Number(*num)),
+            (Operator::Sub, Expr::Number(num)) => num.checked_neg().map(Expr::Number),
+            _ => None,
+        },
+        Expr::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => match cond.unlocated() {
+            Expr::Boolean(true) => Some(then_branch.as_ref().clone()),
+            Expr::Boolean(false) => Some(else_branch.as_ref().clone()),
+            _ => None,
+        },
+        Expr::InterpolatedString(parts) => fold_interpolation(parts),
+        _ => None,
+    };
+
+    if let Some(folded) = folded {
+        *ast = folded;
+    }
+}
+
+fn fold_binary(op: Operator, lhs: &Expr, rhs: &Expr) -> Option<Expr> {
+    match (lhs, rhs) {
+        (Expr::Number(a), Expr::Number(b)) => match op {
+            Operator::Add => a.checked_add(*b).map(Expr::Number),
+            Operator::Sub => a.checked_sub(*b).map(Expr::Number),
+            Operator::Mul => a.checked_mul(*b).map(Expr::Number),
+            // Leave division by zero for the VM to report
+            Operator::Div => a.checked_div(*b).map(Expr::Number),
+            _ => compare(op, a.cmp(b)),
+        },
+        (Expr::LiteralString(a), Expr::LiteralString(b)) => match op {
+            Operator::ConcatString => Some(Expr::LiteralString(format!("{a}{b}"))),
+            _ => compare(op, a.cmp(b)),
+        },
+        (Expr::Boolean(a), Expr::Boolean(b)) => match op {
+            Operator::Equal => Some(Expr::Boolean(a == b)),
+            Operator::NotEqual => Some(Expr::Boolean(a != b)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn compare(op: Operator, ordering: std::cmp::Ordering) -> Option<Expr> {
+    let result = match op {
+        Operator::Equal => ordering.is_eq(),
+        Operator::NotEqual => ordering.is_ne(),
+        Operator::Less => ordering.is_lt(),
+        Operator::LessEqual => ordering.is_le(),
+        Operator::Greater => ordering.is_gt(),
+        Operator::GreaterEqual => ordering.is_ge(),
+        _ => return None,
+    };
+    Some(Expr::Boolean(result))
+}
+
+/// Merge literal parts of an interpolation, or the whole of it when every
+/// part is literal.
+fn fold_interpolation(parts: &[InterpolatedPart]) -> Option<Expr> {
+    let mut folded: Vec<InterpolatedPart> = vec![];
+
+    for part in parts {
+        let text = match part {
+            InterpolatedPart::String(str) => Some(str.clone()),
+            InterpolatedPart::Expr(expr) => match expr.unlocated() {
+                Expr::LiteralString(str) => Some(str.clone()),
+                Expr::Number(num) => Some(num.to_string()),
+                _ => None,
+            },
+        };
+
+        match (text, folded.last_mut()) {
+            (Some(text), Some(InterpolatedPart::String(last))) => last.push_str(&text),
+            (Some(text), _) => folded.push(InterpolatedPart::String(text)),
+            (None, _) => folded.push(part.clone()),
+        }
+    }
+
+    match folded.as_slice() {
+        [] => Some(Expr::LiteralString(String::new())),
+        [InterpolatedPart::String(str)] => Some(Expr::LiteralString(str.clone())),
+        _ if folded.len() < parts.len() => Some(Expr::InterpolatedString(folded)),
+        _ => None,
+    }
+}
+
+/// Replace uses of `let` bound literals with the literal, and more generally
+/// free uses of the names in `env` with their value. Function bodies can't
+/// see the enclosing bindings, so they start from an empty environment.
+fn propagate(ast: &mut Expr, env: &HashMap<String, Expr>) {
     match ast {
-        Expr::Table { fields } => {
-            for field in fields.values_mut() {
-                optimization(field);
+        Expr::Var(name) => {
+            if let Some(value) = env.get(name) {
+                *ast = value.clone();
             }
         }
-        Expr::Binary { lhs, rhs, op } => {
-            optimization(lhs);
-            optimization(rhs);
-
-            match (&**lhs, op, &**rhs) {
-                (Expr::LiteralString(a), Operator::ConcatString, Expr::LiteralString(b)) => {
-                    *ast = Expr::LiteralString(format!("{a}{b}"));
+        Expr::LetIn { let_part, in_part } => {
+            let mut env = env.clone();
+            for (pattern, value) in let_part.iter_mut() {
+                propagate(value, &env);
+                for name in pattern_names(pattern) {
+                    env.remove(&name);
                 }
-                (Expr::Number(_), _, Expr::Number(_)) => {
-                    *ast = Expr::Number(calculate_expr(ast));
+                if let Pattern::Name(name) = pattern
+                    && is_literal(value)
+                {
+                    env.insert(name.clone(), value.clone());
                 }
-                _ => {}
             }
+            propagate(in_part, &env);
         }
-        _ => (),
+        Expr::FunctionDeclare { body, .. } => propagate(body, &HashMap::new()),
+        _ => for_each_child(ast, |child| propagate(child, env)),
     }
 }
 
-fn calculate_expr(ast: &Expr) -> i32 {
+/// Inline calls to small, non-recursive `let` bound functions. `functions`
+/// holds the inlinable functions in scope, `locals` every other local name.
+fn inline(ast: &mut Expr, functions: &HashMap<String, Expr>, locals: &HashSet<String>) {
     match ast {
-        Expr::Number(num) => *num,
-        Expr::Unary { op, rhs } => {
-            let rhs_num = calculate_expr(rhs);
-            match op {
-                Operator::Add => rhs_num,
-                Operator::Sub => -rhs_num,
-                _ => rhs_num,
-            }
-        }
-        Expr::Binary { op, lhs, rhs } => {
-            let lhs_num = calculate_expr(lhs);
-            let rhs_num = calculate_expr(rhs);
-            match op {
-                Operator::Add => lhs_num + rhs_num,
-                Operator::Sub => lhs_num - rhs_num,
-                Operator::Mul => lhs_num * rhs_num,
-                Operator::Div => lhs_num / rhs_num,
-                Operator::ConcatString => 0,
-                Operator::Pipe => 0,
-            }
-        }
-        _ => 0,
+        Expr::LetIn { let_part, in_part } => {
+            let mut functions = functions.clone();
+            let mut locals = locals.clone();
+            for (pattern, value) in let_part.iter_mut() {
+                inline(value, &functions, &locals);
+                for name in pattern_names(pattern) {
+                    functions.remove(&name);
+                    locals.insert(name);
+                }
+                if let Pattern::Name(name) = pattern
+                    && is_inlinable(name, value)
+                {
+                    functions.insert(name.clone(), value.clone());
+                }
+            }
+            inline(in_part, &functions, &locals);
+        }
+        Expr::FunctionDeclare { params, body } => {
+            let locals = params.iter().flat_map(pattern_names).collect();
+            inline(body, &HashMap::new(), &locals)
+        }
+        Expr::FunctionCall { callee, args } => {
+            inline(callee, functions, locals);
+            for arg in args.iter_mut() {
+                inline(arg, functions, locals);
+            }
+
+            let inlined = match callee.unlocated() {
+                Expr::Var(name) => functions
+                    .get(name)
+                    .and_then(|function| inline_call(function, args, locals)),
+                _ => None,
+            };
+            if let Some(inlined) = inlined {
+                *ast = inlined;
+            }
+        }
+        _ => for_each_child(ast, |child| inline(child, functions, locals)),
+    }
+}
+
+fn is_inlinable(name: &str, value: &Expr) -> bool {
+    match value.unlocated() {
+        Expr::FunctionDeclare { params, body } => {
+            params.iter().all(|param| param.name().is_some())
+                && size(body) <= INLINE_LIMIT
+                && !free_vars(value).contains(name)
+        }
+        _ => false,
+    }
+}
+
+/// The body of `function` with its parameters bound to `args`, if that
+/// keeps the meaning of the call.
+fn inline_call(function: &Expr, args: &[Expr], locals: &HashSet<String>) -> Option<Expr> {
+    let Expr::FunctionDeclare { params, body } = function.unlocated() else {
+        return None;
+    };
+    if params.len() != args.len() {
+        return None;
+    }
+
+    let params: Vec<String> = params
+        .iter()
+        .filter_map(|param| param.name().map(String::from))
+        .collect();
+
+    // Names the body uses from outside must not be shadowed at the call site
+    let body_free = free_vars(function);
+    if body_free.iter().any(|name| locals.contains(name)) {
+        return None;
+    }
+
+    // Arguments must not be captured by bindings inside the body
+    let body_bound = bound_names(body);
+    let args_free: HashSet<String> = args.iter().flat_map(free_vars).collect();
+    if args_free.iter().any(|name| body_bound.contains(name)) {
+        return None;
+    }
+
+    // Don't evaluate a non-trivial argument more than once, nor drop one
+    // that can fail
+    let uses = var_uses(body);
+    for (param, arg) in params.iter().zip(args) {
+        let trivial = is_literal(arg) || matches!(arg.unlocated(), Expr::Var(_));
+        let count = uses.get(param).copied().unwrap_or(0);
+        if (!trivial && count > 1) || (count == 0 && !is_pure(arg)) {
+            return None;
+        }
+    }
+
+    let env: HashMap<String, Expr> = params.into_iter().zip(args.iter().cloned()).collect();
+    let mut body = body.as_ref().clone();
+    propagate(&mut body, &env);
+    Some(body)
+}
+
+/// Drop `let` bindings that are never used and have no side effects.
+fn eliminate_dead_bindings(ast: &mut Expr) {
+    for_each_child(ast, eliminate_dead_bindings);
+
+    if let Expr::LetIn { let_part, in_part } = ast {
+        let mut idx = let_part.len();
+        while idx > 0 {
+            idx -= 1;
+            let (pattern, value) = &let_part[idx];
+            let Pattern::Name(name) = pattern else {
+                continue;
+            };
+
+            let rest = Expr::LetIn {
+                let_part: let_part[idx + 1..].to_vec(),
+                in_part: in_part.clone(),
+            };
+            if is_pure(value) && !free_vars(&rest).contains(name) {
+                let_part.remove(idx);
+            }
+        }
+
+        if let_part.is_empty() {
+            *ast = in_part.as_ref().clone();
+        }
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(
+        expr.unlocated(),
+        Expr::Number(_)
+            | Expr::LiteralString(_)
+            | Expr::LiteralStringMultiline(_)
+            | Expr::Boolean(_)
+    )
+}
+
+/// Evaluating `expr` can neither fail nor call into a foreign function, so
+/// dropping it can't change what the program does. Function bodies don't run
+/// until called.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::FunctionCall { .. } | Expr::Pipe { .. } => false,
+        // Missing fields, overflow, division by zero, type mismatches and
+        // values without a string form are runtime errors. Literal operands
+        // that could not fail were already folded.
+        Expr::AccessField { .. }
+        | Expr::Binary { .. }
+        | Expr::Unary { .. }
+        | Expr::If { .. }
+        | Expr::InterpolatedString(_) => false,
+        // Destructuring fails on a value of the wrong shape
+        Expr::LetIn { let_part, .. }
+            if let_part.iter().any(|(pattern, _)| !matches!(pattern, Pattern::Name(_))) =>
+        {
+            false
+        }
+        Expr::FunctionDeclare { .. } => true,
+        _ => {
+            let mut pure = true;
+            for_each_child_ref(expr, &mut |child| pure &= is_pure(child));
+            pure
+        }
+    }
+}
+
+fn size(expr: &Expr) -> usize {
+    let mut total = 1;
+    for_each_child_ref(expr, &mut |child| total += size(child));
+    total
+}
+
+fn pattern_names(pattern: &Pattern) -> Vec<String> {
+    match pattern {
+        Pattern::Name(name) => vec![name.clone()],
+        Pattern::Table(fields) => fields
+            .iter()
+            .flat_map(|(_, pattern, _)| pattern_names(pattern))
+            .collect(),
+        Pattern::List(items) => items
+            .iter()
+            .flat_map(|(pattern, _)| pattern_names(pattern))
+            .collect(),
+    }
+}
+
+fn pattern_defaults(pattern: &Pattern) -> Vec<&Expr> {
+    match pattern {
+        Pattern::Name(_) => vec![],
+        Pattern::Table(fields) => fields
+            .iter()
+            .flat_map(|(_, pattern, default)| default.iter().chain(pattern_defaults(pattern)))
+            .collect(),
+        Pattern::List(items) => items
+            .iter()
+            .flat_map(|(pattern, default)| default.iter().chain(pattern_defaults(pattern)))
+            .collect(),
+    }
+}
+
+/// Names used in `expr` that it doesn't bind itself.
+fn free_vars(expr: &Expr) -> HashSet<String> {
+    let mut free = HashSet::new();
+    match expr {
+        Expr::Var(name) => {
+            free.insert(name.clone());
+        }
+        Expr::LetIn { let_part, in_part } => {
+            let mut bound: HashSet<String> = HashSet::new();
+            for (pattern, value) in let_part {
+                let mut value_free = free_vars(value);
+                // A function can refer to the name it is bound to
+                if let (Pattern::Name(name), Expr::FunctionDeclare { .. }) =
+                    (pattern, value.unlocated())
+                {
+                    value_free.remove(name);
+                }
+                for default in pattern_defaults(pattern) {
+                    value_free.extend(free_vars(default));
+                }
+                free.extend(value_free.into_iter().filter(|name| !bound.contains(name)));
+                bound.extend(pattern_names(pattern));
+            }
+            free.extend(
+                free_vars(in_part)
+                    .into_iter()
+                    .filter(|name| !bound.contains(name)),
+            );
+        }
+        Expr::FunctionDeclare { params, body } => {
+            let bound: HashSet<String> = params.iter().flat_map(pattern_names).collect();
+            for default in params.iter().flat_map(pattern_defaults) {
+                free.extend(free_vars(default));
+            }
+            free.extend(
+                free_vars(body)
+                    .into_iter()
+                    .filter(|name| !bound.contains(name)),
+            );
+        }
+        _ => for_each_child_ref(expr, &mut |child| free.extend(free_vars(child))),
+    }
+    free
+}
+
+/// Every name bound anywhere inside `expr`, by `let` or parameters.
+fn bound_names(expr: &Expr) -> HashSet<String> {
+    let mut bound = HashSet::new();
+    match expr {
+        Expr::LetIn { let_part, .. } => {
+            bound.extend(
+                let_part
+                    .iter()
+                    .flat_map(|(pattern, _)| pattern_names(pattern)),
+            );
+        }
+        Expr::FunctionDeclare { params, .. } => {
+            bound.extend(params.iter().flat_map(pattern_names));
+        }
+        _ => {}
+    }
+    for_each_child_ref(expr, &mut |child| bound.extend(bound_names(child)));
+    bound
+}
+
+/// How many times each name is used, ignoring shadowing. Only used to
+/// bound duplication, so over-counting is harmless.
+fn var_uses(expr: &Expr) -> HashMap<String, usize> {
+    let mut uses = HashMap::new();
+    if let Expr::Var(name) = expr {
+        uses.insert(name.clone(), 1);
+    }
+    for_each_child_ref(expr, &mut |child| {
+        for (name, count) in var_uses(child) {
+            *uses.entry(name).or_insert(0) += count;
+        }
+    });
+    uses
+}
+
+fn for_each_child(expr: &mut Expr, mut visit: impl FnMut(&mut Expr)) {
+    match expr {
+        Expr::Unary { rhs, .. } => visit(rhs),
+        Expr::Binary { lhs, rhs, .. } | Expr::Pipe { lhs, rhs } => {
+            visit(lhs);
+            visit(rhs);
+        }
+        Expr::Table { fields } => fields.values_mut().for_each(visit),
+        Expr::List { items } => items.iter_mut().for_each(visit),
+        Expr::LetIn { let_part, in_part } => {
+            for (_, value) in let_part.iter_mut() {
+                visit(value);
+            }
+            visit(in_part);
+        }
+        Expr::FunctionDeclare { body, .. } => visit(body),
+        Expr::FunctionCall { callee, args } => {
+            visit(callee);
+            args.iter_mut().for_each(visit);
+        }
+        Expr::InterpolatedString(parts) => {
+            for part in parts.iter_mut() {
+                if let InterpolatedPart::Expr(expr) = part {
+                    visit(expr);
+                }
+            }
+        }
+        Expr::AccessField { parent, .. } | Expr::OptionalAccessField { parent, .. } => {
+            visit(parent)
+        }
+        Expr::WithDefault { expr, default } => {
+            visit(expr);
+            visit(default);
+        }
+        Expr::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visit(cond);
+            visit(then_branch);
+            visit(else_branch);
+        }
+        Expr::Located { expr, .. } => visit(expr),
+        Expr::Number(_)
+        | Expr::LiteralString(_)
+        | Expr::LiteralStringMultiline(_)
+        | Expr::Boolean(_)
+        | Expr::Var(_) => {}
+    }
+}
+
+pub(crate) fn for_each_child_ref(expr: &Expr, visit: &mut dyn FnMut(&Expr)) {
+    match expr {
+        Expr::Unary { rhs, .. } => visit(rhs),
+        Expr::Binary { lhs, rhs, .. } | Expr::Pipe { lhs, rhs } => {
+            visit(lhs);
+            visit(rhs);
+        }
+        Expr::Table { fields } => fields.values().for_each(visit),
+        Expr::List { items } => items.iter().for_each(visit),
+        Expr::LetIn { let_part, in_part } => {
+            for (_, value) in let_part {
+                visit(value);
+            }
+            visit(in_part);
+        }
+        Expr::FunctionDeclare { body, .. } => visit(body),
+        Expr::FunctionCall { callee, args } => {
+            visit(callee);
+            args.iter().for_each(visit);
+        }
+        Expr::InterpolatedString(parts) => {
+            for part in parts {
+                if let InterpolatedPart::Expr(expr) = part {
+                    visit(expr);
+                }
+            }
+        }
+        Expr::AccessField { parent, .. } | Expr::OptionalAccessField { parent, .. } => {
+            visit(parent)
+        }
+        Expr::WithDefault { expr, default } => {
+            visit(expr);
+            visit(default);
+        }
+        Expr::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visit(cond);
+            visit(then_branch);
+            visit(else_branch);
+        }
+        Expr::Located { expr, .. } => visit(expr),
+        Expr::Number(_)
+        | Expr::LiteralString(_)
+        | Expr::LiteralStringMultiline(_)
+        | Expr::Boolean(_)
+        | Expr::Var(_) => {}
     }
 }
@@ -1,14 +1,45 @@
-use super::ast::{Expr, Operator};
+use super::ast::{Expr, Operator, Pattern, SourceSpan};
 use super::error::ParseError;
-use super::lexer::Lexer;
+use super::lexer::{LexResult, Lexer};
 use super::token::Token;
 use crate::compiler::ast::InterpolatedPart;
 use crate::diagnostic::{Diagnostic, DiagnosticEngine, Severity, Span};
-use std::collections::HashMap;
-use std::iter::Peekable;
+use std::collections::{HashMap, VecDeque};
+
+/// The lexer with as much lookahead as the grammar needs.
+struct Tokens<'l, 'a> {
+    lexer: &'l mut Lexer<'a>,
+    peeked: VecDeque<LexResult>,
+}
+
+impl<'l, 'a> Tokens<'l, 'a> {
+    fn new(lexer: &'l mut Lexer<'a>) -> Self {
+        Self { lexer, peeked: VecDeque::new() }
+    }
+
+    fn peek(&mut self) -> Option<&LexResult> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&LexResult> {
+        while self.peeked.len() <= n {
+            let token = self.lexer.next()?;
+            self.peeked.push_back(token);
+        }
+        self.peeked.get(n)
+    }
+}
+
+impl Iterator for Tokens<'_, '_> {
+    type Item = LexResult;
+
+    fn next(&mut self) -> Option<LexResult> {
+        self.peeked.pop_front().or_else(|| self.lexer.next())
+    }
+}
 
 pub fn parse_with_engine(lex: &mut Lexer, engine: &mut DiagnosticEngine) -> Option<Expr> {
-    let mut lexer = lex.peekable();
+    let mut lexer = Tokens::new(lex);
     skip_new_line(&mut lexer);
     expr_with_bp(&mut lexer, engine, 0)
 }
@@ -16,7 +47,8 @@ pub fn parse_with_engine(lex: &mut Lexer, engine: &mut DiagnosticEngine) -> Opti
 pub fn parse(lex: &mut Lexer) -> Result<Expr, ParseError> {
     let mut engine = DiagnosticEngine::new();
     match parse_with_engine(lex, &mut engine) {
-        Some(ast) => Ok(ast),
+        // Locations only matter to code generation
+        Some(ast) => Ok(ast.strip_locations()),
         None => {
             if let Some(diag) = engine.diagnostics.first() {
                 Err(ParseError {
@@ -33,13 +65,41 @@ pub fn parse(lex: &mut Lexer) -> Result<Expr, ParseError> {
     }
 }
 
-fn skip_new_line(lexer: &mut Peekable<&mut Lexer>) {
+/// Whether the tokens after an opening `(` close it and continue with `=>`.
+fn starts_function(lexer: &mut Tokens) -> bool {
+    let mut depth = 0usize;
+    let mut n = 0;
+    loop {
+        match lexer.peek_nth(n) {
+            Some(Ok((_, Token::LeftParen | Token::LeftBrace | Token::LeftBracket, _))) => depth += 1,
+            Some(Ok((_, Token::RightBrace | Token::RightBracket, _))) => {
+                depth = depth.saturating_sub(1)
+            }
+            Some(Ok((_, Token::RightParen, _))) if depth == 0 => break,
+            Some(Ok((_, Token::RightParen, _))) => depth -= 1,
+            Some(Ok(_)) => {}
+            _ => return false,
+        }
+        n += 1;
+    }
+
+    loop {
+        n += 1;
+        match lexer.peek_nth(n) {
+            Some(Ok((_, Token::NewLine, _))) => {}
+            Some(Ok((_, Token::EqualRightArrow, _))) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn skip_new_line(lexer: &mut Tokens) {
     while let Some(Ok((_, Token::NewLine, _))) = lexer.peek() {
         lexer.next();
     }
 }
 
-fn sync(lexer: &mut Peekable<&mut Lexer>) {
+fn sync(lexer: &mut Tokens) {
     while let Some(Ok((_, tok, _))) = lexer.peek() {
         match tok {
             Token::Semicolon | Token::RightBrace | Token::RightBracket | Token::NewLine => {
@@ -52,7 +112,7 @@ fn sync(lexer: &mut Peekable<&mut Lexer>) {
     }
 }
 
-fn sync_until<F>(lexer: &mut Peekable<&mut Lexer>, predicate: F)
+fn sync_until<F>(lexer: &mut Tokens, predicate: F)
 where
     F: Fn(&Token) -> bool,
 {
@@ -79,7 +139,7 @@ fn report_error(engine: &mut DiagnosticEngine, line: u32, column: u32, message:
 }
 
 fn expr_with_bp(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
     min_pb: u8,
 ) -> Option<Expr> {
@@ -89,41 +149,47 @@ fn expr_with_bp(
         Some(Ok((_, Token::MultilineString(str), (_, _)))) => Expr::LiteralStringMultiline(str),
         Some(Ok((_, Token::False, (_, _)))) => Expr::Boolean(false),
         Some(Ok((_, Token::True, (_, _)))) => Expr::Boolean(true),
-        Some(Ok((_, Token::LeftParen, (_, _)))) => match lexer.peek() {
-            Some(Ok((_, Token::RightParen, (_, _)))) => {
-                lexer.next();
-                expr_function(lexer, engine, vec![])?
-            }
-            Some(Ok((_, Token::Name(_), (_, _)))) => {
-                let params = get_params_of_function(lexer, engine)?;
-                expr_function(lexer, engine, params)?
-            }
-            _ => {
-                let lhs = expr_with_bp(lexer, engine, 0)?;
-
-                match lexer.next() {
-                    Some(Ok((_, Token::RightParen, (_, _)))) => lhs,
-                    Some(Ok((line, tok, (col, _)))) => {
-                        report_error(
-                            engine,
-                            line,
-                            col,
-                            format!("Expected Token::RightParen, found {:?}", tok),
-                        );
-                        return None;
-                    }
-                    _ => {
-                        report_error(
-                            engine,
-                            0,
-                            0,
-                            "Expected Token::RightParen, found end of life".to_string(),
-                        );
-                        return None;
+        Some(Ok((_, Token::LeftParen, (_, _)))) => {
+            let is_function = starts_function(lexer);
+            match lexer.peek() {
+                Some(Ok((_, Token::RightParen, (_, _)))) => {
+                    lexer.next();
+                    expr_function(lexer, engine, vec![])?
+                }
+                // `(a, { b }) =>` is a parameter list, `({ b = 1; }).b` an expression
+                Some(Ok((_, Token::Name(_) | Token::LeftBrace | Token::LeftBracket, (_, _))))
+                    if is_function =>
+                {
+                    let params = get_params_of_function(lexer, engine)?;
+                    expr_function(lexer, engine, params)?
+                }
+                _ => {
+                    let lhs = expr_with_bp(lexer, engine, 0)?;
+
+                    match lexer.next() {
+                        Some(Ok((_, Token::RightParen, (_, _)))) => lhs,
+                        Some(Ok((line, tok, (col, _)))) => {
+                            report_error(
+                                engine,
+                                line,
+                                col,
+                                format!("Expected Token::RightParen, found {:?}", tok),
+                            );
+                            return None;
+                        }
+                        _ => {
+                            report_error(
+                                engine,
+                                0,
+                                0,
+                                "Expected Token::RightParen, found end of life".to_string(),
+                            );
+                            return None;
+                        }
                     }
                 }
             }
-        },
+        }
         Some(Ok((_, Token::Plus, (_, _)))) => {
             let ((), r_bp) = prefix_binding_power(Operator::Add);
             let rhs = expr_with_bp(lexer, engine, r_bp)?;
@@ -140,16 +206,17 @@ fn expr_with_bp(
                 rhs: Box::new(rhs),
             }
         }
-        Some(Ok((_, Token::Name(name), (_, _)))) => match lexer.peek() {
+        Some(Ok((line, Token::Name(name), (col, _)))) => match lexer.peek() {
             Some(Ok((_, Token::LeftParen, (_, _)))) => {
                 lexer.next();
-                expr_calle(lexer, engine, name)?
+                located(line, col, expr_calle(lexer, engine, Expr::Var(name))?)
             }
             _ => Expr::Var(name),
         },
         Some(Ok((_, Token::LeftBrace, (_, _)))) => expr_table(lexer, engine)?,
         Some(Ok((_, Token::LeftBracket, (_, _)))) => expr_list(lexer, engine)?,
         Some(Ok((_, Token::Let, (_, _)))) => expr_let_in(lexer, engine)?,
+        Some(Ok((_, Token::If, (_, _)))) => expr_if(lexer, engine)?,
         Some(Ok((_, Token::StringStart, (_, _)))) => expr_interpolated_str(lexer, engine)?,
         Some(Ok((line, tok, (col, _)))) => {
             report_error(
@@ -166,23 +233,46 @@ fn expr_with_bp(
         }
     };
 
-    // if lhs is (var1) then this check the following token it is => or not
-    //
+    // Calls and member accesses on any expression, e.g. `(f)(x)`, `f(x)(y)`
+    // and `a.b(c).d`. A call must start on the line of its callee.
+    loop {
+        if let Some(Ok((line, Token::LeftParen, (col, _)))) = lexer.peek() {
+            let (line, col) = (*line, *col);
+            lexer.next();
+            lhs = located(line, col, expr_calle(lexer, engine, lhs)?);
+            continue;
+        }
 
-    skip_new_line(lexer);
+        skip_new_line(lexer);
 
-    if let Some(Ok((_, Token::Dot, (_, _)))) = lexer.peek() {
-        lexer.next();
-        lhs = match expr_member_access(lexer, engine, lhs) {
-            Some(expr) => expr,
-            None => {
-                report_error(engine, 0, 0, "Somthing wrong in member access".to_string());
-                return None;
+        match lexer.peek() {
+            Some(Ok((_, Token::Dot | Token::QuestionDot, (_, _)))) => {
+                lhs = match expr_member_access(lexer, engine, lhs) {
+                    Some(expr) => expr,
+                    None => {
+                        report_error(engine, 0, 0, "Somthing wrong in member access".to_string());
+                        return None;
+                    }
+                }
             }
+            _ => break,
         }
-    };
+    }
+
+    if let Some(Ok((_, Token::Or, (_, _)))) = lexer.peek() {
+        lexer.next();
+        let default = expr_with_bp(lexer, engine, DEFAULT_BINDING_POWER)?;
+        lhs = Expr::WithDefault {
+            expr: Box::new(lhs),
+            default: Box::new(default),
+        };
+    }
 
     loop {
+        let (op_line, op_col) = match lexer.peek() {
+            Some(Ok((line, _, (col, _)))) => (*line, *col),
+            _ => (0, 0),
+        };
         let op = match lexer.peek() {
             Some(Ok((_, Token::EndOfFile, (_, _)))) => break,
             Some(Ok((_, Token::NewLine, (_, _)))) => break,
@@ -193,12 +283,19 @@ fn expr_with_bp(
             Some(Ok((_, Token::Comma, (_, _)))) => break,
             Some(Ok((_, Token::RightBracket, (_, _)))) => break,
             Some(Ok((_, Token::EqualRightArrow, (_, _)))) => break,
+            Some(Ok((_, Token::Then | Token::Else, (_, _)))) => break,
             Some(Ok((_, Token::Plus, (_, _)))) => Operator::Add,
             Some(Ok((_, Token::Dash, (_, _)))) => Operator::Sub,
             Some(Ok((_, Token::Star, (_, _)))) => Operator::Mul,
             Some(Ok((_, Token::Slash, (_, _)))) => Operator::Div,
             Some(Ok((_, Token::DoubleDot, (_, _)))) => Operator::ConcatString,
             Some(Ok((_, Token::Pipe, (_, _)))) => Operator::Pipe,
+            Some(Ok((_, Token::DoubleEqual, (_, _)))) => Operator::Equal,
+            Some(Ok((_, Token::NotEqual, (_, _)))) => Operator::NotEqual,
+            Some(Ok((_, Token::Less, (_, _)))) => Operator::Less,
+            Some(Ok((_, Token::LessEqual, (_, _)))) => Operator::LessEqual,
+            Some(Ok((_, Token::Greater, (_, _)))) => Operator::Greater,
+            Some(Ok((_, Token::GreaterEqual, (_, _)))) => Operator::GreaterEqual,
             Some(Ok((line, tok, (col, _)))) => {
                 report_error(
                     engine,
@@ -225,7 +322,7 @@ fn expr_with_bp(
         }
         lexer.next();
         let rhs = expr_with_bp(lexer, engine, r_pb)?;
-        lhs = match op {
+        let expr = match op {
             Operator::Pipe => build_pipe(engine, lhs, rhs)?,
             _ => Expr::Binary {
                 op,
@@ -233,11 +330,12 @@ fn expr_with_bp(
                 rhs: Box::new(rhs),
             },
         };
+        lhs = located(op_line, op_col, expr);
     }
     Some(lhs)
 }
 
-fn expr_table(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) -> Option<Expr> {
+fn expr_table(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Expr> {
     let mut attr_set = HashMap::new();
     skip_new_line(lexer);
     loop {
@@ -277,6 +375,10 @@ fn expr_table(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) -
 
         let name = match lexer.next() {
             Some(Ok((_, Token::Name(name), _))) => name,
+            // Keywords are fine as keys, like in member access `db.table`
+            Some(Ok((_, tok, _))) if tok.keyword_to_str().is_some() => {
+                tok.keyword_to_str().unwrap_or_default().to_string()
+            }
             Some(Ok((line, tok, (col, _)))) => {
                 report_error(
                     engine,
@@ -319,7 +421,7 @@ fn expr_table(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) -
     Some(Expr::Table { fields: attr_set })
 }
 
-fn expr_list(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) -> Option<Expr> {
+fn expr_list(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Expr> {
     let mut items = vec![];
     skip_new_line(lexer);
     loop {
@@ -363,13 +465,15 @@ fn expr_list(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) ->
 }
 
 fn get_params_of_function(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
-) -> Option<Vec<String>> {
+) -> Option<Vec<Pattern>> {
     let mut params = vec![];
     loop {
         match lexer.next() {
-            Some(Ok((_, Token::Name(name), _))) => params.push(name),
+            Some(Ok((_, Token::Name(name), _))) => params.push(Pattern::Name(name)),
+            Some(Ok((_, Token::LeftBrace, _))) => params.push(pattern_table(lexer, engine)?),
+            Some(Ok((_, Token::LeftBracket, _))) => params.push(pattern_list(lexer, engine)?),
             Some(Ok((_, Token::Comma, _))) => continue,
             Some(Ok((_, Token::RightParen, _))) => break,
             Some(Ok((line, tok, (col, _)))) => {
@@ -402,9 +506,9 @@ fn get_params_of_function(
 }
 
 fn expr_function(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
-    params: Vec<String>,
+    params: Vec<Pattern>,
 ) -> Option<Expr> {
     if !expect_token(lexer, engine, Token::EqualRightArrow) {
         sync(lexer);
@@ -427,14 +531,15 @@ fn expr_function(
 }
 
 fn expr_calle(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
-    calle_name: String,
+    callee: Expr,
 ) -> Option<Expr> {
+    let callee = Box::new(callee);
     if let Some(Ok((_, Token::RightParen, _))) = lexer.peek() {
         lexer.next();
         return Some(Expr::FunctionCall {
-            name: calle_name,
+            callee,
             args: vec![],
         });
     }
@@ -457,7 +562,7 @@ fn expr_calle(
             }
             lexer.next(); // consume ')'
             return Some(Expr::FunctionCall {
-                name: calle_name,
+                callee,
                 args,
             });
         }
@@ -505,13 +610,132 @@ fn expr_calle(
     }
 
     Some(Expr::FunctionCall {
-        name: calle_name,
+        callee,
         args,
     })
 }
 
-fn expr_let_in(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine) -> Option<Expr> {
-    let mut attr_set: HashMap<String, Expr> = HashMap::new();
+/// Parse the rest of `{ field, other: pattern, port ? 3000 }` after `{`.
+fn pattern_table(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Pattern> {
+    let mut fields = vec![];
+    loop {
+        skip_new_line(lexer);
+        let field = match lexer.next() {
+            Some(Ok((_, Token::RightBrace, _))) => break,
+            Some(Ok((_, Token::Name(name), _))) => name,
+            Some(Ok((line, tok, (col, _)))) => {
+                report_error(
+                    engine,
+                    line,
+                    col,
+                    format!("Expected field name in pattern, found {:?}", tok),
+                );
+                return None;
+            }
+            _ => {
+                report_error(engine, 0, 0, "Unexpected end of file in pattern".to_string());
+                return None;
+            }
+        };
+
+        let pattern = if let Some(Ok((_, Token::Colon, _))) = lexer.peek() {
+            lexer.next();
+            pattern_item(lexer, engine)?
+        } else {
+            Pattern::Name(field.clone())
+        };
+
+        let default = pattern_default(lexer, engine)?;
+        fields.push((field, pattern, default));
+
+        if !pattern_separator(lexer, engine, Token::RightBrace)? {
+            break;
+        }
+    }
+    Some(Pattern::Table(fields))
+}
+
+/// Parse the rest of `[first, second ? 0]` after `[`.
+fn pattern_list(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Pattern> {
+    let mut items = vec![];
+    loop {
+        skip_new_line(lexer);
+        if let Some(Ok((_, Token::RightBracket, _))) = lexer.peek() {
+            lexer.next();
+            break;
+        }
+
+        let pattern = pattern_item(lexer, engine)?;
+        let default = pattern_default(lexer, engine)?;
+        items.push((pattern, default));
+
+        if !pattern_separator(lexer, engine, Token::RightBracket)? {
+            break;
+        }
+    }
+    Some(Pattern::List(items))
+}
+
+fn pattern_item(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Pattern> {
+    match lexer.next() {
+        Some(Ok((_, Token::Name(name), _))) => Some(Pattern::Name(name)),
+        Some(Ok((_, Token::LeftBrace, _))) => pattern_table(lexer, engine),
+        Some(Ok((_, Token::LeftBracket, _))) => pattern_list(lexer, engine),
+        Some(Ok((line, tok, (col, _)))) => {
+            report_error(
+                engine,
+                line,
+                col,
+                format!("Expected pattern, found {:?}", tok),
+            );
+            None
+        }
+        _ => {
+            report_error(engine, 0, 0, "Unexpected end of file in pattern".to_string());
+            None
+        }
+    }
+}
+
+fn pattern_default(
+    lexer: &mut Tokens,
+    engine: &mut DiagnosticEngine,
+) -> Option<Option<Expr>> {
+    if let Some(Ok((_, Token::Question, _))) = lexer.peek() {
+        lexer.next();
+        return Some(Some(expr_with_bp(lexer, engine, 0)?));
+    }
+    Some(None)
+}
+
+/// Consume `,` or the closing token. Returns whether more items may follow.
+fn pattern_separator(
+    lexer: &mut Tokens,
+    engine: &mut DiagnosticEngine,
+    close: Token,
+) -> Option<bool> {
+    skip_new_line(lexer);
+    match lexer.next() {
+        Some(Ok((_, Token::Comma, _))) => Some(true),
+        Some(Ok((_, tok, _))) if tok == close => Some(false),
+        Some(Ok((line, tok, (col, _)))) => {
+            report_error(
+                engine,
+                line,
+                col,
+                format!("Expected ',' or {:?} in pattern, found {:?}", close, tok),
+            );
+            None
+        }
+        _ => {
+            report_error(engine, 0, 0, "Unexpected end of file in pattern".to_string());
+            None
+        }
+    }
+}
+
+fn expr_let_in(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Expr> {
+    let mut attr_set: Vec<(Pattern, Expr)> = vec![];
     skip_new_line(lexer);
     loop {
         if let Some(Ok((_, Token::In, (_, _)))) = lexer.peek() {
@@ -523,8 +747,10 @@ fn expr_let_in(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine)
             continue;
         }
 
-        let name = match lexer.next() {
-            Some(Ok((_, Token::Name(name), (_, _)))) => name,
+        let pattern = match lexer.next() {
+            Some(Ok((_, Token::Name(name), (_, _)))) => Pattern::Name(name),
+            Some(Ok((_, Token::LeftBrace, (_, _)))) => pattern_table(lexer, engine)?,
+            Some(Ok((_, Token::LeftBracket, (_, _)))) => pattern_list(lexer, engine)?,
             Some(Ok((line_of_code, _, (start_pos, _)))) => {
                 report_error(
                     engine,
@@ -560,7 +786,7 @@ fn expr_let_in(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine)
             _ => panic!("Unexpect Parser Err"),
         };
 
-        attr_set.insert(name, rhs);
+        attr_set.push((pattern, rhs));
     }
 
     skip_new_line(lexer);
@@ -572,8 +798,34 @@ fn expr_let_in(lexer: &mut Peekable<&mut Lexer>, engine: &mut DiagnosticEngine)
     })
 }
 
+fn expr_if(lexer: &mut Tokens, engine: &mut DiagnosticEngine) -> Option<Expr> {
+    let cond = expr_with_bp(lexer, engine, 0)?;
+
+    skip_new_line(lexer);
+    if !expect_token(lexer, engine, Token::Then) {
+        sync(lexer);
+        return None;
+    }
+    skip_new_line(lexer);
+    let then_branch = expr_with_bp(lexer, engine, 0)?;
+
+    skip_new_line(lexer);
+    if !expect_token(lexer, engine, Token::Else) {
+        sync(lexer);
+        return None;
+    }
+    skip_new_line(lexer);
+    let else_branch = expr_with_bp(lexer, engine, 0)?;
+
+    Some(Expr::If {
+        cond: Box::new(cond),
+        then_branch: Box::new(then_branch),
+        else_branch: Box::new(else_branch),
+    })
+}
+
 fn expr_interpolated_str(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
 ) -> Option<Expr> {
     let mut parts: Vec<InterpolatedPart> = vec![];
@@ -605,20 +857,28 @@ fn expr_interpolated_str(
     Some(Expr::InterpolatedString(parts))
 }
 
+/// Attach the source position a runtime error in `expr` should point at.
+fn located(line: u32, column: u32, expr: Expr) -> Expr {
+    Expr::Located {
+        span: SourceSpan { line, column },
+        expr: Box::new(expr),
+    }
+}
+
 fn build_pipe(engine: &mut DiagnosticEngine, lhs: Expr, rhs: Expr) -> Option<Expr> {
-    match rhs {
-        Expr::FunctionCall { name, mut args } => {
+    match rhs.into_unlocated() {
+        Expr::FunctionCall { callee, mut args } => {
             let mut new_args = vec![lhs];
             new_args.append(&mut args);
 
             Some(Expr::FunctionCall {
-                name,
+                callee,
                 args: new_args,
             })
         }
 
-        Expr::Var(name) => Some(Expr::FunctionCall {
-            name,
+        callee @ (Expr::Var(_) | Expr::AccessField { .. }) => Some(Expr::FunctionCall {
+            callee: Box::new(callee),
             args: vec![lhs],
         }),
 
@@ -630,42 +890,21 @@ fn build_pipe(engine: &mut DiagnosticEngine, lhs: Expr, rhs: Expr) -> Option<Exp
 }
 
 fn expr_member_access(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
     parent: Expr,
 ) -> Option<Expr> {
-    let mut lhs = match lexer.next() {
-        Some(Ok((_, Token::Name(member), _))) => Expr::AccessField {
-            parent: Box::new(parent),
-            child: member.clone(),
-        },
-        Some(Ok((line, tok, (col, _)))) => {
-            report_error(
-                engine,
-                line,
-                col,
-                format!("Expected access field name, found {:?}", tok),
-            );
-            return None;
-        }
-        _ => {
-            report_error(
-                engine,
-                0,
-                0,
-                "Expected access field name, found nothing".into(),
-            );
-            return None;
-        }
-    };
+    let mut lhs = parent;
 
-    while let Some(Ok((_, Token::Dot, _))) = lexer.peek() {
+    while let Some(Ok((_, tok @ (Token::Dot | Token::QuestionDot), _))) = lexer.peek() {
+        let is_optional = *tok == Token::QuestionDot;
         lexer.next();
-        lhs = match lexer.next() {
-            Some(Ok((_, Token::Name(member), _))) => Expr::AccessField {
-                parent: Box::new(lhs),
-                child: member.clone(),
-            },
+        let (member, line, col) = match lexer.next() {
+            Some(Ok((line, Token::Name(member), (col, _)))) => (member, line, col),
+            // Keywords are fine as field names, e.g. `db.table("users")`
+            Some(Ok((line, tok, (col, _)))) if tok.keyword_to_str().is_some() => {
+                (tok.keyword_to_str().unwrap_or_default().to_string(), line, col)
+            }
             Some(Ok((line, tok, (col, _)))) => {
                 report_error(
                     engine,
@@ -685,13 +924,31 @@ fn expr_member_access(
                 return None;
             }
         };
+
+        lhs = located(line, col, if is_optional {
+            Expr::OptionalAccessField {
+                parent: Box::new(lhs),
+                child: member,
+            }
+        } else {
+            Expr::AccessField {
+                parent: Box::new(lhs),
+                child: member,
+            }
+        });
+
+        // `obj.method(args)` and `f(x).y(z)` chain calls onto the access
+        if let Some(Ok((_, Token::LeftParen, _))) = lexer.peek() {
+            lexer.next();
+            lhs = located(line, col, expr_calle(lexer, engine, lhs)?);
+        }
     }
 
     Some(lhs)
 }
 
 fn expect_token(
-    lexer: &mut Peekable<&mut Lexer>,
+    lexer: &mut Tokens,
     engine: &mut DiagnosticEngine,
     expected: Token,
 ) -> bool {
@@ -725,6 +982,9 @@ fn expect_token(
     }
 }
 
+/// `or` binds tighter than any infix operator: `a.b or 1 + 2` is `(a.b or 1) + 2`.
+const DEFAULT_BINDING_POWER: u8 = 9;
+
 fn prefix_binding_power(op: Operator) -> ((), u8) {
     match op {
         Operator::Add | Operator::Sub => ((), 9),
@@ -736,7 +996,13 @@ fn infix_binding_power(op: Operator) -> (u8, u8) {
     match op {
         Operator::Pipe => (0, 1),
         Operator::ConcatString => (0, 1),
-        Operator::Add | Operator::Sub => (1, 2),
-        Operator::Mul | Operator::Div => (3, 4),
+        Operator::Equal
+        | Operator::NotEqual
+        | Operator::Less
+        | Operator::LessEqual
+        | Operator::Greater
+        | Operator::GreaterEqual => (1, 2),
+        Operator::Add | Operator::Sub => (3, 4),
+        Operator::Mul | Operator::Div => (5, 6),
     }
 }
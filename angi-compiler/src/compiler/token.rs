@@ -9,10 +9,13 @@ pub enum Token {
     Equal,           // =
     Semicolon,       // ;
     Comma,           // ,
+    Colon,           // :
+    Question,        // ?
     RightArrow,      // ->
     EqualRightArrow, // =>
     Bar,             // |
     Dot,             // .
+    QuestionDot,     // ?.
     DoubleDot,       // ..
     Dolar,           // $
     // Operator
@@ -22,6 +25,12 @@ pub enum Token {
     Slash,           // /
     Percent,         // %
     Pipe,            // |>
+    DoubleEqual,     // ==
+    NotEqual,        // !=
+    Less,            // <
+    LessEqual,       // <=
+    Greater,         // >
+    GreaterEqual,    // >=
     Bind,            // >>=
 
     Name(String),
@@ -38,6 +47,10 @@ pub enum Token {
     // Preserve keyword
     Let,
     In,
+    Or,
+    If,
+    Then,
+    Else,
     True,
     False,
     EnumDeclare,
@@ -53,6 +66,10 @@ impl Token {
         match name {
             "let"   => Some(Token::Let),
             "in"    => Some(Token::In),
+            "or"    => Some(Token::Or),
+            "if"    => Some(Token::If),
+            "then"  => Some(Token::Then),
+            "else"  => Some(Token::Else),
             "true"  => Some(Token::True),
             "false" => Some(Token::False),
             "enum" => Some(Token::EnumDeclare),
@@ -61,14 +78,73 @@ impl Token {
         }
     }
 
+    /// Source spelling of a keyword token, used where keywords are
+    /// allowed as plain names (e.g. `db.table(..)`).
+    pub fn keyword_to_str(&self) -> Option<&'static str> {
+        match self {
+            Token::Let          => Some("let"),
+            Token::In           => Some("in"),
+            Token::Or           => Some("or"),
+            Token::If           => Some("if"),
+            Token::Then         => Some("then"),
+            Token::Else         => Some("else"),
+            Token::True         => Some("true"),
+            Token::False        => Some("false"),
+            Token::EnumDeclare  => Some("enum"),
+            Token::TableDeclare => Some("table"),
+            _ => None
+        }
+    }
+
     pub fn is_prefix_token(&self) -> bool {
         matches!(self, Token::Plus | Token::Dash)
     }
 
-    pub fn to_str_symbol(&self) -> &str {
-        match self {
-            Token::Semicolon => ";",
-            _ => todo!()
-        }
+    /// How the token reads in source, for error messages.
+    pub fn to_str_symbol(&self) -> String {
+        let symbol = match self {
+            Token::LeftBrace       => "{",
+            Token::RightBrace      => "}",
+            Token::LeftBracket     => "[",
+            Token::RightBracket    => "]",
+            Token::LeftParen       => "(",
+            Token::RightParen      => ")",
+            Token::Equal           => "=",
+            Token::Semicolon       => ";",
+            Token::Comma           => ",",
+            Token::Colon           => ":",
+            Token::Question        => "?",
+            Token::RightArrow      => "->",
+            Token::EqualRightArrow => "=>",
+            Token::Bar             => "|",
+            Token::Dot             => ".",
+            Token::QuestionDot     => "?.",
+            Token::DoubleDot       => "..",
+            Token::Dolar           => "$",
+            Token::Plus            => "+",
+            Token::Dash            => "-",
+            Token::Star            => "*",
+            Token::Slash           => "/",
+            Token::Percent         => "%",
+            Token::Pipe            => "|>",
+            Token::DoubleEqual     => "==",
+            Token::NotEqual        => "!=",
+            Token::Less            => "<",
+            Token::LessEqual       => "<=",
+            Token::Greater         => ">",
+            Token::GreaterEqual    => ">=",
+            Token::Bind            => ">>=",
+            Token::Name(name)      => return name.clone(),
+            Token::String(str) | Token::MultilineString(str) => return format!("{str:?}"),
+            Token::Number(num)     => return num.to_string(),
+            Token::StringStart | Token::StringEnd => "\"",
+            Token::InterpStart     => "${",
+            Token::InterpEnd       => "}",
+            Token::NewLine         => "end of line",
+            Token::EndOfFile       => "end of file",
+            Token::None            => "nothing",
+            keyword => keyword.keyword_to_str().unwrap_or_default(),
+        };
+        symbol.to_string()
     }
 }
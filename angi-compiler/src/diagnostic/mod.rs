@@ -1,4 +1,5 @@
 use ariadne::{Color,  ReportKind};
+use serde_json::{json, Value};
 use std::ops::Range;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,6 +18,14 @@ impl Severity {
         }
     }
 
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+
     fn get_color(&self) -> Color {
         match self {
             Severity::Error => Color::Red,
@@ -74,42 +83,96 @@ impl DiagnosticEngine {
             .any(|d| matches!(d.severity, Severity::Error))
     }
 
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+
+    /// All diagnostics as one JSON object, for editors and CI. Lines are
+    /// 1-based and columns 0-based, a line of 0 means the location is unknown.
+    pub fn to_json(&self, filename: &str) -> Value {
+        let diagnostics: Vec<Value> = self
+            .diagnostics
+            .iter()
+            .map(|d| {
+                json!({
+                    "severity": d.severity.as_str(),
+                    "message": d.message,
+                    "line": d.span.line,
+                    "column": d.span.column,
+                    "length": d.span_len,
+                    "help": d.help,
+                    "notes": d.notes,
+                })
+            })
+            .collect();
+
+        json!({
+            "file": filename,
+            "errors": self.count(Severity::Error),
+            "warnings": self.count(Severity::Warning),
+            "diagnostics": diagnostics,
+        })
+    }
+
     /// Emit all diagnostics using ariadne for beautiful output
     pub fn emit(&self, source: &str, filename: &str) {
-        if self.diagnostics.is_empty() {
-            return;
+        let line_starts = compute_line_starts(source);
+
+        for diagnostic in &self.diagnostics {
+            build_report(diagnostic, filename, &line_starts, true)
+                .print((filename, ariadne::Source::from(source)))
+                .unwrap();
         }
+    }
 
+    /// The same reports as [`DiagnosticEngine::emit`], uncolored, for showing
+    /// outside the terminal.
+    pub fn render(&self, source: &str, filename: &str) -> String {
         let line_starts = compute_line_starts(source);
+        let mut output = Vec::new();
 
         for diagnostic in &self.diagnostics {
-            let span_range = diagnostic.span.to_range(&line_starts, diagnostic.span_len);
-
-            let mut report = ariadne::Report::build(
-                diagnostic.severity.get_report_kind(),
-                (filename, span_range.clone()),
-            )
-            .with_message(&diagnostic.message)
-            .with_label(
-                ariadne::Label::new((filename, span_range.clone()))
-                    .with_message(&diagnostic.message)
-                    .with_color(diagnostic.severity.get_color()),
-            );
-
-            if let Some(help) = &diagnostic.help {
-                report = report.with_note(help);
-            }
-
-            for note in &diagnostic.notes {
-                report = report.with_note(note);
-            }
-
-            report
-                .finish()
-                .print((filename, ariadne::Source::from(source)))
+            build_report(diagnostic, filename, &line_starts, false)
+                .write((filename, ariadne::Source::from(source)), &mut output)
                 .unwrap();
         }
+
+        String::from_utf8_lossy(&output).into_owned()
+    }
+}
+
+fn build_report<'a>(
+    diagnostic: &'a Diagnostic,
+    filename: &'a str,
+    line_starts: &[usize],
+    color: bool,
+) -> ariadne::Report<'a, (&'a str, Range<usize>)> {
+    let span_range = diagnostic.span.to_range(line_starts, diagnostic.span_len);
+
+    let mut report = ariadne::Report::build(
+        diagnostic.severity.get_report_kind(),
+        (filename, span_range.clone()),
+    )
+    .with_config(ariadne::Config::default().with_color(color))
+    .with_message(&diagnostic.message)
+    .with_label(
+        ariadne::Label::new((filename, span_range.clone()))
+            .with_message(&diagnostic.message)
+            .with_color(diagnostic.severity.get_color()),
+    );
+
+    if let Some(help) = &diagnostic.help {
+        report = report.with_note(help);
     }
+
+    for note in &diagnostic.notes {
+        report = report.with_note(note);
+    }
+
+    report.finish()
 }
 
 impl Default for DiagnosticEngine {
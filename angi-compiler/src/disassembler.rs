@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write};
+
+use angi_ins::{
+    CONST_BOOL, CONST_FLOAT, CONST_INT, CONST_NONE, CONST_STRING, MAGIC_NUMBER, METADATA_BYTES,
+    OpCode, OperandWithNum, extract_opcode, version_string,
+};
+use angi_runtime::debug_info::DebugInfo;
+use angi_runtime::modules::get_default_foreign_function;
+use angi_utils::read_byte::{read_i64, read_str_with_len, read_u8, read_u32};
+
+#[derive(Debug, PartialEq)]
+pub struct DisassemblyError {
+    pub message: String,
+}
+
+impl fmt::Display for DisassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[DisassemblyError] {}", self.message)
+    }
+}
+
+fn error(message: impl Into<String>) -> DisassemblyError {
+    DisassemblyError { message: message.into() }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+    None,
+    Float(f64),
+}
+
+impl Const {
+    /// The value alone, as it appears in instruction comments.
+    fn value(&self) -> String {
+        match self {
+            Const::Int(int) => int.to_string(),
+            Const::Str(str) => format!("{str:?}"),
+            Const::Bool(bool) => bool.to_string(),
+            Const::None => "none".into(),
+            Const::Float(float) => format!("{float:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::Int(_) => write!(f, "int {}", self.value()),
+            Const::Str(_) => write!(f, "str {}", self.value()),
+            Const::Bool(_) => write!(f, "bool {}", self.value()),
+            Const::None => write!(f, "none"),
+            Const::Float(_) => write!(f, "float {}", self.value()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FunctionEntry {
+    pub nargs: u32,
+    /// Instruction index of the first instruction of the body.
+    pub ins: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalEntry {
+    /// Constant holding the global function name.
+    pub name: u32,
+    pub function: u32,
+}
+
+/// The sections of a bytecode image, decoded but not interpreted.
+/// Tables are stored in index order, so entry `i` has the 1-based index `i + 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub version: u32,
+    pub consts: Vec<Const>,
+    /// Instruction index of each thunk body.
+    pub thunks: Vec<u32>,
+    pub functions: Vec<FunctionEntry>,
+    pub globals: Vec<GlobalEntry>,
+    pub code: Vec<u32>,
+}
+
+impl Image {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DisassemblyError> {
+        let mut cursor = 0;
+        let mut header = [0u32; (METADATA_BYTES / 4) as usize];
+        for field in header.iter_mut() {
+            *field = read_u32(bytes, &mut cursor).ok_or_else(|| error("truncated header"))?;
+        }
+
+        let [magic, version, const_offset, const_size, thunk_offset, thunk_size, function_offset,
+            function_size, global_offset, global_size, code_offset, code_size] = header;
+
+        if magic != MAGIC_NUMBER {
+            return Err(error("not an angi bytecode image"));
+        }
+
+        let mut cursor = const_offset as usize;
+        let mut consts = vec![];
+        for idx in 1..=const_size {
+            let truncated = || error(format!("truncated constant #{idx}"));
+            match read_u8(bytes, &mut cursor).ok_or_else(truncated)? {
+                CONST_INT => {
+                    consts.push(Const::Int(read_i64(bytes, &mut cursor).ok_or_else(truncated)?))
+                }
+                CONST_STRING => {
+                    let len = read_u32(bytes, &mut cursor).ok_or_else(truncated)?;
+                    let str = read_str_with_len(bytes, &mut cursor, len as usize)
+                        .ok_or_else(truncated)?;
+                    consts.push(Const::Str(str));
+                }
+                CONST_BOOL => {
+                    consts.push(Const::Bool(read_u8(bytes, &mut cursor).ok_or_else(truncated)? != 0))
+                }
+                CONST_NONE => consts.push(Const::None),
+                CONST_FLOAT => {
+                    let bits = read_i64(bytes, &mut cursor).ok_or_else(truncated)?;
+                    consts.push(Const::Float(f64::from_bits(bits as u64)));
+                }
+                kind => return Err(error(format!("unknown type {kind} of constant #{idx}"))),
+            }
+        }
+
+        let read_words = |offset: u32, count: u32, section: &str| {
+            let mut cursor = offset as usize;
+            (0..count)
+                .map(|_| {
+                    read_u32(bytes, &mut cursor)
+                        .ok_or_else(|| error(format!("truncated {section} section")))
+                })
+                .collect::<Result<Vec<u32>, _>>()
+        };
+
+        let thunks = read_words(thunk_offset, thunk_size, "thunk")?;
+        let functions = read_words(function_offset, function_size * 2, "function")?
+            .chunks(2)
+            .map(|pair| FunctionEntry { nargs: pair[0], ins: pair[1] })
+            .collect();
+        let globals = read_words(global_offset, global_size * 2, "global function")?
+            .chunks(2)
+            .map(|pair| GlobalEntry { name: pair[0], function: pair[1] })
+            .collect();
+        let code = read_words(code_offset, code_size, "code")?;
+
+        Ok(Image { version, consts, thunks, functions, globals, code })
+    }
+
+    /// Encode the image with the sections laid out back to back, the same
+    /// way `BytecodeGen` writes them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut consts = vec![];
+        for constant in &self.consts {
+            match constant {
+                Const::Int(int) => {
+                    consts.push(CONST_INT);
+                    consts.extend_from_slice(&int.to_be_bytes());
+                }
+                Const::Str(str) => {
+                    consts.push(CONST_STRING);
+                    consts.extend_from_slice(&(str.len() as u32).to_be_bytes());
+                    consts.extend_from_slice(str.as_bytes());
+                }
+                Const::Bool(bool) => consts.extend_from_slice(&[CONST_BOOL, *bool as u8]),
+                Const::None => consts.push(CONST_NONE),
+                Const::Float(float) => {
+                    consts.push(CONST_FLOAT);
+                    consts.extend_from_slice(&float.to_bits().to_be_bytes());
+                }
+            }
+        }
+
+        let const_offset = METADATA_BYTES;
+        let thunk_offset = const_offset + consts.len() as u32;
+        let function_offset = thunk_offset + self.thunks.len() as u32 * 4;
+        let global_offset = function_offset + self.functions.len() as u32 * 8;
+        let code_offset = global_offset + self.globals.len() as u32 * 8;
+
+        let header = [
+            MAGIC_NUMBER,
+            self.version,
+            const_offset,
+            self.consts.len() as u32,
+            thunk_offset,
+            self.thunks.len() as u32,
+            function_offset,
+            self.functions.len() as u32,
+            global_offset,
+            self.globals.len() as u32,
+            code_offset,
+            self.code.len() as u32,
+        ];
+        let tables = self
+            .thunks
+            .iter()
+            .copied()
+            .chain(self.functions.iter().flat_map(|function| [function.nargs, function.ins]))
+            .chain(self.globals.iter().flat_map(|global| [global.name, global.function]))
+            .chain(self.code.iter().copied());
+
+        let mut bytes: Vec<u8> = header.into_iter().flat_map(u32::to_be_bytes).collect();
+        bytes.extend_from_slice(&consts);
+        bytes.extend(tables.flat_map(u32::to_be_bytes));
+
+        let total = bytes.len() as u32 + 4;
+        bytes.extend_from_slice(&total.to_be_bytes());
+        bytes
+    }
+
+    /// Render the image as `.avm` assembly, which `assembler::assemble`
+    /// reads back. With `debug_info`, thunks and functions are named after
+    /// the source bindings they were compiled from.
+    pub fn disassemble(&self, debug_info: Option<&DebugInfo>) -> String {
+        Disassembler::new(self, debug_info).render()
+    }
+}
+
+/// What starts at an instruction index, for labels and boundaries.
+enum Entry {
+    Thunk(usize),
+    Function(usize),
+}
+
+struct Disassembler<'a> {
+    image: &'a Image,
+    entries: BTreeMap<u32, Vec<Entry>>,
+    labels: BTreeSet<u32>,
+    names: HashMap<u32, String>,
+    foreign: HashMap<u32, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(image: &'a Image, debug_info: Option<&DebugInfo>) -> Self {
+        let mut entries: BTreeMap<u32, Vec<Entry>> = BTreeMap::new();
+        for (idx, ins) in image.thunks.iter().enumerate() {
+            entries.entry(*ins).or_default().push(Entry::Thunk(idx + 1));
+        }
+        for (idx, function) in image.functions.iter().enumerate() {
+            entries.entry(function.ins).or_default().push(Entry::Function(idx + 1));
+        }
+
+        let labels = entries
+            .keys()
+            .copied()
+            .chain(image.code.iter().filter_map(|word| jump_target(*word)))
+            .collect();
+
+        let mut names = HashMap::new();
+        if let Some(info) = debug_info {
+            for scope in &info.scopes {
+                names.entry(scope.start).or_insert_with(|| scope.name.clone());
+            }
+        }
+        for global in &image.globals {
+            let function = image.functions.get((global.function as usize).wrapping_sub(1));
+            if let (Some(function), Some(Const::Str(name))) =
+                (function, image.consts.get((global.name as usize).wrapping_sub(1)))
+            {
+                names.entry(function.ins).or_insert_with(|| name.clone());
+            }
+        }
+
+        let foreign = get_default_foreign_function()
+            .name_to_idx_map()
+            .into_iter()
+            .map(|(name, idx)| (idx, name))
+            .collect();
+
+        Disassembler { image, entries, labels, names, foreign }
+    }
+
+    fn render(&self) -> String {
+        let image = self.image;
+        let mut out = String::new();
+
+        let _ = writeln!(out, ".format {}", version_string(image.version));
+
+        let _ = writeln!(out, "\n.const {}", image.consts.len());
+        for (idx, constant) in image.consts.iter().enumerate() {
+            let _ = writeln!(out, "    #{:<5} {constant}", idx + 1);
+        }
+
+        let _ = writeln!(out, "\n.thunk {}", image.thunks.len());
+        for (idx, ins) in image.thunks.iter().enumerate() {
+            let _ = writeln!(out, "    %{:<5} {}{}", idx + 1, label(*ins), self.name_comment(*ins));
+        }
+
+        let _ = writeln!(out, "\n.function {}", image.functions.len());
+        for (idx, function) in image.functions.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "    @{:<5} {}, {}{}",
+                idx + 1,
+                label(function.ins),
+                function.nargs,
+                self.name_comment(function.ins)
+            );
+        }
+
+        let _ = writeln!(out, "\n.global {}", image.globals.len());
+        for global in &image.globals {
+            let _ = writeln!(
+                out,
+                "    #{:<5} @{}{}",
+                global.name,
+                global.function,
+                self.const_comment(global.name)
+            );
+        }
+
+        let _ = writeln!(out, "\n.code {}", image.code.len());
+        let _ = writeln!(out, "\n; ---- root");
+        for (ins, word) in image.code.iter().enumerate() {
+            let ins = ins as u32;
+            for entry in self.entries.get(&ins).into_iter().flatten() {
+                let (kind, idx) = match entry {
+                    Entry::Thunk(idx) => ("thunk", format!("%{idx}")),
+                    Entry::Function(idx) => ("function", format!("@{idx}")),
+                };
+                let name = self.names.get(&ins).map(String::as_str).unwrap_or("<anonymous>");
+                let _ = writeln!(out, "\n; ---- {kind} {idx} {name}");
+            }
+            if self.labels.contains(&ins) {
+                let _ = writeln!(out, "{}:", label(ins));
+            }
+            let _ = writeln!(out, "    {}", self.instruction(*word));
+        }
+
+        out
+    }
+
+    fn name_comment(&self, ins: u32) -> String {
+        self.names.get(&ins).map(|name| format!(" ; {name}")).unwrap_or_default()
+    }
+
+    fn const_comment(&self, idx: u32) -> String {
+        match self.image.consts.get((idx as usize).wrapping_sub(1)) {
+            Some(constant) => format!(" ; {}", constant.value()),
+            None => " ; <missing>".into(),
+        }
+    }
+
+    /// One instruction, e.g. `LOADCONST r1, #3 ; "Hello"`.
+    fn instruction(&self, word: u32) -> String {
+        let Some(opcode) = extract_opcode(word) else {
+            return format!(".word {word:#010x} ; unknown opcode");
+        };
+
+        let params = opcode.decode_with_num(word);
+        let mut comment = String::new();
+        let operands: Vec<String> = params
+            .iter()
+            .map(|param| match param {
+                OperandWithNum::RegAddr(reg) => format!("r{reg}"),
+                OperandWithNum::ConstIdx(idx) => match opcode {
+                    OpCode::LOADCONST => {
+                        comment = self.const_comment(*idx);
+                        format!("#{idx}")
+                    }
+                    OpCode::MAKETHUNK => {
+                        if let Some(ins) = self.image.thunks.get((*idx as usize).wrapping_sub(1)) {
+                            comment = self.name_comment(*ins);
+                        }
+                        format!("%{idx}")
+                    }
+                    OpCode::MAKEFUNC => {
+                        if let Some(function) =
+                            self.image.functions.get((*idx as usize).wrapping_sub(1))
+                        {
+                            comment = self.name_comment(function.ins);
+                        }
+                        format!("@{idx}")
+                    }
+                    OpCode::CFOREIGN => {
+                        if let Some(name) = self.foreign.get(idx) {
+                            comment = format!(" ; {name}");
+                        }
+                        format!("${idx}")
+                    }
+                    OpCode::JMP | OpCode::JMPSOME | OpCode::JMPIFNOT => label(*idx),
+                    _ => idx.to_string(),
+                },
+            })
+            .collect();
+
+        if operands.is_empty() {
+            format!("{}{comment}", opcode.name())
+        } else {
+            format!("{} {}{comment}", opcode.name(), operands.join(", "))
+        }
+    }
+}
+
+fn label(ins: u32) -> String {
+    format!("L{ins}")
+}
+
+fn jump_target(word: u32) -> Option<u32> {
+    match extract_opcode(word)? {
+        opcode @ (OpCode::JMP | OpCode::JMPSOME | OpCode::JMPIFNOT) => {
+            opcode.decode(word).last().copied()
+        }
+        _ => None,
+    }
+}
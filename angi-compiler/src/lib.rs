@@ -3,3 +3,5 @@ pub mod command;
 pub mod diagnostic;
 pub mod type_checking;
 pub mod macro_function;
+pub mod assembler;
+pub mod disassembler;
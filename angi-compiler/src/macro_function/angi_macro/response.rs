@@ -24,18 +24,21 @@ pub fn html_template(params: Vec<Expr>, ctx: &mut MacroContext) -> Result<Expr,
 
 #[allow(unused_variables)]
 pub fn html(params: Vec<Expr>, ctx: &mut MacroContext) -> Result<Expr, MacroError> {
-    if let Some(Expr::LiteralString(str)) = params.first() {
-        Ok(Expr::Table {
+    match params.first() {
+        Some(
+            expr @ (Expr::LiteralString(_)
+            | Expr::LiteralStringMultiline(_)
+            | Expr::InterpolatedString(_)),
+        ) => Ok(Expr::Table {
             fields: HashMap::from([
                 (
                     String::from("type"),
                     Expr::LiteralString(String::from("html")),
                 ),
-                (String::from("html"), Expr::LiteralString(str.clone())),
+                (String::from("html"), expr.clone()),
             ]),
-        })
-    } else {
-        Err(MacroError::MismatchParams)
+        }),
+        _ => Err(MacroError::MismatchParams),
     }
 }
 
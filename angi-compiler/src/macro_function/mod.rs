@@ -86,7 +86,7 @@ impl MacroRegistry {
                 let let_part = let_part
                     .into_iter()
                     .map(|(k, v)| Ok((k, self.expand_expr(v)?)))
-                    .collect::<Result<HashMap<_, _>, _>>()?;
+                    .collect::<Result<Vec<_>, _>>()?;
 
                 let in_part = Box::new(self.expand_expr(*in_part)?);
 
@@ -98,17 +98,21 @@ impl MacroRegistry {
                 Ok(Expr::FunctionDeclare { params, body })
             }
 
-            Expr::FunctionCall { name, args } => {
+            Expr::FunctionCall { callee, args } => {
                 let args = args
                     .into_iter()
                     .map(|e| self.expand_expr(e))
                     .collect::<Result<Vec<_>, _>>()?;
 
-                if let Some(f) = self.macros.get(&name) {
+                if let Expr::Var(name) = callee.as_ref()
+                    && let Some(f) = self.macros.get(name)
+                {
                     return f(args, &mut self.ctx);
                 }
 
-                Ok(Expr::FunctionCall { name, args })
+                let callee = Box::new(self.expand_expr(*callee)?);
+
+                Ok(Expr::FunctionCall { callee, args })
             }
 
             Expr::InterpolatedString(parts) => {
@@ -125,6 +129,34 @@ impl MacroRegistry {
                 Ok(Expr::InterpolatedString(parts))
             }
 
+            Expr::AccessField { parent, child } => {
+                let parent = Box::new(self.expand_expr(*parent)?);
+                Ok(Expr::AccessField { parent, child })
+            }
+
+            Expr::OptionalAccessField { parent, child } => {
+                let parent = Box::new(self.expand_expr(*parent)?);
+                Ok(Expr::OptionalAccessField { parent, child })
+            }
+
+            Expr::WithDefault { expr, default } => {
+                let expr = Box::new(self.expand_expr(*expr)?);
+                let default = Box::new(self.expand_expr(*default)?);
+                Ok(Expr::WithDefault { expr, default })
+            }
+
+            Expr::If { cond, then_branch, else_branch } => {
+                let cond = Box::new(self.expand_expr(*cond)?);
+                let then_branch = Box::new(self.expand_expr(*then_branch)?);
+                let else_branch = Box::new(self.expand_expr(*else_branch)?);
+                Ok(Expr::If { cond, then_branch, else_branch })
+            }
+
+            Expr::Located { span, expr } => {
+                let expr = Box::new(self.expand_expr(*expr)?);
+                Ok(Expr::Located { span, expr })
+            }
+
             other => Ok(other),
         }
     }
@@ -158,7 +190,7 @@ impl MacroRegistry {
             }
 
             Expr::LetIn { let_part, in_part } => {
-                for v in let_part.values_mut() {
+                for (_, v) in let_part.iter_mut() {
                     self.expand_expr_inplace(v)?;
                 }
                 self.expand_expr_inplace(in_part)?;
@@ -176,14 +208,41 @@ impl MacroRegistry {
                 }
             }
 
-            Expr::FunctionCall { name, args } => {
+            Expr::AccessField { parent, .. } | Expr::OptionalAccessField { parent, .. } => {
+                self.expand_expr_inplace(parent)?;
+            }
+
+            Expr::WithDefault { expr, default } => {
+                self.expand_expr_inplace(expr)?;
+                self.expand_expr_inplace(default)?;
+            }
+
+            Expr::If { cond, then_branch, else_branch } => {
+                self.expand_expr_inplace(cond)?;
+                self.expand_expr_inplace(then_branch)?;
+                self.expand_expr_inplace(else_branch)?;
+            }
+
+            Expr::Located { expr, .. } => {
+                self.expand_expr_inplace(expr)?;
+            }
+
+            Expr::FunctionCall { callee, args } => {
                 let args_into = args.clone();
 
                 for arg in args {
                     self.expand_expr_inplace(arg)?;
                 }
 
-                if let Some(f) = self.macros.get(name) {
+                let macro_fn = match callee.as_ref() {
+                    Expr::Var(name) => self.macros.get(name),
+                    _ => {
+                        self.expand_expr_inplace(callee)?;
+                        None
+                    }
+                };
+
+                if let Some(f) = macro_fn {
                     let new_expr = f(args_into, &mut self.ctx)?;
                     *expr = new_expr;
 
@@ -1,5 +1,7 @@
+use std::process::ExitCode;
+
 use angi::command::handle_command;
 
-fn main() {
-    handle_command();
+fn main() -> ExitCode {
+    handle_command()
 }
@@ -11,6 +11,7 @@ pub enum Type {
         params_type: Vec<Type>,
         return_type: Box<Type>
     },
+    Optional(Box<Type>),
     Any,
     TableDynamic,
     ListDynamic,
@@ -14,7 +14,7 @@ pub fn type_checking(expr: &Expr, diagnostic: &mut DiagnosticEngine) {
 fn infer(expr: &Expr) -> Type {
     match expr {
         Expr::Number(_) => Type::Number,
-        Expr::LiteralString(_) => Type::String,
+        Expr::LiteralString(_) | Expr::LiteralStringMultiline(_) | Expr::InterpolatedString(_) => Type::String,
         Expr::Table { fields: _ }  => Type::TableDynamic,
         Expr::List { items: _ }  => Type::ListDynamic,
         Expr::FunctionDeclare { params: _, body: _ } => Type::FunctionDynamic,
@@ -22,11 +22,13 @@ fn infer(expr: &Expr) -> Type {
         Expr::LetIn { let_part: _, in_part } => {
             infer(in_part)
         }
+        Expr::Located { expr, .. } => infer(expr),
         _ => Type::Unknown
     }
 }
 
 fn check(attribute_name: &str, expr: &Expr, expected: &Type, diagnostic: &mut DiagnosticEngine) {
+    let expr = expr.unlocated();
     match expected {
         Type::Number => {
             if !matches!(expr, Expr::Number(_)) {
@@ -34,7 +36,7 @@ fn check(attribute_name: &str, expr: &Expr, expected: &Type, diagnostic: &mut Di
             }
         }
         Type::String => {
-            if !matches!(expr, Expr::LiteralString(_)) {
+            if !matches!(expr, Expr::LiteralString(_) | Expr::LiteralStringMultiline(_) | Expr::InterpolatedString(_)) {
                 report_error(diagnostic, format!("The {} expect {:?}, but found {:?}", attribute_name, expected, infer(expr)));
             }
         }
@@ -50,11 +52,12 @@ fn check(attribute_name: &str, expr: &Expr, expected: &Type, diagnostic: &mut Di
 
             if let Expr::Table { fields: fields_in_expr } = expr {
                 for (name, type_check) in field_schema {
-                    match fields_in_expr.get(name) {
-                        Some(expr_from_expr) => {
+                    match (fields_in_expr.get(name), type_check) {
+                        (Some(expr_from_expr), _) => {
                             check(&format!("{}.{}", attribute_name, name), expr_from_expr, type_check, diagnostic);
                         },
-                        None => {
+                        (None, Type::Optional(_)) => {},
+                        (None, _) => {
                             report_error(diagnostic, format!("The {} attribute not found in {}", name, attribute_name));
                         }
                     }
@@ -74,7 +77,12 @@ fn check(attribute_name: &str, expr: &Expr, expected: &Type, diagnostic: &mut Di
             }
         }
         Type::Function { params_type: _, return_type: _ } => {
-            todo!()
+            if !matches!(infer(expr), Type::FunctionDynamic) {
+                report_error(diagnostic, format!("The {} expect {:?}, but found {:?}", attribute_name, "Function", infer(expr)));
+            }
+        }
+        Type::Optional(inner) => {
+            check(attribute_name, expr, inner, diagnostic);
         }
         _ => panic!("The {:?} is not support in type checking right now", expected)
     }
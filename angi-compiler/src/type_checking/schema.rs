@@ -7,6 +7,7 @@ pub fn get_root_schema() -> Type  {
         ("port".to_string(), Type::Number),
         ("routes".to_string(), Type::List(
             Box::new(Type::Table(HashMap::from([
+                ("method".to_string(), Type::Optional(Box::new(Type::String))),
                 ("path".to_string(), Type::String),
                 ("handler".to_string(), Type::Function {
                     params_type: vec![],
@@ -14,5 +15,21 @@ pub fn get_root_schema() -> Type  {
                 }),
             ]))),
         )),
+        ("static".to_string(), Type::Optional(Box::new(
+            Type::Table(HashMap::from([
+                ("prefix".to_string(), Type::Optional(Box::new(Type::String))),
+                ("dir".to_string(), Type::String),
+            ])),
+        ))),
+        ("server".to_string(), Type::Optional(Box::new(
+            Type::Table(HashMap::from([
+                ("pool_size".to_string(), Type::Optional(Box::new(Type::Number))),
+                ("max_instructions".to_string(), Type::Optional(Box::new(Type::Number))),
+                ("timeout_ms".to_string(), Type::Optional(Box::new(Type::Number))),
+                ("max_memory".to_string(), Type::Optional(Box::new(Type::Number))),
+                ("max_value_len".to_string(), Type::Optional(Box::new(Type::Number))),
+                ("max_body_bytes".to_string(), Type::Optional(Box::new(Type::Number))),
+            ])),
+        ))),
     ]))
 }
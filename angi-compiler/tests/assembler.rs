@@ -0,0 +1,120 @@
+use angi::assembler::assemble;
+use angi::compiler::compile_with_debug_info;
+use angi::disassembler::Image;
+use angi_runtime::value::Function;
+use angi_runtime::vm::VM;
+
+#[test]
+fn assembler_test_round_trip_compiled_program() {
+    let (bytecode, debug_info) = compile_with_debug_info(r#"
+{
+    port = 3030;
+    pick = (flag) => if flag then "yes; \"quoted\"\n" else "no";
+    routes = [ { path = "/"; handler = () => html("<h1>Hi</h1>"); } ];
+}
+    "#, "test.ag").unwrap();
+
+    let image = Image::from_bytes(&bytecode).unwrap();
+    let text = image.disassemble(Some(&debug_info));
+
+    let assembled = assemble(&text).unwrap();
+    assert_eq!(assembled, image);
+    assert_eq!(assembled.to_bytes(), bytecode);
+}
+
+#[test]
+fn assembler_test_hand_written_program() {
+    let image = assemble(r#"
+.format 0.1
+.const
+    #1 str "answer"
+    #2 int 40
+    #3 int 2
+    #4 str "double"
+.function
+    @1 double, 1
+.code
+    MAKETABLE r0
+    LOADCONST r1, #1
+    LOADCONST r2, #2
+    LOADCONST r3, #3
+    ADD r2, r2, r3
+    SETATTR r0, r1, r2
+    LOADCONST r1, #4
+    MAKEFUNC r2, @1
+    SETATTR r0, r1, r2
+    RETURN r0
+
+double:                     ; (n) => n + n
+    LOADARG r0
+    ADD r1, r0, r0
+    RETURN r1
+    "#).unwrap();
+
+    let mut vm = VM::new_from_bytes(image.to_bytes()).unwrap();
+
+    assert_eq!(vm.eval::<i64>("answer").unwrap(), 42);
+
+    let double = vm.eval::<Function>("double").unwrap();
+    let result: i64 = double.call(&mut vm, (21_i64,)).unwrap();
+    assert_eq!(result, 42);
+}
+
+#[test]
+fn assembler_test_typed_constants() {
+    let image = assemble(r#"
+.const
+    #1 str "half"
+    #2 float 0.5
+    #3 str "sum"
+    #4 int 2
+    #5 str "flag"
+    #6 bool true
+    #7 str "nothing"
+    #8 none
+.code
+    MAKETABLE r0
+    LOADCONST r1, #1
+    LOADCONST r2, #2
+    SETATTR r0, r1, r2
+    LOADCONST r1, #3
+    LOADCONST r3, #4
+    ADD r2, r2, r3
+    SETATTR r0, r1, r2
+    LOADCONST r1, #5
+    LOADCONST r2, #6
+    SETATTR r0, r1, r2
+    LOADCONST r1, #7
+    LOADCONST r2, #8
+    SETATTR r0, r1, r2
+    RETURN r0
+    "#).unwrap();
+
+    let text = image.disassemble(None);
+    assert!(text.contains("    #2     float 0.5\n"));
+    assert!(text.contains("    #8     none\n"));
+    assert!(text.contains("LOADCONST r2, #6 ; true"));
+    assert_eq!(assemble(&text).unwrap(), image);
+
+    let mut vm = VM::new_from_bytes(image.to_bytes()).unwrap();
+
+    assert_eq!(vm.eval::<f64>("half").unwrap(), 0.5);
+    assert_eq!(vm.eval::<f64>("sum").unwrap(), 2.5);
+    assert!(vm.eval::<bool>("flag").unwrap());
+    let nothing = vm.eval::<bool>("nothing").unwrap_err();
+    assert!(nothing.to_string().contains("None"), "{nothing}");
+}
+
+#[test]
+fn assembler_test_reports_errors_with_line() {
+    let error = |src: &str| assemble(src).unwrap_err();
+
+    assert_eq!(error(".code\n    NOPE r0\n").line, 2);
+    assert!(error(".code\n    NOPE r0\n").message.contains("unknown opcode NOPE"));
+    assert!(error(".code\n    RETURN r16\n").message.contains("register r16"));
+    assert!(error(".code\n    MAKETABLE r0, r1\n").message.contains("takes 1 operands"));
+    assert!(error(".code\n    JMP nowhere\n").message.contains("unknown label nowhere"));
+    assert!(error(".const\n    #2 int 1\n").message.contains("expected entry #1"));
+    assert!(error(".const\n    #1 str \"open\n").message.contains("quoted string"));
+    assert_eq!(error(".const\n    #1 int 1\n.bogus\n").line, 3);
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use angi::compiler::ast::{Expr, Operator};
+use angi::compiler::ast::{Expr, Operator, Pattern};
 use angi::compiler::lexer::Lexer;
 use angi::compiler::parser::parse;
 
@@ -303,7 +303,7 @@ fn ast_test_function_declare_with_one_param() {
             (
                 String::from("port"),
                 Expr::FunctionDeclare {
-                    params: vec![String::from("name")],
+                    params: vec![Pattern::Name(String::from("name"))],
                     body: Box::new(Expr::Binary {
                         op: Operator::Add,
                         lhs: Box::new(Expr::LiteralString("Hello".into())),
@@ -330,9 +330,9 @@ fn ast_test_function_declare_more_param() {
                 String::from("port"),
                 Expr::FunctionDeclare {
                     params: vec![
-                        String::from("name"),
-                        String::from("age"),
-                        String::from("address")
+                        Pattern::Name(String::from("name")),
+                        Pattern::Name(String::from("age")),
+                        Pattern::Name(String::from("address"))
                     ],
                     body: Box::new(Expr::LiteralString("Hello".into()))
                 },
@@ -351,3 +351,200 @@ fn ast_test_function_declare_more_param() {
 //         rhs: Box::new(Expr::FunctionCall { name: "handle".into(), args: vec![] }),
 //     }));
 // }
+
+#[test]
+fn ast_test_optional_access_field() {
+    let mut lex = Lexer::new_from_str("req?.query.name\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::AccessField {
+        parent: Box::new(Expr::OptionalAccessField {
+            parent: Box::new(Expr::Var("req".into())),
+            child: "query".into(),
+        }),
+        child: "name".into(),
+    }));
+}
+
+#[test]
+fn ast_test_access_field_with_default() {
+    let mut lex = Lexer::new_from_str("config.port or 3000 + 1\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::Binary {
+        op: Operator::Add,
+        lhs: Box::new(Expr::WithDefault {
+            expr: Box::new(Expr::AccessField {
+                parent: Box::new(Expr::Var("config".into())),
+                child: "port".into(),
+            }),
+            default: Box::new(Expr::Number(3000)),
+        }),
+        rhs: Box::new(Expr::Number(1)),
+    }));
+}
+
+#[test]
+fn ast_test_chained_method_call() {
+    let mut lex = Lexer::new_from_str("db.table(\"x\").insert(data)\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::FunctionCall {
+        callee: Box::new(Expr::AccessField {
+            parent: Box::new(Expr::FunctionCall {
+                callee: Box::new(Expr::AccessField {
+                    parent: Box::new(Expr::Var("db".into())),
+                    child: "table".into(),
+                }),
+                args: vec![Expr::LiteralString("x".into())],
+            }),
+            child: "insert".into(),
+        }),
+        args: vec![Expr::Var("data".into())],
+    }));
+}
+
+#[test]
+fn ast_test_member_access_on_call() {
+    let mut lex = Lexer::new_from_str("f(x).y\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::AccessField {
+        parent: Box::new(Expr::FunctionCall {
+            callee: Box::new(Expr::Var("f".into())),
+            args: vec![Expr::Var("x".into())],
+        }),
+        child: "y".into(),
+    }));
+}
+
+#[test]
+fn ast_test_function_declare_destructuring_param() {
+    let mut lex = Lexer::new_from_str("({ data, port ? 3000, user: { name } }, [first]) => data\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::FunctionDeclare {
+        params: vec![
+            Pattern::Table(vec![
+                ("data".into(), Pattern::Name("data".into()), None),
+                ("port".into(), Pattern::Name("port".into()), Some(Expr::Number(3000))),
+                ("user".into(), Pattern::Table(vec![
+                    ("name".into(), Pattern::Name("name".into()), None),
+                ]), None),
+            ]),
+            Pattern::List(vec![(Pattern::Name("first".into()), None)]),
+        ],
+        body: Box::new(Expr::Var("data".into())),
+    }));
+}
+
+#[test]
+fn ast_test_parenthesized_table_and_list_are_expressions() {
+    let mut lex = Lexer::new_from_str("({ b = 1; }).b\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::AccessField {
+        parent: Box::new(Expr::Table {
+            fields: HashMap::from([("b".into(), Expr::Number(1))]),
+        }),
+        child: "b".into(),
+    }));
+
+    let mut lex = Lexer::new_from_str("([1, 2])\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::List {
+        items: vec![Expr::Number(1), Expr::Number(2)],
+    }));
+
+    let mut lex = Lexer::new_from_str("{ a = ({ b = 1; }).b; c = ([1, 2]); }\n");
+    assert!(parse(&mut lex).is_ok());
+}
+
+#[test]
+fn ast_test_let_destructuring() {
+    let mut lex = Lexer::new_from_str("let { a, b ? 2 } = t; [x] = l; in a\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::LetIn {
+        let_part: vec![
+            (
+                Pattern::Table(vec![
+                    ("a".into(), Pattern::Name("a".into()), None),
+                    ("b".into(), Pattern::Name("b".into()), Some(Expr::Number(2))),
+                ]),
+                Expr::Var("t".into()),
+            ),
+            (Pattern::List(vec![(Pattern::Name("x".into()), None)]), Expr::Var("l".into())),
+        ],
+        in_part: Box::new(Expr::Var("a".into())),
+    }));
+}
+
+#[test]
+fn ast_test_if_then_else() {
+    let mut lex = Lexer::new_from_str("if n <= 1 then 1 else n * 2\n");
+    let expr = parse(&mut lex);
+    assert_eq!(expr, Ok(Expr::If {
+        cond: Box::new(Expr::Binary {
+            op: Operator::LessEqual,
+            lhs: Box::new(Expr::Var("n".into())),
+            rhs: Box::new(Expr::Number(1)),
+        }),
+        then_branch: Box::new(Expr::Number(1)),
+        else_branch: Box::new(Expr::Binary {
+            op: Operator::Mul,
+            lhs: Box::new(Expr::Var("n".into())),
+            rhs: Box::new(Expr::Number(2)),
+        }),
+    }));
+}
+
+#[test]
+fn ast_test_calls_on_any_expression() {
+    let call = |callee: Expr, arg: &str| Expr::FunctionCall {
+        callee: Box::new(callee),
+        args: vec![Expr::Var(arg.into())],
+    };
+
+    let mut lex = Lexer::new_from_str("(f)(x)\n");
+    assert_eq!(parse(&mut lex), Ok(call(Expr::Var("f".into()), "x")));
+
+    let mut lex = Lexer::new_from_str("f(x)(y)\n");
+    assert_eq!(parse(&mut lex), Ok(call(call(Expr::Var("f".into()), "x"), "y")));
+
+    let mut lex = Lexer::new_from_str("a.b(x)(y).c\n");
+    assert_eq!(parse(&mut lex), Ok(Expr::AccessField {
+        parent: Box::new(call(
+            call(
+                Expr::AccessField {
+                    parent: Box::new(Expr::Var("a".into())),
+                    child: "b".into(),
+                },
+                "x",
+            ),
+            "y",
+        )),
+        child: "c".into(),
+    }));
+}
+
+#[test]
+fn ast_test_keywords_as_table_keys() {
+    let mut lex = Lexer::new_from_str("{ table = 1; if = 2; }.table\n");
+    assert_eq!(parse(&mut lex), Ok(Expr::AccessField {
+        parent: Box::new(Expr::Table {
+            fields: HashMap::from([
+                ("table".into(), Expr::Number(1)),
+                ("if".into(), Expr::Number(2)),
+            ]),
+        }),
+        child: "table".into(),
+    }));
+}
+
+#[test]
+fn ast_test_or_as_key() {
+    let mut lex = Lexer::new_from_str("{ or = 1; }.or or 2\n");
+    assert_eq!(parse(&mut lex), Ok(Expr::WithDefault {
+        expr: Box::new(Expr::AccessField {
+            parent: Box::new(Expr::Table {
+                fields: HashMap::from([("or".into(), Expr::Number(1))]),
+            }),
+            child: "or".into(),
+        }),
+        default: Box::new(Expr::Number(2)),
+    }));
+}
@@ -0,0 +1,137 @@
+use angi::compiler::{check, compile_with_engine, referenced_templates, CompileOptions};
+use angi::diagnostic::{DiagnosticEngine, Severity};
+
+#[test]
+fn check_test_accepts_valid_source() {
+    let engine = check(r#"
+{
+    port = 3000 + 30;
+    routes = [
+        {
+            path = "/";
+            handler = (req) => "Hello";
+        }
+    ];
+}
+    "#);
+
+    assert!(engine.diagnostics.is_empty());
+}
+
+#[test]
+fn check_test_reports_type_and_name_errors() {
+    let engine = check(r#"
+{
+    port = "3000";
+    routes = [
+        {
+            path = "/";
+            handler = (req) => missing;
+        }
+    ];
+}
+    "#);
+
+    assert_eq!(engine.count(Severity::Error), 2);
+    let messages: Vec<&str> = engine.diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert!(messages.iter().any(|m| m.contains("<root>.port expect Number")));
+    assert!(messages.iter().any(|m| m.contains("Variable 'missing' not found")));
+}
+
+#[test]
+fn check_test_reports_parse_errors() {
+    let engine = check("{ port = ; }\n");
+
+    assert!(engine.has_error());
+}
+
+#[test]
+fn check_test_json_output() {
+    let engine = check("{ port = \"3000\"; routes = []; }\n");
+    let json = engine.to_json("app.ag");
+
+    assert_eq!(json["file"], "app.ag");
+    assert_eq!(json["errors"], 1);
+    assert_eq!(json["warnings"], 0);
+
+    let diagnostic = &json["diagnostics"][0];
+    assert_eq!(diagnostic["severity"], "error");
+    assert!(diagnostic["message"].as_str().unwrap().contains("<root>.port"));
+    assert!(diagnostic["line"].is_u64());
+    assert!(diagnostic["help"].is_null());
+}
+
+#[test]
+fn check_test_compile_collects_diagnostics_for_rendering() {
+    let source = "{ port = ; }\n";
+    let mut engine = DiagnosticEngine::new();
+
+    assert!(compile_with_engine(source, "app.ag", CompileOptions::default(), &mut engine).is_err());
+    assert!(engine.has_error());
+
+    let report = engine.render(source, "app.ag");
+    assert!(report.contains("app.ag:1:"), "{report}");
+    assert!(!report.contains('\u{1b}'), "{report}");
+
+    let mut engine = DiagnosticEngine::new();
+    assert!(compile_with_engine("{ port = 3000; }", "app.ag", CompileOptions::default(), &mut engine).is_ok());
+    assert_eq!(engine.render("{ port = 3000; }", "app.ag"), "");
+}
+
+#[test]
+fn check_test_finds_referenced_templates() {
+    let templates = referenced_templates(r#"
+{
+    routes = [
+        {
+            path = "/";
+            handler = (req) => htmlTemplate("views/index.html");
+        },
+        {
+            path = "/user";
+            handler = (req) => render({ name = "a"; }, "views/user.html");
+        },
+        {
+            path = "/dynamic";
+            handler = (req) => render({ name = "b"; }, req.query.name);
+        }
+    ];
+}
+    "#);
+
+    assert_eq!(
+        templates.into_iter().collect::<Vec<_>>(),
+        vec!["views/index.html".to_string(), "views/user.html".to_string()]
+    );
+}
+
+#[test]
+fn check_test_never_panics_on_examples() {
+    let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+
+    let mut pending = vec![examples];
+    let mut checked = 0;
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != "ag") {
+                continue;
+            }
+
+            let src = std::fs::read_to_string(&path).unwrap();
+            let result = std::panic::catch_unwind(|| check(&src));
+            assert!(result.is_ok(), "check panicked on {}", path.display());
+            checked += 1;
+        }
+    }
+
+    assert!(checked > 0);
+
+    let engine = check("{ a = ]; }\n");
+    let messages: Vec<&str> = engine.diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert!(messages.iter().any(|m| m.contains("found \"]\"")), "{messages:?}");
+}
@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn angi(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_angi")).args(args).output().unwrap()
+}
+
+/// A source file under the system temp dir, unique per test.
+fn source_file(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("angi-cli-{}-{name}", std::process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn cli_test_help_lists_subcommands() {
+    let output = angi(&["--help"]);
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    for command in ["build", "run", "check", "asm", "debug"] {
+        assert!(help.contains(command), "{help}");
+    }
+
+    let output = angi(&["debug", "--help"]);
+    let help = String::from_utf8(output.stdout).unwrap();
+    for command in ["lex", "ast", "run", "writebc", "readbc", "writear", "readar"] {
+        assert!(help.contains(command), "{help}");
+    }
+
+    let output = angi(&["build", "--help"]);
+    let help = String::from_utf8(output.stdout).unwrap();
+    for flag in ["--out", "--release", "--opt-level", "--force", "--static-dir", "--target", "--runtime-dir"] {
+        assert!(help.contains(flag), "{help}");
+    }
+
+    let output = angi(&["run", "--help"]);
+    let help = String::from_utf8(output.stdout).unwrap();
+    for flag in ["--opt-level", "--watch"] {
+        assert!(help.contains(flag), "{help}");
+    }
+}
+
+#[test]
+fn cli_test_usage_errors_exit_with_2() {
+    assert_eq!(angi(&[]).status.code(), Some(2));
+    assert_eq!(angi(&["build"]).status.code(), Some(2));
+    assert_eq!(angi(&["build", "app.ag", "-O7"]).status.code(), Some(2));
+    assert_eq!(angi(&["debug", "readbc"]).status.code(), Some(2));
+    assert_eq!(angi(&["run"]).status.code(), Some(2));
+}
+
+#[test]
+fn cli_test_check_exit_codes() {
+    let valid = source_file("valid.ag", "{ port = 3000; routes = []; }\n");
+    let invalid = source_file("invalid.ag", "{ port = \"3000\"; routes = []; }\n");
+
+    assert_eq!(angi(&["check", valid.to_str().unwrap()]).status.code(), Some(0));
+
+    let output = angi(&["check", invalid.to_str().unwrap(), "--json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.contains("\"errors\":1"), "{json}");
+
+    let output = angi(&["check", "does-not-exist.ag"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("error: "));
+
+    fs::remove_file(valid).unwrap();
+    fs::remove_file(invalid).unwrap();
+}
+
+#[test]
+fn cli_test_writebc_then_readbc() {
+    let source = source_file("readbc.ag", "{ port = 3030; }\n");
+    let image = source.with_extension("bc");
+
+    let status = angi(&["debug", "writebc", source.to_str().unwrap(), image.to_str().unwrap()]).status;
+    assert!(status.success());
+
+    let output = angi(&["debug", "readbc", image.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("int 3030"));
+
+    fs::remove_file(source).unwrap();
+    fs::remove_file(image).unwrap();
+}
+
+#[test]
+fn cli_test_debug_run_evaluates_port_and_routes() {
+    let source = source_file("debug-run.ag", r#"
+{
+    port = 3030;
+    routes = [
+        { path = "/"; handler = (req) => html("home"); }
+    ];
+}
+"#);
+    let image = source.with_extension("bc");
+    let status = angi(&["debug", "writebc", source.to_str().unwrap(), image.to_str().unwrap()]).status;
+    assert!(status.success());
+
+    let output = angi(&["debug", "run", image.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("port: 3030"), "{stdout}");
+    assert!(stdout.contains("  /\n"), "{stdout}");
+
+    let broken = source_file("debug-run-broken.ag", "{ port = { a = 1; }.b; routes = []; }\n");
+    let status = angi(&["debug", "writebc", broken.to_str().unwrap(), image.to_str().unwrap()]).status;
+    assert!(status.success());
+    assert_eq!(angi(&["debug", "run", image.to_str().unwrap()]).status.code(), Some(1));
+
+    // Opening succeeds, every write fails
+    if cfg!(target_os = "linux") {
+        let output = angi(&["debug", "writebc", source.to_str().unwrap(), "/dev/full"]);
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    fs::remove_file(source).unwrap();
+    fs::remove_file(broken).unwrap();
+    fs::remove_file(image).unwrap();
+}
+
+#[test]
+fn cli_test_run_reports_source_errors() {
+    let output = angi(&["run", "does-not-exist.ag"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("error: "));
+
+    let invalid = source_file("run-invalid.ag", "{ port = ; }\n");
+    assert_eq!(angi(&["run", invalid.to_str().unwrap()]).status.code(), Some(1));
+
+    fs::remove_file(invalid).unwrap();
+
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let busy = source_file("run-busy.ag", &format!("{{ port = {port}; routes = []; }}\n"));
+    let output = angi(&["run", busy.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Cannot listen on"));
+
+    fs::remove_file(busy).unwrap();
+}
+
+#[test]
+fn cli_test_rebuild_overwrites_output() {
+    let source = source_file("rebuild.ag", "{ port = 3000; routes = []; }\n");
+    let out = source.with_extension("bin");
+
+    assert!(angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap()]).status.success());
+    let first = fs::metadata(&out).unwrap().len();
+    assert!(angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap()]).status.success());
+    assert_eq!(fs::metadata(&out).unwrap().len(), first);
+
+    fs::remove_file(source).unwrap();
+    fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn cli_test_build_refuses_to_replace_other_files() {
+    let source = source_file("refuse.ag", "{ port = 3000; routes = []; }\n");
+    let out = source.with_extension("txt");
+    fs::write(&out, "not a build").unwrap();
+
+    let output = angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--force"));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "not a build");
+
+    let output = angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap(), "--force"]);
+    assert!(output.status.success());
+    assert_ne!(fs::read(&out).unwrap(), b"not a build");
+
+    let dir = std::env::temp_dir();
+    let output = angi(&["build", source.to_str().unwrap(), "-o", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    fs::remove_file(source).unwrap();
+    fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn cli_test_build_reports_write_errors() {
+    let source = source_file("unwritable.ag", "{ port = 3000; routes = []; }\n");
+    let out = std::env::temp_dir().join("angi-missing-dir").join("app");
+
+    let output = angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("error: Cannot write"));
+
+    fs::remove_file(source).unwrap();
+}
+
+#[test]
+fn cli_test_build_for_another_target() {
+    let source = source_file("cross.ag", "{ port = 3000; routes = []; }\n");
+    let runtime_dir = source.with_extension("runtimes");
+    let triple = "x86_64-unknown-linux-musl";
+    fs::create_dir_all(runtime_dir.join(triple)).unwrap();
+    fs::write(runtime_dir.join(triple).join("server"), b"PREBUILT").unwrap();
+    let out = source.with_extension("bin");
+
+    let output = angi(&[
+        "build", source.to_str().unwrap(), "-o", out.to_str().unwrap(),
+        "--target", triple, "--runtime-dir", runtime_dir.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(fs::read(&out).unwrap().starts_with(b"PREBUILT"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_angi"))
+        .args(["build", source.to_str().unwrap(), "-o", out.to_str().unwrap(), "--target", "aarch64-apple-darwin"])
+        .env("ANGI_RUNTIME_DIR", &runtime_dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("available: {triple}")), "{stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_angi"))
+        .args(["build", source.to_str().unwrap(), "-o", out.to_str().unwrap(), "--target", triple])
+        .env_remove("ANGI_RUNTIME_DIR")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("ANGI_RUNTIME_DIR"));
+
+    fs::remove_file(source).unwrap();
+    fs::remove_file(out).unwrap();
+    fs::remove_dir_all(runtime_dir).unwrap();
+}
+
+#[test]
+fn cli_test_build_bundles_static_dir_and_templates() {
+    let project = std::env::temp_dir().join(format!("angi-cli-{}-bundle", std::process::id()));
+    fs::create_dir_all(project.join("assets/css")).unwrap();
+    fs::create_dir_all(project.join("views")).unwrap();
+    fs::write(project.join("assets/css/app.css"), "body {}").unwrap();
+    fs::write(project.join("views/page.html"), "<h1>page</h1>").unwrap();
+    let source = project.join("app.ag");
+    fs::write(&source, r#"
+{
+    port = 3000;
+    routes = [
+        {
+            path = "/page";
+            handler = (req) => htmlTemplate("views/page.html");
+        }
+    ];
+    static = { dir = "assets"; };
+}
+"#).unwrap();
+    let out = project.join("app");
+
+    // Config paths are relative to the working directory, not to the source
+    let output = angi(&["build", source.to_str().unwrap(), "-o", out.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("assets"));
+
+    let build = |project: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_angi"))
+            .args(["build", "app.ag", "-o", "app"])
+            .current_dir(project)
+            .output()
+            .unwrap()
+    };
+
+    let output = build(&project);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = angi(&["debug", "readar", out.to_str().unwrap()]);
+    let entries = String::from_utf8(output.stdout).unwrap();
+    assert!(entries.contains("key: static/css/app.css"), "{entries}");
+    assert!(entries.contains("key: template/views/page.html"), "{entries}");
+
+    fs::remove_file(project.join("views/page.html")).unwrap();
+    let output = build(&project);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("views/page.html"));
+
+    fs::remove_dir_all(project).unwrap();
+}
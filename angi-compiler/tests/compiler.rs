@@ -1,5 +1,9 @@
-use angi::compiler::compile;
-use angi_runtime::{value::{Function, List, Table}, vm::VM};
+use angi::compiler::optimization::OptLevel;
+use angi::compiler::{CompileOptions, compile, compile_with_debug_info, compile_with_options};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use angi_runtime::{error::VmError, limits::{ExecutionLimits, Limit}, value::{Function, List, Table, Value}, vm::VM};
 
 
 #[test]
@@ -91,3 +95,557 @@ fn compiler_test_use_global_function() {
     assert_eq!(result.get::<String>("html"), Some(String::from("<h1>Hello World</h1>")));
 }
 
+
+#[test]
+fn compiler_test_indented_string() {
+    let bytecode = compile(r#"
+{
+    name = "Angi";
+    page = let
+        title = "Home";
+    in
+        ''
+          <main>
+            <h1>${title}</h1>
+          </main>
+        '';
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    let page = vm.eval::<String>("page").unwrap();
+    assert_eq!(page, "<main>\n  <h1>Home</h1>\n</main>\n");
+}
+
+#[test]
+fn compiler_test_html_with_indented_string() {
+    let bytecode = compile(r#"
+{
+    handler = () => html(''
+        <p>
+          Hello
+        </p>
+    '');
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    let function = vm.eval::<Function>("handler").unwrap();
+    let result: Table = function.call(&mut vm, ()).unwrap();
+
+    assert_eq!(result.get::<String>("html"), Some(String::from("<p>\n  Hello\n</p>\n")));
+}
+
+#[test]
+fn compiler_test_optional_field_and_default() {
+    let bytecode = compile(r#"
+let
+    config = { port = 8080; nested = { name = "angi"; }; };
+in
+{
+    port = config.port or 3000;
+    timeout = config.timeout or 30;
+    name = config.nested.name or "none";
+    missing = config.other.name or "none";
+    safe = config?.other;
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<i64>("port").unwrap(), 8080);
+    assert_eq!(vm.eval::<i64>("timeout").unwrap(), 30);
+    assert_eq!(vm.eval::<String>("name").unwrap(), "angi");
+    assert_eq!(vm.eval::<String>("missing").unwrap(), "none");
+    assert!(matches!(vm.eval_value("safe").unwrap(), Value::None));
+}
+
+#[test]
+fn compiler_test_missing_field_is_runtime_error() {
+    let bytecode = compile(r#"
+let
+    config = { port = 8080; };
+in
+{
+    timeout = config.timeout;
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert!(matches!(vm.eval_value("timeout"), Err(VmError::NotFoundAttribute { .. })));
+}
+
+#[test]
+fn compiler_test_call_on_member_and_call_result() {
+    let bytecode = compile(r#"
+let
+    math = { double = (x) => x * 2; inc = (x) => x + 1; };
+    make = (n) => { value = n; twice = (x) => x * 2; };
+in
+{
+    a = math.double(21);
+    b = math.inc(math.double(3));
+    c = make(5).value;
+    d = make(1).twice(4);
+    e = 10 |> math.inc;
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<i64>("a").unwrap(), 42);
+    assert_eq!(vm.eval::<i64>("b").unwrap(), 7);
+    assert_eq!(vm.eval::<i64>("c").unwrap(), 5);
+    assert_eq!(vm.eval::<i64>("d").unwrap(), 8);
+    assert_eq!(vm.eval::<i64>("e").unwrap(), 11);
+}
+
+#[test]
+fn compiler_test_call_non_function_is_runtime_error() {
+    let bytecode = compile(r#"
+let
+    config = { port = 8080; };
+in
+{
+    port = config.port(1);
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert!(matches!(vm.eval_value("port"), Err(VmError::ValueTypeMismatch { .. })));
+}
+
+#[test]
+fn compiler_test_destructuring_params_and_let() {
+    let bytecode = compile(r#"
+let
+    greet = ({ name, greeting ? "Hello" }) => greeting .. " " .. name;
+    sum = ([a, b ? 10]) => a + b;
+    { port, nested: { depth } } = { port = 8080; nested = { depth = 2; }; };
+in
+{
+    a = greet({ name = "angi"; });
+    b = greet({ name = "angi"; greeting = "Hi"; });
+    c = sum([1, 2]);
+    d = sum([1]);
+    port = port;
+    depth = depth;
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<String>("a").unwrap(), "Hello angi");
+    assert_eq!(vm.eval::<String>("b").unwrap(), "Hi angi");
+    assert_eq!(vm.eval::<i64>("c").unwrap(), 3);
+    assert_eq!(vm.eval::<i64>("d").unwrap(), 11);
+    assert_eq!(vm.eval::<i64>("port").unwrap(), 8080);
+    assert_eq!(vm.eval::<i64>("depth").unwrap(), 2);
+}
+
+#[test]
+fn compiler_test_parenthesized_table_and_list() {
+    let bytecode = compile(r#"
+{
+    a = ({ b = 1; }).b;
+    c = ([1, 2]);
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<i64>("a").unwrap(), 1);
+    assert!(vm.eval_value("c").is_ok());
+}
+
+#[test]
+fn compiler_test_destructuring_missing_field_is_runtime_error() {
+    let bytecode = compile(r#"
+let
+    get_data = ({ data }) => data;
+in
+{
+    value = get_data({ other = 1; });
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert!(matches!(vm.eval_value("value"), Err(VmError::NotFoundAttribute { .. })));
+}
+
+#[test]
+fn compiler_test_if_and_comparison() {
+    let bytecode = compile(r#"
+let
+    max = (a, b) => if a > b then a else b;
+    sign = (n) => if n < 0 then "negative" else if n == 0 then "zero" else "positive";
+in
+{
+    max = max(3, 7);
+    negative = sign(0 - 5);
+    zero = sign(0);
+    positive = sign(2);
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<i64>("max").unwrap(), 7);
+    assert_eq!(vm.eval::<String>("negative").unwrap(), "negative");
+    assert_eq!(vm.eval::<String>("zero").unwrap(), "zero");
+    assert_eq!(vm.eval::<String>("positive").unwrap(), "positive");
+}
+
+#[test]
+fn compiler_test_tail_recursion_runs_in_constant_stack() {
+    let bytecode = compile(r#"
+let
+    sum = (n, acc) => if n == 0 then acc else sum(n - 1, acc + n);
+in
+{
+    total = sum(1000000, 0);
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert_eq!(vm.eval::<i64>("total").unwrap(), 500000500000);
+}
+
+#[test]
+fn compiler_test_thunk_cache_survives_clone_and_clear() {
+    let bytecode = compile(r#"
+{
+    app = { name = "angi" .. "-" .. "app"; routes = [{ path = "/"; }]; };
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+    assert_eq!(vm.eval::<String>("app.name").unwrap(), "angi-app");
+
+    let mut cloned = vm.clone();
+    assert_eq!(cloned.eval::<String>("app.name").unwrap(), "angi-app");
+
+    vm.clear_thunk_cache();
+    assert_eq!(vm.eval::<String>("app.name").unwrap(), "angi-app");
+    let mut routes = vm.eval::<List<Table>>("app.routes").unwrap();
+    routes.force(&mut vm);
+    assert_eq!(routes.get(0).unwrap().get::<String>("path"), Some(String::from("/")));
+}
+
+#[test]
+fn compiler_test_program_is_shared_between_vms() {
+    let bytecode = compile(r#"
+{
+    greeting = "Hello";
+    handler = (name) => "Hello " .. name;
+}
+    "#, "test.ag").unwrap();
+
+    let vm = VM::new_from_bytes(bytecode).unwrap();
+    let cloned = vm.clone();
+    assert!(Arc::ptr_eq(vm.program(), cloned.program()));
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let program = vm.program().clone();
+            std::thread::spawn(move || {
+                let mut vm = VM::new_from_program(program);
+                let handler = vm.eval::<Function>("handler").unwrap();
+                let value = handler.call::<String, _>(&mut vm, (format!("{i}"),)).unwrap();
+                assert_eq!(value, format!("Hello {i}"));
+                vm.eval::<String>("greeting").unwrap()
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), "Hello");
+    }
+}
+
+#[test]
+fn compiler_test_instruction_budget_stops_runaway_handler() {
+    let bytecode = compile(r#"
+let
+    spin = (n) => spin(n + 1);
+in
+{
+    handler = spin;
+    small = () => 1 + 2;
+}
+    "#, "test.ag").unwrap();
+
+    let limits = ExecutionLimits { max_instructions: Some(10_000), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let result = handler.call::<i64, _>(&mut vm, (0,));
+    assert!(matches!(
+        result,
+        Err(VmError::LimitExceeded { limit: Limit::Instructions(10_000) })
+    ));
+
+    // The budget is per call, so the VM is still usable afterwards
+    let small = vm.eval::<Function>("small").unwrap();
+    assert_eq!(small.call::<i64, _>(&mut vm, ()).unwrap(), 3);
+}
+
+#[test]
+fn compiler_test_timeout_stops_runaway_handler() {
+    let bytecode = compile(r#"
+let
+    spin = (n) => spin(n + 1);
+in
+{
+    handler = spin;
+}
+    "#, "test.ag").unwrap();
+
+    let timeout = Duration::from_millis(50);
+    let limits = ExecutionLimits { timeout: Some(timeout), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let started = Instant::now();
+    let result = handler.call::<i64, _>(&mut vm, (0,));
+
+    assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::Time(t) }) if t == timeout));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn compiler_test_memory_cap_stops_growing_string() {
+    let bytecode = compile(r#"
+let
+    grow = (s, n) => if n == 0 then s else grow(s .. s, n - 1);
+in
+{
+    handler = grow;
+}
+    "#, "test.ag").unwrap();
+
+    let limits = ExecutionLimits { max_memory: Some(64 * 1024), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let result = handler.call::<String, _>(&mut vm, (String::from("ab"), 40));
+    assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::Memory(_) })));
+
+    // Small workloads still fit under the cap
+    let value = handler.call::<String, _>(&mut vm, (String::from("ab"), 3)).unwrap();
+    assert_eq!(value.len(), 16);
+}
+
+#[test]
+fn compiler_test_memory_cap_charges_nested_values_once() {
+    let bytecode = compile(r#"
+let
+    nest = (t, n) => if n == 0 then t else nest({ inner = t; }, n - 1);
+in
+{
+    handler = nest;
+}
+    "#, "test.ag").unwrap();
+
+    // Linear in the depth, nesting must not charge the inner tables again
+    let limits = ExecutionLimits { max_memory: Some(64 * 1024), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let table = handler.call::<Table, _>(&mut vm, (String::from("leaf"), 200)).unwrap();
+    assert!(table.get::<Table>("inner").is_some());
+
+    let result = handler.call::<Table, _>(&mut vm, (String::from("leaf"), 2000));
+    assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::Memory(_) })));
+}
+
+#[test]
+fn compiler_test_integer_overflow_is_an_error() {
+    let bytecode = compile(r#"
+{
+    add = (a, b) => a + b;
+    sub = (a, b) => a - b;
+    mul = (a, b) => a * b;
+    div = (a, b) => a / b;
+}
+    "#, "test.ag").unwrap();
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    let cases = [
+        ("add", i64::MAX, 1),
+        ("sub", i64::MIN, 1),
+        ("mul", 2147483647 * 2147483647, 2147483647),
+        ("div", i64::MIN, -1),
+    ];
+    for (name, a, b) in cases {
+        let function = vm.eval::<Function>(name).unwrap();
+        let result = function.call::<i64, _>(&mut vm, (a, b));
+        assert!(matches!(result, Err(VmError::InstructionExecution { .. })), "{name}: {result:?}");
+    }
+
+    let div = vm.eval::<Function>("div").unwrap();
+    assert!(div.call::<i64, _>(&mut vm, (1, 0)).is_err());
+    assert_eq!(div.call::<i64, _>(&mut vm, (i64::MIN, 1)).unwrap(), i64::MIN);
+}
+
+#[test]
+fn compiler_test_calls_on_any_expression() {
+    let src = r#"
+{
+    parenthesized = (x) => let double = (n) => n * 2; in (double)(x);
+    chained = (x) => let double = (n) => n * 2; pick = (f) => f; in pick(double)(x);
+}
+    "#;
+
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let (bytecode, _) = compile_with_options(src, "test.ag", CompileOptions { opt_level }).unwrap();
+        let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+        for name in ["parenthesized", "chained"] {
+            let function = vm.eval::<Function>(name).unwrap();
+            assert_eq!(function.call::<i64, _>(&mut vm, (21,)).unwrap(), 42, "{name} at {opt_level:?}");
+        }
+    }
+
+    // Keywords work as keys on both sides
+    let bytecode = compile(r#"let db = { table = "users"; }; in { name = db.table; }"#, "test.ag").unwrap();
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+    assert_eq!(vm.eval::<String>("name").unwrap(), "users");
+}
+
+#[test]
+fn compiler_test_limits_apply_to_host_arguments() {
+    let bytecode = compile(r#"
+{
+    handler = (req) => 1;
+}
+    "#, "test.ag").unwrap();
+
+    let limits = ExecutionLimits { max_value_len: Some(8), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode.clone()).unwrap().with_limits(limits);
+    let handler = vm.eval::<Function>("handler").unwrap();
+    assert_eq!(handler.call::<i64, _>(&mut vm, (String::from("short"),)).unwrap(), 1);
+    let result = handler.call::<i64, _>(&mut vm, (String::from("far too long"),));
+    assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::ValueLen(8) })));
+
+    let limits = ExecutionLimits { max_memory: Some(1024), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let result = handler.call::<i64, _>(&mut vm, ("x".repeat(4096),));
+    assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::Memory(1024) })));
+
+    // The next call starts from a fresh budget
+    assert_eq!(handler.call::<i64, _>(&mut vm, (String::from("small"),)).unwrap(), 1);
+}
+
+#[test]
+fn compiler_test_value_len_cap() {
+    let bytecode = compile(r#"
+{
+    small = () => [1, 2, 3];
+    big = () => [1, 2, 3, 4, 5, 6];
+    long = (s) => s .. s;
+}
+    "#, "test.ag").unwrap();
+
+    let limits = ExecutionLimits { max_value_len: Some(4), ..Default::default() };
+    let mut vm = VM::new_from_bytes(bytecode).unwrap().with_limits(limits);
+
+    let small = vm.eval::<Function>("small").unwrap();
+    assert!(small.call::<List<i64>, _>(&mut vm, ()).is_ok());
+
+    let big = vm.eval::<Function>("big").unwrap();
+    assert!(matches!(
+        big.call::<List<i64>, _>(&mut vm, ()),
+        Err(VmError::LimitExceeded { limit: Limit::ValueLen(4) })
+    ));
+
+    let long = vm.eval::<Function>("long").unwrap();
+    assert!(matches!(
+        long.call::<String, _>(&mut vm, (String::from("abc"),)),
+        Err(VmError::LimitExceeded { limit: Limit::ValueLen(4) })
+    ));
+}
+
+#[test]
+fn compiler_test_runtime_error_has_stack_trace() {
+    let (bytecode, debug_info) = compile_with_debug_info(r#"{
+    handler = (user) => let
+        greet = (u) => "Hello " .. u.name;
+    in "<p>" .. greet(user);
+}"#, "app.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes_with_debug_info(bytecode, debug_info).unwrap();
+    let handler = vm.eval::<Function>("handler").unwrap();
+    let result = handler.call::<String, _>(&mut vm, (42,));
+    assert!(matches!(result, Err(VmError::ValueTypeMismatch { .. })));
+
+    let trace = vm.stack_trace().unwrap();
+    let frames: Vec<_> = trace
+        .frames
+        .iter()
+        .map(|frame| (frame.function.as_str(), frame.file.as_str(), frame.location))
+        .collect();
+
+    assert_eq!(frames, vec![
+        ("greet", "app.ag", Some((3, 38))),
+        ("handler", "app.ag", Some((4, 17))),
+    ]);
+    assert!(trace.to_string().contains("at greet (app.ag:3:38)"));
+}
+
+#[test]
+fn compiler_test_boolean_literals() {
+    let bytecode = compile(r#"
+{
+    enabled = true;
+    disabled = false;
+    pick = (flag) => if flag == true then "on" else "off";
+}
+    "#, "test.ag").unwrap();
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+
+    assert!(vm.eval::<bool>("enabled").unwrap());
+    assert!(!vm.eval::<bool>("disabled").unwrap());
+
+    let pick = vm.eval::<Function>("pick").unwrap();
+    let result: String = pick.call(&mut vm, (true,)).unwrap();
+    assert_eq!(result, "on");
+}
+
+#[test]
+fn compiler_test_constant_pool_is_interned() {
+    let bytecode = compile(r#"
+{
+    a = "same";
+    b = "same";
+    c = 7;
+    d = 7;
+    e = true;
+    f = true;
+    routes = [
+        { path = "/1"; handler = () => html("<p>1</p>"); },
+        { path = "/2"; handler = () => html("<p>2</p>"); }
+    ];
+}
+    "#, "test.ag").unwrap();
+
+    let image = angi::disassembler::Image::from_bytes(&bytecode).unwrap();
+    let count = |constant: &angi::disassembler::Const| {
+        image.consts.iter().filter(|c| *c == constant).count()
+    };
+
+    use angi::disassembler::Const;
+    assert_eq!(count(&Const::Str("same".into())), 1);
+    assert_eq!(count(&Const::Int(7)), 1);
+    assert_eq!(count(&Const::Bool(true)), 1);
+    assert_eq!(count(&Const::Str("type".into())), 1);
+    assert_eq!(count(&Const::Str("path".into())), 1);
+}
@@ -0,0 +1,63 @@
+use angi::compiler::optimization::OptLevel;
+use angi::compiler::{CompileOptions, compile, compile_with_debug_info, compile_with_options};
+use angi::disassembler::{Const, Image};
+
+#[test]
+fn disassembler_test_symbolic_operands() {
+    let (bytecode, debug_info) = compile_with_options(r#"
+{
+    pick = (flag) => if flag then "yes" else "no";
+}
+    "#, "test.ag", CompileOptions { opt_level: OptLevel::O1 }).unwrap();
+
+    let image = Image::from_bytes(&bytecode).unwrap();
+    assert_eq!(image.consts[1], Const::Str("yes".into()));
+    assert_eq!(image.functions.len(), 1);
+
+    let text = image.disassemble(Some(&debug_info));
+
+    assert!(text.starts_with(".format 0.2\n"));
+    assert!(text.contains("    @1     L5, 1 ; pick\n"));
+    assert!(text.contains("; ---- root\n    MAKETABLE r0\n"));
+    assert!(text.contains("    MAKEFUNC r2, @1 ; pick\n"));
+    assert!(text.contains(
+        "; ---- function @1 pick\nL5:\n    LOADARG r0\n    JMPIFNOT r0, L9\n    LOADCONST r1, #2 ; \"yes\"\n"
+    ));
+    assert!(text.contains("    JMP L10\nL9:\n"));
+    assert!(text.contains("L10:\n    RETURN r1\n"));
+}
+
+#[test]
+fn disassembler_test_names_nested_functions() {
+    let (bytecode, debug_info) = compile_with_debug_info(r#"
+{
+    greet = (name) => let shout = (s) => s .. "!"; in shout("Hello " .. name);
+}
+    "#, "test.ag").unwrap();
+
+    let text = Image::from_bytes(&bytecode).unwrap().disassemble(Some(&debug_info));
+
+    assert!(text.contains("; ---- function @1 greet\n"));
+    assert!(text.contains("; ---- function @2 shout\n"));
+    assert!(text.contains("CONCAT"));
+}
+
+#[test]
+fn disassembler_test_without_debug_info() {
+    let bytecode = compile(r#"{ port = 3030; }"#, "test.ag").unwrap();
+
+    let text = Image::from_bytes(&bytecode).unwrap().disassemble(None);
+
+    assert!(text.contains("    #2     int 3030\n"));
+    assert!(text.contains("    LOADCONST r2, #2 ; 3030\n"));
+    assert!(text.trim_end().ends_with("RETURN r0"));
+}
+
+#[test]
+fn disassembler_test_rejects_truncated_image() {
+    let bytecode = compile(r#"{ port = 3030; }"#, "test.ag").unwrap();
+
+    assert!(Image::from_bytes(&bytecode[..20]).is_err());
+    assert!(Image::from_bytes(&bytecode[..bytecode.len() - 8]).is_err());
+    assert!(Image::from_bytes(b"not bytecode at all, not even close to it....").is_err());
+}
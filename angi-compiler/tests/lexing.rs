@@ -66,3 +66,32 @@ fn lexing_test_empty_string() {
         Ok((1, Token::EndOfFile, (14, 14)))
     ])
 }
+
+#[test]
+fn lexing_indented_string_strips_indentation() {
+    let lex = Lexer::new_from_str("''\n    <div>\n      <p>Hi</p>\n    </div>\n  ''");
+    let tokens: Vec<LexResult> = lex.collect();
+    assert_eq!(tokens, vec![
+        Ok((1, Token::MultilineString("<div>\n  <p>Hi</p>\n</div>\n".into()), (1, 4))),
+        Ok((5, Token::EndOfFile, (5, 5)))
+    ])
+}
+
+#[test]
+fn lexing_indented_string_with_interpolation() {
+    let lex = Lexer::new_from_str("''\n  Hello ${name}!\n''");
+    let tokens: Vec<(u32, Token)> = lex.map(|t| {
+        let (line, tok, _) = t.unwrap();
+        (line, tok)
+    }).collect();
+    assert_eq!(tokens, vec![
+        (1, Token::StringStart),
+        (1, Token::String("Hello ".into())),
+        (2, Token::InterpStart),
+        (2, Token::Name("name".into())),
+        (2, Token::InterpEnd),
+        (2, Token::String("!\n".into())),
+        (1, Token::StringEnd),
+        (3, Token::EndOfFile)
+    ])
+}
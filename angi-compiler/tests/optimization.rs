@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use angi::compiler::ast::{Expr, InterpolatedPart};
+use angi::compiler::lexer::Lexer;
+use angi::compiler::optimization::{OptLevel, optimize};
+use angi::compiler::parser::parse;
+use angi::compiler::{CompileOptions, check, compile_with_options};
+use angi::disassembler::Image;
+use angi_runtime::value::Function;
+use angi_runtime::vm::VM;
+
+fn optimized(src: &'static str, level: OptLevel) -> Expr {
+    let mut ast = parse(&mut Lexer::new_from_str(src)).unwrap();
+    optimize(&mut ast, level);
+    ast
+}
+
+fn table(fields: Vec<(&str, Expr)>) -> Expr {
+    Expr::Table {
+        fields: fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect::<HashMap<_, _>>(),
+    }
+}
+
+#[test]
+fn optimization_test_propagates_let_constants() {
+    let expr = optimized("let x = 2; y = x * 3; in y + 1\n", OptLevel::O1);
+    assert_eq!(expr, Expr::Number(7));
+
+    let expr = optimized("let x = 1; in let x = 2; in x\n", OptLevel::O1);
+    assert_eq!(expr, Expr::Number(2));
+}
+
+#[test]
+fn optimization_test_descends_into_lists_and_tables() {
+    let expr = optimized(
+        "{ a = [1 + 2, \"a\" .. \"b\"]; b = { c = 2 * 3; }; }\n",
+        OptLevel::O1,
+    );
+    assert_eq!(
+        expr,
+        table(vec![
+            (
+                "a",
+                Expr::List {
+                    items: vec![Expr::Number(3), Expr::LiteralString("ab".into())]
+                }
+            ),
+            ("b", table(vec![("c", Expr::Number(6))])),
+        ])
+    );
+}
+
+#[test]
+fn optimization_test_folds_interpolations_and_conditions() {
+    let expr = optimized(
+        "let name = \"angi\"; in \"hi ${name}, ${1 + 1}!\"\n",
+        OptLevel::O1,
+    );
+    assert_eq!(expr, Expr::LiteralString("hi angi, 2!".into()));
+
+    let expr = optimized("(user) => \"hi ${user} and ${2 * 3}\"\n", OptLevel::O1);
+    let Expr::FunctionDeclare { body, .. } = expr else {
+        panic!("Expected a function")
+    };
+    assert_eq!(
+        *body,
+        Expr::InterpolatedString(vec![
+            InterpolatedPart::String("hi ".into()),
+            InterpolatedPart::Expr(Expr::Var("user".into())),
+            InterpolatedPart::String(" and 6".into()),
+        ])
+    );
+
+    let expr = optimized("if 1 < 2 then \"yes\" else \"no\"\n", OptLevel::O1);
+    assert_eq!(expr, Expr::LiteralString("yes".into()));
+}
+
+#[test]
+fn optimization_test_keeps_what_it_cannot_fold() {
+    // Division by zero and overflow are left for the VM to report
+    let expr = optimized("1 / 0\n", OptLevel::O1);
+    assert!(matches!(expr, Expr::Binary { .. }));
+
+    // A binding with a call may have side effects
+    let expr = optimized("let unused = html(\"x\"); in 1\n", OptLevel::O1);
+    assert!(matches!(expr, Expr::LetIn { .. }));
+
+    let expr = optimized("let x = 2; in x * 3\n", OptLevel::O0);
+    assert!(matches!(expr, Expr::LetIn { .. }));
+}
+
+#[test]
+fn optimization_test_removes_dead_bindings() {
+    let expr = optimized(
+        "let f = (n) => n; unused = [1, 2]; in \"done\"\n",
+        OptLevel::O1,
+    );
+    assert_eq!(expr, Expr::LiteralString("done".into()));
+}
+
+#[test]
+fn optimization_test_inlines_small_functions() {
+    let src = "let double = (n) => n * 2; in double(21)\n";
+
+    assert!(matches!(optimized(src, OptLevel::O1), Expr::LetIn { .. }));
+    assert_eq!(optimized(src, OptLevel::O2), Expr::Number(42));
+
+    // Recursive functions stay calls
+    let recursive = "let f = (n) => if n == 0 then 0 else f(n - 1); in f(3)\n";
+    assert!(matches!(
+        optimized(recursive, OptLevel::O2),
+        Expr::LetIn { .. }
+    ));
+
+    // A non-trivial argument used twice would be evaluated twice
+    let twice = "(x) => let sq = (n) => n * n; in sq(x + 1)\n";
+    let Expr::FunctionDeclare { body, .. } = optimized(twice, OptLevel::O2) else {
+        panic!("Expected a function")
+    };
+    assert!(matches!(*body, Expr::LetIn { .. }));
+}
+
+#[test]
+fn optimization_test_does_not_inline_into_parameter_scope() {
+    // `render` in `wrap` is not the parameter of the enclosing function
+    let src = "{ page = (render) => let wrap = (s) => render(s); in wrap(\"x\"); }\n";
+
+    let Expr::Table { fields } = optimized(src, OptLevel::O2) else {
+        panic!("Expected a table")
+    };
+    let Expr::FunctionDeclare { body, .. } = &fields["page"] else {
+        panic!("Expected a function")
+    };
+    assert!(matches!(**body, Expr::LetIn { .. }));
+
+    let mut errors = vec![];
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let (bytecode, _) =
+            compile_with_options(src, "test.ag", CompileOptions { opt_level }).unwrap();
+        let mut vm = VM::new_from_bytes(bytecode).unwrap();
+        let page = vm.eval::<Function>("page").unwrap();
+        let result = page.call::<String, _>(&mut vm, (String::from("param"),));
+        errors.push(format!("{:?}", result.unwrap_err()));
+    }
+    assert!(errors.iter().all(|error| *error == errors[0]), "{errors:?}");
+}
+
+#[test]
+fn optimization_test_levels_reject_the_same_names() {
+    let sources = [
+        "{ a = let unused = missing; in 1; }\n",
+        "{ t = let s = \"S\"; in { u = { v = s; }; }; }\n",
+    ];
+
+    for src in sources {
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let result = compile_with_options(src, "test.ag", CompileOptions { opt_level });
+            assert!(result.is_err(), "{src} compiled at {opt_level:?}");
+        }
+        assert!(check(src).has_error(), "{src}");
+    }
+}
+
+#[test]
+fn optimization_test_levels_agree_at_runtime() {
+    let src = r#"
+{
+    handler = (name) =>
+        let greet = (who) => "Hello, ${who}";
+            punct = "!";
+            unused = 1 + 2;
+        in greet(name) .. punct;
+}
+    "#;
+
+    let mut sizes = vec![];
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let (bytecode, _) =
+            compile_with_options(src, "test.ag", CompileOptions { opt_level }).unwrap();
+        sizes.push(Image::from_bytes(&bytecode).unwrap().code.len());
+
+        let mut vm = VM::new_from_bytes(bytecode).unwrap();
+        let handler = vm.eval::<Function>("handler").unwrap();
+        let result: String = handler.call(&mut vm, (String::from("Angi"),)).unwrap();
+        assert_eq!(result, "Hello, Angi!", "{opt_level:?}");
+    }
+
+    assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{sizes:?}");
+}
+
+#[test]
+fn optimization_test_keeps_bindings_that_can_fail() {
+    let bodies = [
+        "let unused = { a = 1; }.b; in 5",
+        "let unused = n + \"s\"; in 5",
+        "let t = { a = n; }; unused = \"${t}\"; in 5",
+        "let { b } = { a = n; }; in 5",
+        "let f = (x) => 5; in f({ a = 1; }.b)",
+    ];
+
+    for body in bodies {
+        let src = format!("{{ handler = (n) => {body}; }}\n");
+
+        let mut results = vec![];
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let (bytecode, _) =
+                compile_with_options(&src, "test.ag", CompileOptions { opt_level }).unwrap();
+            let mut vm = VM::new_from_bytes(bytecode).unwrap();
+            let handler = vm.eval::<Function>("handler").unwrap();
+            results.push(format!("{:?}", handler.call::<i64, _>(&mut vm, (0,))));
+        }
+
+        assert!(results[0].starts_with("Err"), "{body}: {results:?}");
+        assert!(results.iter().all(|result| *result == results[0]), "{body}: {results:?}");
+    }
+}
+
+#[test]
+fn optimization_test_compile_does_not_optimize() {
+    let src = "{ a = let x = 2; in x * 3; }\n";
+    let (unoptimized, _) =
+        compile_with_options(src, "test.ag", CompileOptions { opt_level: OptLevel::O0 }).unwrap();
+    assert_eq!(angi::compiler::compile(src, "test.ag").unwrap(), unoptimized);
+}
@@ -0,0 +1,116 @@
+use angi::compiler::bytecode::BytecodeGen;
+use angi::compiler::lexer::Lexer;
+use angi::compiler::parser::parse;
+use angi::compiler::{CompileOptions, compile_with_options};
+use angi::compiler::optimization::OptLevel;
+use angi::disassembler::Image;
+use angi_runtime::value::Function;
+use angi_runtime::vm::VM;
+
+/// Bytecode for `src` without the AST optimizer, so only the peephole
+/// pass differs between the two runs.
+fn emit(src: &'static str, peephole: bool) -> Vec<u8> {
+    let ast = parse(&mut Lexer::new_from_str(src)).unwrap();
+    BytecodeGen::new().with_peephole(peephole).get_binary(ast).unwrap()
+}
+
+fn instruction_count(bytecode: &[u8]) -> usize {
+    Image::from_bytes(bytecode).unwrap().code.len()
+}
+
+#[test]
+fn peephole_test_merges_constant_concatenations() {
+    let src = "{ greeting = \"Hello\" .. \", \" .. \"world\"; }\n";
+
+    // MAKETABLE, key, 3 loads and 2 CONCATs, SETATTR, RETURN
+    assert_eq!(instruction_count(&emit(src, false)), 9);
+    // MAKETABLE, key, one load, SETATTR, RETURN
+    let bytecode = emit(src, true);
+    assert_eq!(instruction_count(&bytecode), 5);
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+    assert_eq!(vm.eval::<String>("greeting").unwrap(), "Hello, world");
+}
+
+#[test]
+fn peephole_test_merges_interpolated_literals() {
+    let src = "{ label = (name) => \"<${name}> #${1}${0}!\"; }\n";
+
+    let before = emit(src, false);
+    let after = emit(src, true);
+    // The five parts after `name`, with the empty one between `${1}` and
+    // `${0}`, become a single load and CONCAT
+    assert_eq!(instruction_count(&before) - instruction_count(&after), 8);
+
+    for bytecode in [before, after] {
+        let mut vm = VM::new_from_bytes(bytecode).unwrap();
+        let label = vm.eval::<Function>("label").unwrap();
+        let result: String = label.call(&mut vm, (String::from("angi"),)).unwrap();
+        assert_eq!(result, "<angi> #10!");
+    }
+}
+
+#[test]
+fn peephole_test_removes_moves_and_dead_loads() {
+    let src = "{ sign = (n) => let f = (x) => if x < 0 then \"-\" else \"+\"; in f(n); }\n";
+
+    let before = emit(src, false);
+    let after = emit(src, true);
+    // Each branch MOVE is folded into its load, and the unused MAKEFUNC
+    // that lets `f` call itself is dropped
+    assert_eq!(instruction_count(&before) - instruction_count(&after), 3);
+
+    let text = Image::from_bytes(&after).unwrap().disassemble(None);
+    assert!(!text.contains("MOVE"), "{text}");
+
+    for bytecode in [before, after] {
+        let mut vm = VM::new_from_bytes(bytecode).unwrap();
+        let sign = vm.eval::<Function>("sign").unwrap();
+        let result: String = sign.call(&mut vm, (-3,)).unwrap();
+        assert_eq!(result, "-");
+        let result: String = sign.call(&mut vm, (3,)).unwrap();
+        assert_eq!(result, "+");
+    }
+}
+
+#[test]
+fn peephole_test_compacts_registers() {
+    let src = "{ answer = let double = (n) => n * 2; in double(21); }\n";
+
+    let text = Image::from_bytes(&emit(src, false)).unwrap().disassemble(None);
+    assert!(text.contains("    LOADARG r1\n"), "{text}");
+
+    let bytecode = emit(src, true);
+    let text = Image::from_bytes(&bytecode).unwrap().disassemble(None);
+    assert!(
+        text.contains("    LOADARG r0\n    LOADCONST r1, #3 ; 2\n    MUL r2, r0, r1\n    RETURN r2\n"),
+        "{text}"
+    );
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+    assert_eq!(vm.eval::<i64>("answer").unwrap(), 42);
+}
+
+#[test]
+fn peephole_test_keeps_debug_info_in_range() {
+    let src = r#"
+{
+    describe = (n) =>
+        let kind = if n < 10 then "small" else "large";
+        in "${n} is ${kind}";
+}
+    "#;
+
+    let (bytecode, debug_info) =
+        compile_with_options(src, "test.ag", CompileOptions { opt_level: OptLevel::O1 }).unwrap();
+    let len = instruction_count(&bytecode) as u32;
+
+    assert!(debug_info.lines.windows(2).all(|pair| pair[0].ins < pair[1].ins));
+    assert!(debug_info.lines.iter().all(|entry| entry.ins < len));
+    assert!(debug_info.scopes.iter().all(|scope| scope.start < scope.end && scope.end <= len));
+
+    let mut vm = VM::new_from_bytes(bytecode).unwrap();
+    let describe = vm.eval::<Function>("describe").unwrap();
+    let result: String = describe.call(&mut vm, (42,)).unwrap();
+    assert_eq!(result, "42 is large");
+}
@@ -0,0 +1,88 @@
+use angi::compiler::lexer::Lexer;
+use angi::compiler::parser::parse;
+use angi::diagnostic::DiagnosticEngine;
+use angi::type_checking::type_checking;
+
+fn check(src: &'static str) -> DiagnosticEngine {
+    let mut lex = Lexer::new_from_str(src);
+    let ast = parse(&mut lex).unwrap();
+    let mut engine = DiagnosticEngine::new();
+    type_checking(&ast, &mut engine);
+    engine
+}
+
+#[test]
+fn type_checking_optional_fields_can_be_omitted() {
+    let engine = check(r#"
+{
+    port = 3000;
+    routes = [
+       {
+           path = "/";
+           handler = () => "Hello";
+       }
+    ];
+}
+    "#);
+
+    assert!(!engine.has_error());
+}
+
+#[test]
+fn type_checking_optional_fields_are_checked_when_present() {
+    let engine = check(r#"
+{
+    port = 3000;
+    routes = [
+       {
+           method = 1;
+           path = "/";
+           handler = () => "Hello";
+       }
+    ];
+    static = {
+       prefix = "/assets";
+    };
+}
+    "#);
+
+    let messages: Vec<&str> = engine.diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages.len(), 2);
+    assert!(messages.iter().any(|m| m.contains("routes[0].method")));
+    assert!(messages.iter().any(|m| m.contains("dir attribute not found in <root>.static")));
+}
+
+#[test]
+fn type_checking_server_pool_size() {
+    let engine = check(r#"
+{
+    port = 3000;
+    routes = [];
+    server = {
+       pool_size = "eight";
+    };
+}
+    "#);
+
+    let messages: Vec<&str> = engine.diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("<root>.server.pool_size"));
+}
+
+#[test]
+fn type_checking_server_limits() {
+    let engine = check(r#"
+{
+    port = 3000;
+    routes = [];
+    server = {
+       max_instructions = 1000000;
+       timeout_ms = "slow";
+    };
+}
+    "#);
+
+    let messages: Vec<&str> = engine.diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("<root>.server.timeout_ms"));
+}
@@ -1,10 +1,55 @@
+//! Instruction set and image format shared by the compiler and the VM.
+//!
+//! # Image layout
+//!
+//! Every number is a big-endian u32 unless noted otherwise.
+//!
+//! ```text
+//! header    [magic][version]
+//!           [const offset][const count]
+//!           [thunk offset][thunk count]
+//!           [function offset][function count]
+//!           [global function offset][global function count]
+//!           [code offset][instruction count]          METADATA_BYTES in total
+//! consts    ([u8 type][payload])*                      see `CONST_*`
+//! thunks    ([instruction index])*
+//! functions ([arg count][instruction index])*
+//! globals   ([name const idx][function idx])*
+//! code      ([opcode u8][operands u24])*
+//! footer    [total image length]
+//! ```
+//!
+//! Constant, thunk and function indices are 1-based. Readers must use the
+//! offsets from the header rather than assume sections are contiguous.
+//!
+//! # Versioning
+//!
+//! `version` is `major << 16 | minor`. The minor version is bumped for
+//! additive changes, such as a new opcode or constant type: existing codes
+//! are never renumbered, so a VM runs every image with its own major version
+//! and a minor version up to its own. The major version is bumped when the
+//! layout above changes, and a VM rejects images from any other major.
+//!
+//! | version | changes                                                            |
+//! |---------|--------------------------------------------------------------------|
+//! | 0.1     | initial layout, opcodes 1-20                                       |
+//! | 0.2     | bool, none, float consts; opcodes 21-30 (`OPTFIELD` to `TAILCALL`) |
+
 #[macro_use]
 mod macros;
 mod native_function;
 
-pub const VERSION: u32 = 0x00000001; // "0.0.1"
+/// Format version written by this compiler and the newest one the VM runs.
+pub const VERSION: u32 = format_version(0, 2);
 pub const METADATA_BYTES: u32 = 48;
 pub const MAGIC_NUMBER: u32 = 0x414E4749; // "ANGI"
+// Constant type tags and their payloads
+pub const CONST_INT: u8 = 0;    // i64
+pub const CONST_STRING: u8 = 1; // [u32 len][utf-8 bytes]
+pub const CONST_BOOL: u8 = 2;   // u8, 0 or 1
+pub const CONST_NONE: u8 = 3;   // no payload
+pub const CONST_FLOAT: u8 = 4;  // f64 bits as u64
+
 pub const OPCODE_BITS: u32 = 8;
 pub const REG_BITS: u32 = 4;
 pub const CONST_BITS: u32 = 20;
@@ -25,6 +70,8 @@ pub enum OperandWithNum {
     ConstIdx(u32),
 }
 
+// Codes are append-only: a new opcode takes the next free code and bumps the
+// minor `VERSION`, so images built before it keep decoding the same way.
 define_opcodes! {
     LOADCONST = { code = 1 , layout = [RegAddr,ConstIdx] },            // Load Const
     LOADIM    = { code = 2 , layout = [] },                            // Load immediately
@@ -46,9 +93,42 @@ define_opcodes! {
     GETFIELD  = { code = 18, layout = [RegAddr,RegAddr,RegAddr] },             // Call Function
     CFOREIGN  = { code = 19, layout = [RegAddr,ConstIdx] },             // Call Function
     RESETPAR  = { code = 20, layout = [] },                            // Call Function
+    OPTFIELD  = { code = 21, layout = [RegAddr,RegAddr,RegAddr] },     // Get Field, None if missing
+    MOVE      = { code = 22, layout = [RegAddr,RegAddr] },             // Move register
+    JMPSOME   = { code = 23, layout = [RegAddr,ConstIdx] },            // Jump if not None
+    EQ        = { code = 24, layout = [RegAddr,RegAddr,RegAddr] },     // Equal
+    NE        = { code = 25, layout = [RegAddr,RegAddr,RegAddr] },     // Not Equal
+    LT        = { code = 26, layout = [RegAddr,RegAddr,RegAddr] },     // Less Than
+    LE        = { code = 27, layout = [RegAddr,RegAddr,RegAddr] },     // Less or Equal
+    JMP       = { code = 28, layout = [ConstIdx] },                    // Jump
+    JMPIFNOT  = { code = 29, layout = [RegAddr,ConstIdx] },            // Jump if false or None
+    TAILCALL  = { code = 30, layout = [RegAddr] },                     // Call reusing the current frame
 }
 
 pub fn extract_opcode(byte: u32) -> Option<OpCode> {
     let bit = byte >> OPCODE_OFFSET;
     OpCode::from_u8(bit as u8)
 }
+
+pub const fn format_version(major: u32, minor: u32) -> u32 {
+    (major << 16) | (minor & 0xFFFF)
+}
+
+pub const fn version_major(version: u32) -> u32 {
+    version >> 16
+}
+
+pub const fn version_minor(version: u32) -> u32 {
+    version & 0xFFFF
+}
+
+/// `"major.minor"` for messages.
+pub fn version_string(version: u32) -> String {
+    format!("{}.{}", version_major(version), version_minor(version))
+}
+
+/// Whether a VM built for `VERSION` can run an image tagged `version`.
+pub const fn is_compatible_version(version: u32) -> bool {
+    version_major(version) == version_major(VERSION)
+        && version_minor(version) <= version_minor(VERSION)
+}
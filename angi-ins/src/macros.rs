@@ -26,6 +26,24 @@ macro_rules! define_opcodes {
                 }
             }
 
+            /// Look up an opcode by its mnemonic, e.g. `"LOADCONST"`.
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(
+                        stringify!($name) => Some(OpCode::$name),
+                    )*
+                    _ => None,
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(
+                        OpCode::$name => stringify!($name),
+                    )*
+                }
+            }
+
             pub fn to_u8(self) -> u8 {
                 self as u8
             }
@@ -1,4 +1,7 @@
-use angi_ins::{extract_opcode, OpCode, Operand, OPCODE_OFFSET};
+use angi_ins::{
+    OPCODE_OFFSET, OpCode, Operand, VERSION, extract_opcode, format_version, is_compatible_version,
+    version_major, version_string,
+};
 
 
 #[test]
@@ -11,6 +14,13 @@ fn test_opcode_from_and_to_u8() {
     assert_eq!(OpCode::ADDLIST.to_u8(), 8);
 }
 
+#[test]
+fn test_opcode_from_and_to_name() {
+    assert_eq!(OpCode::from_name("LOADCONST"), Some(OpCode::LOADCONST));
+    assert_eq!(OpCode::from_name("loadconst"), None);
+    assert_eq!(OpCode::TAILCALL.name(), "TAILCALL");
+}
+
 #[test]
 fn test_opcode_to_u32() {
     assert_eq!(OpCode::RETURN.to_u32(), 6);
@@ -75,3 +85,13 @@ fn test_roundtrip_all_opcodes() {
 }
 
 
+
+#[test]
+fn test_format_version() {
+    assert_eq!(VERSION, format_version(0, 2));
+    assert_eq!(version_string(format_version(2, 7)), "2.7");
+    assert!(is_compatible_version(format_version(0, 0)));
+    assert!(is_compatible_version(VERSION));
+    assert!(!is_compatible_version(VERSION + 1));
+    assert!(!is_compatible_version(format_version(version_major(VERSION) + 1, 0)));
+}
@@ -4,13 +4,19 @@ use crate::{error::VmError, value::Value};
 pub enum ConstantValue {
     Int(i64),
     String(String),
+    Bool(bool),
+    None,
+    Float(f64),
 }
 
 impl std::fmt::Display for ConstantValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ConstantValue::Int(int) => int.fmt(f),
-            ConstantValue::String(str) => str.fmt(f)
+            ConstantValue::String(str) => str.fmt(f),
+            ConstantValue::Bool(bool) => bool.fmt(f),
+            ConstantValue::None => write!(f, "none"),
+            ConstantValue::Float(float) => float.fmt(f),
         }
     }
 }
@@ -19,7 +25,10 @@ impl ConstantValue {
    pub fn to_value(&self) -> Value {
         match self {
             ConstantValue::Int(int) => Value::Int(*int),
-            ConstantValue::String(str) => Value::String(str.clone())
+            ConstantValue::String(str) => Value::String(str.clone()),
+            ConstantValue::Bool(bool) => Value::Bool(*bool),
+            ConstantValue::None => Value::None,
+            ConstantValue::Float(float) => Value::Float(*float),
         }
     }
 
@@ -56,6 +65,10 @@ mod tests {
         let c2 = ConstantValue::String("xyz".into());
         let v2 = c2.to_value();
         assert!(matches!(v2, Value::String(s) if s == "xyz"));
+
+        assert!(matches!(ConstantValue::Bool(true).to_value(), Value::Bool(true)));
+        assert!(matches!(ConstantValue::None.to_value(), Value::None));
+        assert!(matches!(ConstantValue::Float(1.5).to_value(), Value::Float(f) if f == 1.5));
     }
 
     #[test]
@@ -0,0 +1,198 @@
+use crate::error::VmError;
+use angi_utils::read_byte::{read_str_with_len, read_u32};
+use std::fmt;
+
+/// Name of the archive entry holding the encoded `DebugInfo`.
+pub const DEBUG_INFO_ENTRY: &str = "debuginfo";
+
+/// Source mapping for a compiled program, stored next to the bytecode.
+///
+/// Layout, every number is a big-endian u32:
+///
+/// ```text
+/// [file len][file]
+/// [line count] ([ins][line][column])*     sorted by ins, line 0 = unknown
+/// [scope count]([start][end][name len][name])*
+/// ```
+///
+/// `ins` is an instruction index counted from the start of the code section.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub file: String,
+    pub lines: Vec<LineEntry>,
+    pub scopes: Vec<ScopeEntry>,
+}
+
+/// Instructions from `ins` up to the next entry come from `line:column`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineEntry {
+    pub ins: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// The function or thunk body spanning instructions `start..end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeEntry {
+    pub start: u32,
+    pub end: u32,
+    pub name: String,
+}
+
+impl DebugInfo {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        write_str(&mut bytes, &self.file);
+
+        bytes.extend_from_slice(&(self.lines.len() as u32).to_be_bytes());
+        for entry in &self.lines {
+            bytes.extend_from_slice(&entry.ins.to_be_bytes());
+            bytes.extend_from_slice(&entry.line.to_be_bytes());
+            bytes.extend_from_slice(&entry.column.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.scopes.len() as u32).to_be_bytes());
+        for scope in &self.scopes {
+            bytes.extend_from_slice(&scope.start.to_be_bytes());
+            bytes.extend_from_slice(&scope.end.to_be_bytes());
+            write_str(&mut bytes, &scope.name);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VmError> {
+        let mut cursor = 0;
+
+        let file = read_str(bytes, &mut cursor)?;
+
+        let line_count = read_field(bytes, &mut cursor, "line count")?;
+        let mut lines = Vec::with_capacity(line_count as usize);
+        for _ in 0..line_count {
+            lines.push(LineEntry {
+                ins: read_field(bytes, &mut cursor, "line ins")?,
+                line: read_field(bytes, &mut cursor, "line")?,
+                column: read_field(bytes, &mut cursor, "column")?,
+            });
+        }
+
+        let scope_count = read_field(bytes, &mut cursor, "scope count")?;
+        let mut scopes = Vec::with_capacity(scope_count as usize);
+        for _ in 0..scope_count {
+            scopes.push(ScopeEntry {
+                start: read_field(bytes, &mut cursor, "scope start")?,
+                end: read_field(bytes, &mut cursor, "scope end")?,
+                name: read_str(bytes, &mut cursor)?,
+            });
+        }
+
+        Ok(DebugInfo { file, lines, scopes })
+    }
+
+    /// `(line, column)` of the instruction at index `ins`.
+    pub fn location(&self, ins: u32) -> Option<(u32, u32)> {
+        let idx = self.lines.partition_point(|entry| entry.ins <= ins);
+        let entry = self.lines.get(idx.checked_sub(1)?)?;
+
+        (entry.line != 0).then_some((entry.line, entry.column))
+    }
+
+    /// Name of the innermost function or thunk containing `ins`.
+    pub fn scope_name(&self, ins: u32) -> &str {
+        self.scopes
+            .iter()
+            .filter(|scope| scope.start <= ins && ins < scope.end)
+            .min_by_key(|scope| scope.end - scope.start)
+            .map(|scope| scope.name.as_str())
+            .unwrap_or("<root>")
+    }
+}
+
+fn write_str(bytes: &mut Vec<u8>, str: &str) {
+    bytes.extend_from_slice(&(str.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(str.as_bytes());
+}
+
+fn read_field(bytes: &[u8], cursor: &mut usize, name: &str) -> Result<u32, VmError> {
+    read_u32(bytes, cursor).ok_or_else(|| VmError::UnexpectedError {
+        message: format!("Error in get {name} of debug info"),
+    })
+}
+
+fn read_str(bytes: &[u8], cursor: &mut usize) -> Result<String, VmError> {
+    let len = read_field(bytes, cursor, "string length")? as usize;
+
+    read_str_with_len(bytes, cursor, len).ok_or_else(|| VmError::UnexpectedError {
+        message: "Error in get string of debug info".into(),
+    })
+}
+
+/// One frame of an Angi-level stack trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub file: String,
+    pub location: Option<(u32, u32)>,
+}
+
+/// Frames of a failed execution, innermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StackTrace {
+    pub frames: Vec<TraceFrame>,
+}
+
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in &self.frames {
+            match frame.location {
+                Some((line, column)) => writeln!(
+                    f,
+                    "    at {} ({}:{}:{})",
+                    frame.function, frame.file, line, column
+                )?,
+                None => writeln!(f, "    at {} ({})", frame.function, frame.file)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DebugInfo, LineEntry, ScopeEntry};
+
+    #[test]
+    fn test_round_trip_and_lookup() {
+        let info = DebugInfo {
+            file: "app.ag".into(),
+            lines: vec![
+                LineEntry { ins: 0, line: 0, column: 0 },
+                LineEntry { ins: 3, line: 2, column: 5 },
+                LineEntry { ins: 7, line: 4, column: 1 },
+            ],
+            scopes: vec![
+                ScopeEntry { start: 5, end: 20, name: "outer".into() },
+                ScopeEntry { start: 8, end: 12, name: "inner".into() },
+            ],
+        };
+
+        let decoded = DebugInfo::from_bytes(&info.to_bytes()).unwrap();
+        assert_eq!(decoded, info);
+
+        assert_eq!(decoded.location(1), None);
+        assert_eq!(decoded.location(3), Some((2, 5)));
+        assert_eq!(decoded.location(6), Some((2, 5)));
+        assert_eq!(decoded.location(100), Some((4, 1)));
+
+        assert_eq!(decoded.scope_name(2), "<root>");
+        assert_eq!(decoded.scope_name(6), "outer");
+        assert_eq!(decoded.scope_name(9), "inner");
+    }
+
+    #[test]
+    fn test_truncated_bytes_fail() {
+        let bytes = DebugInfo { file: "app.ag".into(), ..DebugInfo::default() }.to_bytes();
+        assert!(DebugInfo::from_bytes(&bytes[..bytes.len() - 2]).is_err());
+    }
+}
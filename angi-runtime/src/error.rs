@@ -1,6 +1,10 @@
 use std::fmt;
 
 use angi_archive::ExtractorError;
+use angi_ins::{VERSION, version_string};
+
+use crate::limits::Limit;
+use crate::verifier::VerifyError;
 
 #[derive(Debug)]
 pub enum VmError {
@@ -14,7 +18,10 @@ pub enum VmError {
         cursor: usize
     },
     ExtractorError { err: ExtractorError },
-    NotFoundAttribute { message: String }
+    NotFoundAttribute { message: String },
+    LimitExceeded { limit: Limit },
+    InvalidBytecode { error: VerifyError },
+    IncompatibleVersion { found: u32 },
 }
 
 impl fmt::Display for VmError {
@@ -32,6 +39,22 @@ impl fmt::Display for VmError {
                 write!(f, "[ErrorInGetOpcode] {message}, ins: {ins}, cursor: {cursor}")
             }
             VmError::NotFoundAttribute { message } => write!(f, "[NotFoundAttribute] {message}"),
+            VmError::LimitExceeded { limit } => write!(f, "[LimitExceeded] {limit}"),
+            VmError::InvalidBytecode { error } => write!(f, "[InvalidBytecode] {error}"),
+            VmError::IncompatibleVersion { found } => {
+                let hint = if *found > VERSION {
+                    "it was built by a newer compiler, upgrade the runtime"
+                } else {
+                    "it was built by an older compiler, rebuild it"
+                };
+                write!(
+                    f,
+                    "[IncompatibleVersion] bytecode format {} is not supported by this runtime \
+                     (format {}), {hint}",
+                    version_string(*found),
+                    version_string(VERSION)
+                )
+            }
             _ => {
                 write!(f, "Error not implement display yet")
             }
@@ -1,9 +1,14 @@
+pub mod debug_info;
 pub mod error;
 pub mod metadata;
 pub mod register;
 pub mod tree;
 pub mod value;
 pub mod vm;
+pub mod limits;
 pub mod modules;
+pub mod pool;
+pub mod program;
+pub mod verifier;
 mod constant;
 mod function;
@@ -0,0 +1,33 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Per-call execution limits. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    /// Approximate bytes the program may allocate for the values it builds.
+    pub max_memory: Option<usize>,
+    /// Longest string in bytes, and most items in a single list or table.
+    pub max_value_len: Option<usize>,
+}
+
+/// The limit a call ran into, carried by `VmError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions(u64),
+    Time(Duration),
+    Memory(usize),
+    ValueLen(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Instructions(max) => write!(f, "instruction budget of {max} exhausted"),
+            Limit::Time(timeout) => write!(f, "execution time exceeded {:?}", timeout),
+            Limit::Memory(max) => write!(f, "memory cap of {max} bytes exceeded"),
+            Limit::ValueLen(max) => write!(f, "value grew past the cap of {max}"),
+        }
+    }
+}
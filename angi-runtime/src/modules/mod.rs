@@ -6,6 +6,8 @@ mod database;
 mod template;
 mod error;
 
+pub use template::{TemplateLoader, load_template, set_template_loader};
+
 type ForeignFn = fn(Vec<Value>) -> Result<Value, ForeignFnError>;
 
 #[derive(Debug,Clone,Copy)]
@@ -47,13 +49,16 @@ impl FunctionRegistry {
         idx
     }
     pub fn resolve(&self, idx: u32, args: Vec<Value>) -> Result<Value, ForeignFnError> {
-        println!("idx {}", idx);
-        println!("args {:?}", args);
         match self.idx_map.get(&idx) {
             Some(func) => func(args),
-            None => panic!("Function index {} not found", idx),
+            None => Err(ForeignFnError::Unexpected {
+                message: format!("Function index {} not found", idx),
+            }),
         }
     }
+    pub fn contains_idx(&self, idx: u32) -> bool {
+        self.idx_map.contains_key(&idx)
+    }
     pub fn is_have_function(&self, name: &String) -> bool {
         self.func.contains_key(name)
     }
@@ -5,9 +5,30 @@ use crate::value::Value;
 use minijinja::value::Value as MiniValue;
 use minijinja::Environment;
 use std::fs;
+use std::io;
+use std::sync::OnceLock;
 
 use crate::modules::ForeignFnError;
 
+/// Looks up a template bundled with the program by the path it is rendered as.
+pub type TemplateLoader = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+static TEMPLATE_LOADER: OnceLock<TemplateLoader> = OnceLock::new();
+
+/// Read templates through `loader` before the file system. Only the first
+/// loader set for the process is used.
+pub fn set_template_loader(loader: TemplateLoader) {
+    let _ = TEMPLATE_LOADER.set(loader);
+}
+
+/// The template at `path`, bundled or from disk.
+pub fn load_template(path: &str) -> io::Result<String> {
+    match TEMPLATE_LOADER.get().and_then(|loader| loader(path)) {
+        Some(template) => Ok(template),
+        None => fs::read_to_string(path),
+    }
+}
+
 pub fn render_fn(args: Vec<Value>) -> Result<Value, ForeignFnError> {
     if args.len() != 2 {
         return Err(ForeignFnError::Unexpected {
@@ -33,7 +54,7 @@ pub fn render_fn(args: Vec<Value>) -> Result<Value, ForeignFnError> {
         }
     };
 
-    let template_str = fs::read_to_string(path)
+    let template_str = load_template(path)
         .map_err(|e| ForeignFnError::Unexpected { message: e.to_string(),})?;
 
     let env = Environment::new();
@@ -0,0 +1,133 @@
+use crate::limits::ExecutionLimits;
+use crate::program::Program;
+use crate::vm::VM;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+/// A pool of `size` VMs over one shared program. Taking a VM blocks until one
+/// is free, so at most `size` callers run the program at once.
+pub struct VmPool {
+    program: Arc<Program>,
+    idle: Mutex<Vec<VM>>,
+    returned: Condvar,
+    size: usize,
+    limits: ExecutionLimits,
+}
+
+impl VmPool {
+    pub fn new(program: Arc<Program>, size: usize) -> Self {
+        let size = size.max(1);
+        let idle = (0..size).map(|_| VM::new_from_program(program.clone())).collect();
+
+        VmPool {
+            program,
+            idle: Mutex::new(idle),
+            returned: Condvar::new(),
+            size,
+            limits: ExecutionLimits::default(),
+        }
+    }
+
+    /// Limits applied to every VM handed out by the pool.
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        for vm in self.idle.get_mut().unwrap_or_else(PoisonError::into_inner) {
+            vm.set_limits(limits);
+        }
+        self
+    }
+
+    pub fn limits(&self) -> ExecutionLimits {
+        self.limits
+    }
+
+    pub fn program(&self) -> &Arc<Program> {
+        &self.program
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Wait for a free VM.
+    pub fn get(self: &Arc<Self>) -> PooledVm {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        let vm = loop {
+            match idle.pop() {
+                Some(vm) => break vm,
+                None => idle = self.returned.wait(idle).unwrap_or_else(PoisonError::into_inner),
+            }
+        };
+
+        PooledVm {
+            vm: Some(vm),
+            pool: self.clone(),
+        }
+    }
+
+    fn put_back(&self, mut vm: VM) {
+        vm.reset();
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner).push(vm);
+        self.returned.notify_one();
+    }
+}
+
+/// A VM borrowed from a `VmPool`, handed back when dropped.
+pub struct PooledVm {
+    vm: Option<VM>,
+    pool: Arc<VmPool>,
+}
+
+impl Deref for PooledVm {
+    type Target = VM;
+
+    fn deref(&self) -> &VM {
+        self.vm.as_ref().expect("pooled vm is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledVm {
+    fn deref_mut(&mut self) -> &mut VM {
+        self.vm.as_mut().expect("pooled vm is only taken on drop")
+    }
+}
+
+impl Drop for PooledVm {
+    fn drop(&mut self) {
+        if let Some(vm) = self.vm.take() {
+            self.pool.put_back(vm);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuses_and_bounds_vms() {
+        let pool = Arc::new(VmPool::new(Arc::new(Program::default()), 2));
+        assert_eq!(pool.idle_count(), 2);
+
+        let first = pool.get();
+        let second = pool.get();
+        assert_eq!(pool.idle_count(), 0);
+        assert!(Arc::ptr_eq(first.program(), pool.program()));
+
+        let waiting = {
+            let pool = pool.clone();
+            std::thread::spawn(move || drop(pool.get()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.join().unwrap();
+        drop(second);
+        assert_eq!(pool.idle_count(), 2);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::constant::ConstantValue;
+use crate::debug_info::DebugInfo;
+use crate::error::VmError;
+use crate::function::Function;
+use crate::metadata::MetaData;
+use crate::modules::{FunctionRegistry, get_default_foreign_function};
+use crate::value::Value;
+use crate::verifier::verify;
+use angi_ins::{
+    CONST_BOOL, CONST_FLOAT, CONST_INT, CONST_NONE, CONST_STRING, MAGIC_NUMBER, is_compatible_version,
+};
+use angi_utils::read_byte::{read_i64, read_str_with_len, read_u8, read_u32};
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+/// A loaded bytecode image, shared as one `Arc<Program>` by every `VM`
+/// executing it. The code and tables never change once loaded, only the
+/// thunk cache does: thunks are pure, so a value forced by one VM is reused
+/// by all of them.
+pub struct Program {
+    pub(crate) metadata: MetaData,
+    pub(crate) const_pool: HashMap<usize, ConstantValue>,
+    pub(crate) thunk_table: HashMap<usize, u32>,
+    pub(crate) function_table: HashMap<usize, Function>,
+    pub(crate) global_function_table: HashMap<String, usize>,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) registry_func: FunctionRegistry,
+    pub(crate) debug_info: Option<DebugInfo>,
+    thunk_cache: RwLock<HashMap<u32, Value>>,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program {
+            metadata: MetaData::default(),
+            const_pool: HashMap::new(),
+            thunk_table: HashMap::new(),
+            function_table: HashMap::new(),
+            global_function_table: HashMap::new(),
+            bytes: vec![],
+            registry_func: get_default_foreign_function(),
+            debug_info: None,
+            thunk_cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl Program {
+    pub fn load(bytes: Vec<u8>) -> Result<Self, VmError> {
+        let mut program = Program {
+            bytes,
+            ..Program::default()
+        };
+        program.load_metadata()?;
+        program.load_const()?;
+        program.load_thunk_table()?;
+        program.load_function_table()?;
+        program.load_global_function_table()?;
+        verify(&program)?;
+        Ok(program)
+    }
+
+    pub fn with_debug_info(mut self, debug_info: DebugInfo) -> Self {
+        self.debug_info = Some(debug_info);
+        self
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    pub fn get_metadata(&self) -> MetaData {
+        self.metadata
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Forced thunk values. Thunks never depend on call arguments, so one
+    /// cache is valid for every VM running this program.
+    pub(crate) fn cached_thunk(&self, thunk_idx: u32) -> Option<Value> {
+        self.thunk_cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&thunk_idx)
+            .cloned()
+    }
+
+    pub(crate) fn cache_thunk(&self, thunk_idx: u32, value: Value) {
+        self.thunk_cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(thunk_idx, value);
+    }
+
+    pub(crate) fn clear_thunk_cache(&self) {
+        self.thunk_cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    fn load_metadata(&mut self) -> Result<(), VmError> {
+        let mut cursor = 0;
+        let magic_code =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get magic code".into(),
+            })?;
+
+        if magic_code != MAGIC_NUMBER {
+            return Err(VmError::UnexpectedError {
+                message: "Magic code not suitable".into(),
+            });
+        };
+
+        let version =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get version".into(),
+            })?;
+
+        if !is_compatible_version(version) {
+            return Err(VmError::IncompatibleVersion { found: version });
+        }
+
+        let const_offset =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get const_offset".into(),
+            })?;
+        let const_size =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get const_size".into(),
+            })?;
+        let thunk_offset =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get thunk_offset".into(),
+            })?;
+        let thunk_size =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get thunk_size".into(),
+            })?;
+        let function_offset =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get function_offset".into(),
+            })?;
+        let function_size =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get function_size".into(),
+            })?;
+        let global_function_offset =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get function_offset".into(),
+            })?;
+        let global_function_size =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get function_size".into(),
+            })?;
+        let code_offset =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in get code_offset".into(),
+            })?;
+        let code_size =
+            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                message: "Error in code_size".into(),
+            })?;
+
+        self.metadata = MetaData {
+            magic_code,
+            version,
+            const_offset,
+            const_size,
+            thunk_offset,
+            thunk_size,
+            function_offset,
+            function_size,
+            global_function_offset,
+            global_function_size,
+            code_offset,
+            code_size,
+        };
+        Ok(())
+    }
+
+    fn load_const(&mut self) -> Result<(), VmError> {
+        let const_size = self.metadata.const_size;
+        let const_offset = self.metadata.const_offset;
+
+        let mut cursor = const_offset as usize;
+
+        for i in 1..(const_size + 1) {
+            let const_type =
+                read_u8(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get const_size".into(),
+                })?;
+
+            match const_type {
+                CONST_INT => {
+                    let number = read_i64(&self.bytes, &mut cursor).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error in get number value const".into(),
+                        }
+                    })?;
+
+                    self.const_pool
+                        .insert(i as usize, ConstantValue::Int(number));
+                }
+                CONST_STRING => {
+                    let str_len = read_u32(&self.bytes, &mut cursor).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error in get number value const".into(),
+                        }
+                    })?;
+
+                    let string = read_str_with_len(&self.bytes, &mut cursor, str_len as usize)
+                        .ok_or_else(|| VmError::UnexpectedError {
+                            message: "Error in get string value const".into(),
+                        })?;
+
+                    self.const_pool
+                        .insert(i as usize, ConstantValue::String(string));
+                }
+                CONST_BOOL => {
+                    let bool = read_u8(&self.bytes, &mut cursor).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error in get bool value const".into(),
+                        }
+                    })?;
+
+                    self.const_pool.insert(i as usize, ConstantValue::Bool(bool != 0));
+                }
+                CONST_NONE => {
+                    self.const_pool.insert(i as usize, ConstantValue::None);
+                }
+                CONST_FLOAT => {
+                    let bits = read_i64(&self.bytes, &mut cursor).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error in get float value const".into(),
+                        }
+                    })?;
+
+                    self.const_pool
+                        .insert(i as usize, ConstantValue::Float(f64::from_bits(bits as u64)));
+                }
+                _ => {
+                    return Err(VmError::UnexpectedError {
+                        message: format!("Unexpect const type {}, {}", const_type, i),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_thunk_table(&mut self) -> Result<(), VmError> {
+        let thunk_size = self.metadata.thunk_size;
+        let thunk_offset = self.metadata.thunk_offset;
+
+        let mut cursor = thunk_offset as usize;
+
+        for i in 1..(thunk_size + 1) {
+            let thunk_code_offset =
+                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get thunk code offset".into(),
+                })?;
+
+            self.thunk_table.insert(i as usize, thunk_code_offset * 4);
+        }
+
+        Ok(())
+    }
+
+    fn load_function_table(&mut self) -> Result<(), VmError> {
+        let function_size = self.metadata.function_size;
+        let function_offset = self.metadata.function_offset;
+
+        let mut cursor = function_offset as usize;
+
+        for i in 1..(function_size + 1) {
+            let nargs =
+                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get function_narg".into(),
+                })?;
+            let offset =
+                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get function_body".into(),
+                })?;
+
+            self.function_table.insert(
+                i as usize,
+                Function {
+                    nargs,
+                    offset: offset * 4,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn load_global_function_table(&mut self) -> Result<(), VmError> {
+        let global_function_size = self.metadata.global_function_size;
+        let global_function_offset = self.metadata.global_function_offset;
+
+        let mut cursor = global_function_offset as usize;
+
+        for _ in 1..(global_function_size + 1) {
+            let const_idx =
+                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get const_idx".into(),
+                })?;
+
+            let function_idx =
+                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                    message: "Error in get function_idx".into(),
+                })?;
+
+            let const_name = self.const_pool.get(&(const_idx as usize)).ok_or_else(|| {
+                VmError::UnexpectedError {
+                    message: format!("Error in get const_name {const_idx}"),
+                }
+            })?;
+
+            if let ConstantValue::String(name) = const_name {
+                self.global_function_table
+                    .insert(name.clone(), function_idx as usize);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -58,6 +58,15 @@ impl Register {
             list.push(value);
         }
     }
+    /// Item count of the list or table in `idx`.
+    pub fn collection_len(&self, idx: usize) -> Option<usize> {
+        match &self.regs[idx] {
+            Value::List(list) => Some(list.len()),
+            Value::Table(table) => Some(table.len()),
+            _ => None,
+        }
+    }
+
     pub fn reset_all(&mut self) {
         self.regs = [
                 Value::None,
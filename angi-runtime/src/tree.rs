@@ -80,6 +80,18 @@ where
         Ok(())
     }
 
+    /// Number of direct children.
+    pub fn len(&self) -> usize {
+        match self {
+            Tree::Leaf(_) => 0,
+            Tree::Branchs(branchs) => branchs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn to_json(&self) -> String {
         self.traverse(0)
     }
@@ -18,6 +18,7 @@ use super::tree::Tree;
 #[serde(untagged)]
 pub enum Value {
     Int(i64),
+    Float(f64),
     String(String),
     Bool(bool),
     Table(Box<Tree<Value>>),
@@ -31,6 +32,7 @@ impl Clone for Value {
     fn clone(&self) -> Self {
         match self {
             Self::Int(arg0) => Self::Int(*arg0),
+            Self::Float(arg0) => Self::Float(*arg0),
             Self::String(arg0) => Self::String(arg0.clone()),
             Self::Table(arg0) => Self::Table(arg0.clone()),
             Self::Thunk(arg0) => Self::Thunk(*arg0),
@@ -46,6 +48,7 @@ impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Int(int) => write!(f, "Int({int})"),
+            Value::Float(float) => write!(f, "Float({float:?})"),
             Value::String(string) => write!(f, "String({string})"),
             Value::Table(table) => write!(f, "Table: {:?}", table),
             Value::List(list) => write!(f, "List: {:?}", list),
@@ -62,6 +65,7 @@ impl Value {
         match self {
             Value::String(str) => Ok(str.clone()),
             Value::Int(int) => Ok(int.to_string()),
+            Value::Float(float) => Ok(float.to_string()),
             _ => Err(VmError::ValueTypeMismatch {
                 message: "value not string".into(),
             }),
@@ -76,6 +80,27 @@ impl Value {
         raw.to_value()
     }
 
+    /// Rough number of bytes this value occupies, used for memory caps.
+    pub fn approx_size(&self) -> usize {
+        let heap = match self {
+            Value::String(str) => str.len(),
+            Value::List(list) => list.iter().map(Value::approx_size).sum(),
+            Value::Table(table) => tree_size(table),
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + heap
+    }
+
+    /// Bytes a value adds when stored in a table or a list: its slot and the
+    /// bytes of a string. Nested tables and lists were charged as they were built.
+    pub fn slot_size(&self) -> usize {
+        let heap = match self {
+            Value::String(str) => str.len(),
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + heap
+    }
+
     pub fn resolve_thunk(&mut self, vm: &mut VM) -> Result<(), VmError> {
         if let Value::Thunk(thunk_idx) = self {
             *self = vm.eval_thunk(*thunk_idx)?;
@@ -115,6 +140,41 @@ impl ToValue for i64 {
     }
 }
 
+impl FromValue for f64 {
+    fn from_value(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::Float(float) => Ok(float),
+            Value::Int(int) => Ok(int as f64),
+            v => Err(VmError::ValueTypeMismatch {
+                message: generate_error_message_when_mismatch_casting(v, "f64".into()),
+            }),
+        }
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::Bool(bool) => Ok(bool),
+            v => Err(VmError::ValueTypeMismatch {
+                message: generate_error_message_when_mismatch_casting(v, "bool".into()),
+            }),
+        }
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
 impl FromValue for String {
     fn from_value(v: Value) -> Result<Self, VmError> {
         match v {
@@ -161,6 +221,17 @@ impl<A: ToValue, B: ToValue, C: ToValue> ToArgValue for (A, B, C) {
     }
 }
 
+fn tree_size(tree: &Tree<Value>) -> usize {
+    match tree {
+        Tree::Leaf(Some(value)) => value.approx_size(),
+        Tree::Leaf(None) => 0,
+        Tree::Branchs(branchs) => branchs
+            .iter()
+            .map(|(key, child)| key.len() + tree_size(child))
+            .sum(),
+    }
+}
+
 fn generate_error_message_when_mismatch_casting(src: Value, dest: String) -> String {
     format!("Cannot casting from {src} to {dest}")
 }
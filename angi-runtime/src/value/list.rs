@@ -39,12 +39,18 @@ impl<T> List<T> where T : FromValue {
     }
 
     pub fn force(&mut self, vm: &mut VM) {
-       self.raw_child = Some(
+        self.try_force(vm).unwrap()
+    }
+
+    /// Like `force`, but returns the first item that fails to evaluate.
+    pub fn try_force(&mut self, vm: &mut VM) -> Result<(), VmError> {
+        self.raw_child = Some(
             self.value_child
                 .iter()
-                .map(|v| vm.force::<T>(v.clone()).unwrap())
-                .collect::<Vec<_>>()
+                .map(|v| vm.force::<T>(v.clone()))
+                .collect::<Result<Vec<_>, _>>()?
         );
+        Ok(())
     }
 }
 
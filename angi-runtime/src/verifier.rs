@@ -0,0 +1,297 @@
+use crate::error::VmError;
+use crate::program::Program;
+use angi_ins::{
+    CONST_BITS, METADATA_BYTES, OPCODE_MASK, OPCODE_OFFSET, OpCode, Operand, REG_BITS,
+    extract_opcode,
+};
+use angi_utils::read_byte::read_u32;
+use std::fmt;
+
+/// Why a bytecode image was rejected. `ins` is an instruction index
+/// counted from the start of the code section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    SectionOutOfBounds { section: &'static str },
+    UnknownOpcode { ins: u32, byte: u32 },
+    UnsupportedOpcode { ins: u32, opcode: OpCode },
+    MalformedOperands { ins: u32, byte: u32 },
+    InvalidConst { ins: u32, idx: u32 },
+    InvalidThunk { ins: u32, idx: u32 },
+    InvalidFunction { ins: u32, idx: u32 },
+    InvalidForeignFunction { ins: u32, idx: u32 },
+    InvalidJump { ins: u32, target: u32 },
+    InvalidEntry { table: &'static str, idx: usize, offset: u32 },
+    FallsOffEnd,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::SectionOutOfBounds { section } => {
+                write!(f, "{section} section is out of bounds")
+            }
+            VerifyError::UnknownOpcode { ins, byte } => {
+                write!(f, "unknown opcode in {byte:#010x} at ins {ins}")
+            }
+            VerifyError::UnsupportedOpcode { ins, opcode } => {
+                write!(f, "{opcode:?} at ins {ins} is not supported by this VM")
+            }
+            VerifyError::MalformedOperands { ins, byte } => {
+                write!(f, "unused operand bits set in {byte:#010x} at ins {ins}")
+            }
+            VerifyError::InvalidConst { ins, idx } => write!(f, "no constant #{idx} for ins {ins}"),
+            VerifyError::InvalidThunk { ins, idx } => write!(f, "no thunk #{idx} for ins {ins}"),
+            VerifyError::InvalidFunction { ins, idx } => {
+                write!(f, "no function #{idx} for ins {ins}")
+            }
+            VerifyError::InvalidForeignFunction { ins, idx } => {
+                write!(f, "no foreign function #{idx} for ins {ins}")
+            }
+            VerifyError::InvalidJump { ins, target } => {
+                write!(f, "jump at ins {ins} targets {target}, outside the code")
+            }
+            VerifyError::InvalidEntry { table, idx, offset } => {
+                write!(f, "{table} entry #{idx} points at {offset}, outside the code")
+            }
+            VerifyError::FallsOffEnd => write!(f, "code does not end with a return or jump"),
+        }
+    }
+}
+
+/// Check a loaded program before anything runs it, so `handle_instruction`
+/// only ever sees valid opcodes, indices and jump targets.
+pub(crate) fn verify(program: &Program) -> Result<(), VmError> {
+    verify_sections(program)
+        .and_then(|_| verify_tables(program))
+        .and_then(|_| verify_code(program))
+        .map_err(|error| VmError::InvalidBytecode { error })
+}
+
+fn verify_sections(program: &Program) -> Result<(), VerifyError> {
+    let metadata = program.metadata;
+    let end = |offset: u32, size: u32, entry_bytes: u64| offset as u64 + size as u64 * entry_bytes;
+
+    let sections = [
+        ("const", metadata.const_offset as u64, metadata.thunk_offset as u64),
+        ("thunk", end(metadata.thunk_offset, metadata.thunk_size, 4), metadata.function_offset as u64),
+        (
+            "function",
+            end(metadata.function_offset, metadata.function_size, 8),
+            metadata.global_function_offset as u64,
+        ),
+        (
+            "global function",
+            end(metadata.global_function_offset, metadata.global_function_size, 8),
+            metadata.code_offset as u64,
+        ),
+        // The image ends with a 4 byte total size footer
+        ("code", end(metadata.code_offset, metadata.code_size, 4) + 4, program.bytes.len() as u64),
+    ];
+
+    if (metadata.const_offset as u64) < METADATA_BYTES as u64 {
+        return Err(VerifyError::SectionOutOfBounds { section: "metadata" });
+    }
+
+    for (section, section_end, next_start) in sections {
+        if section_end > next_start {
+            return Err(VerifyError::SectionOutOfBounds { section });
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_tables(program: &Program) -> Result<(), VerifyError> {
+    let code_bytes = program.metadata.code_size * 4;
+
+    for (idx, offset) in &program.thunk_table {
+        if *offset >= code_bytes {
+            return Err(VerifyError::InvalidEntry { table: "thunk", idx: *idx, offset: *offset });
+        }
+    }
+
+    for (idx, function) in &program.function_table {
+        if function.offset >= code_bytes {
+            return Err(VerifyError::InvalidEntry {
+                table: "function",
+                idx: *idx,
+                offset: function.offset,
+            });
+        }
+    }
+
+    for (idx, function_idx) in program.global_function_table.values().enumerate() {
+        if !program.function_table.contains_key(function_idx) {
+            return Err(VerifyError::InvalidEntry {
+                table: "global function",
+                idx,
+                offset: *function_idx as u32,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_code(program: &Program) -> Result<(), VerifyError> {
+    let code_size = program.metadata.code_size;
+    let mut cursor = program.metadata.code_offset as usize;
+    let mut last_opcode = None;
+
+    for ins in 0..code_size {
+        let byte = read_u32(&program.bytes, &mut cursor)
+            .ok_or(VerifyError::SectionOutOfBounds { section: "code" })?;
+
+        let opcode = extract_opcode(byte).ok_or(VerifyError::UnknownOpcode { ins, byte })?;
+
+        let operand_bits: u32 = opcode
+            .layout()
+            .iter()
+            .map(|operand| match operand {
+                Operand::RegAddr => REG_BITS,
+                Operand::ConstIdx => CONST_BITS,
+            })
+            .sum();
+        let operands = byte & !(OPCODE_MASK << OPCODE_OFFSET);
+        if operand_bits < OPCODE_OFFSET && operands >> operand_bits != 0 {
+            return Err(VerifyError::MalformedOperands { ins, byte });
+        }
+
+        let params = opcode.decode(byte);
+        match opcode {
+            OpCode::LOADCONST if !program.const_pool.contains_key(&(params[1] as usize)) => {
+                return Err(VerifyError::InvalidConst { ins, idx: params[1] });
+            }
+            OpCode::MAKETHUNK if !program.thunk_table.contains_key(&(params[1] as usize)) => {
+                return Err(VerifyError::InvalidThunk { ins, idx: params[1] });
+            }
+            OpCode::MAKEFUNC if !program.function_table.contains_key(&(params[1] as usize)) => {
+                return Err(VerifyError::InvalidFunction { ins, idx: params[1] });
+            }
+            OpCode::CFOREIGN if !program.registry_func.contains_idx(params[1]) => {
+                return Err(VerifyError::InvalidForeignFunction { ins, idx: params[1] });
+            }
+            OpCode::JMP | OpCode::JMPSOME | OpCode::JMPIFNOT => {
+                let target = *params.last().unwrap_or(&0);
+                if target >= code_size {
+                    return Err(VerifyError::InvalidJump { ins, target });
+                }
+            }
+            OpCode::LOADIM => return Err(VerifyError::UnsupportedOpcode { ins, opcode }),
+            _ => {}
+        }
+
+        last_opcode = Some(opcode);
+    }
+
+    match last_opcode {
+        Some(OpCode::RETURN | OpCode::JMP | OpCode::TAILCALL) => Ok(()),
+        _ => Err(VerifyError::FallsOffEnd),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VerifyError;
+    use crate::{error::VmError, program::Program};
+    use angi_ins::{MAGIC_NUMBER, METADATA_BYTES, OpCode, VERSION, format_version};
+
+    /// A minimal image: no constants or tables, just `code`.
+    fn image(code: &[[u8; 4]]) -> Vec<u8> {
+        let offset = METADATA_BYTES;
+        let mut bytes = vec![];
+        for word in [MAGIC_NUMBER, VERSION, offset, 0, offset, 0, offset, 0, offset, 0, offset] {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(code.len() as u32).to_be_bytes());
+        for ins in code {
+            bytes.extend_from_slice(ins);
+        }
+        let total = bytes.len() as u32 + 4;
+        bytes.extend_from_slice(&total.to_be_bytes());
+        bytes
+    }
+
+    fn verify_error(code: &[[u8; 4]]) -> VerifyError {
+        match Program::load(image(code)) {
+            Err(VmError::InvalidBytecode { error }) => error,
+            Err(err) => panic!("Expected InvalidBytecode, got {err:?}"),
+            Ok(_) => panic!("Expected InvalidBytecode, image was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_accepts_valid_image() {
+        let code = [OpCode::MAKETABLE.encode(vec![0]), OpCode::RETURN.encode(vec![0])];
+        assert!(Program::load(image(&code)).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_bad_operands() {
+        let ret = OpCode::RETURN.encode(vec![0]);
+
+        assert_eq!(
+            verify_error(&[OpCode::LOADCONST.encode(vec![0, 7]), ret]),
+            VerifyError::InvalidConst { ins: 0, idx: 7 }
+        );
+        assert_eq!(
+            verify_error(&[OpCode::MAKETHUNK.encode(vec![0, 1]), ret]),
+            VerifyError::InvalidThunk { ins: 0, idx: 1 }
+        );
+        assert_eq!(
+            verify_error(&[OpCode::MAKEFUNC.encode(vec![0, 2]), ret]),
+            VerifyError::InvalidFunction { ins: 0, idx: 2 }
+        );
+        assert_eq!(
+            verify_error(&[OpCode::CFOREIGN.encode(vec![0, 999]), ret]),
+            VerifyError::InvalidForeignFunction { ins: 0, idx: 999 }
+        );
+        assert_eq!(
+            verify_error(&[OpCode::JMP.encode(vec![5]), ret]),
+            VerifyError::InvalidJump { ins: 0, target: 5 }
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_opcodes_and_layout() {
+        let ret = OpCode::RETURN.encode(vec![0]);
+
+        assert_eq!(
+            verify_error(&[[0xff, 0, 0, 0], ret]),
+            VerifyError::UnknownOpcode { ins: 0, byte: 0xff00_0000 }
+        );
+        assert_eq!(
+            verify_error(&[[OpCode::RETURN.to_u8(), 0, 1, 0], ret]),
+            VerifyError::MalformedOperands { ins: 0, byte: 0x0600_0100 }
+        );
+        assert_eq!(verify_error(&[OpCode::MAKETABLE.encode(vec![0])]), VerifyError::FallsOffEnd);
+    }
+
+    #[test]
+    fn test_checks_format_version() {
+        let code = [OpCode::RETURN.encode(vec![0])];
+        let with_version = |version: u32| {
+            let mut bytes = image(&code);
+            bytes[4..8].copy_from_slice(&version.to_be_bytes());
+            Program::load(bytes)
+        };
+
+        assert!(with_version(format_version(0, 0)).is_ok());
+        for version in [VERSION + 1, format_version(1, 0)] {
+            match with_version(version) {
+                Err(err @ VmError::IncompatibleVersion { .. }) => {
+                    assert!(err.to_string().contains("newer compiler"))
+                }
+                other => panic!("Expected IncompatibleVersion, got {:?}", other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_truncated_image() {
+        let mut bytes = image(&[OpCode::RETURN.encode(vec![0])]);
+        bytes.truncate(bytes.len() - 6);
+
+        assert!(Program::load(bytes).is_err());
+    }
+}
@@ -1,72 +1,127 @@
 use crate::constant::ConstantValue;
+use crate::debug_info::{DEBUG_INFO_ENTRY, DebugInfo, StackTrace, TraceFrame};
 use crate::error::VmError;
-use crate::function::Function;
+use crate::limits::{ExecutionLimits, Limit};
 use crate::metadata::MetaData;
-use crate::modules::{FunctionRegistry, get_default_foreign_function};
+use crate::program::Program;
 use crate::register::Register;
+use crate::tree::Tree;
 use crate::value::{FromValue, ToArgValue, Value};
 use angi_archive::Extractor;
-use angi_ins::{MAGIC_NUMBER, OpCode, extract_opcode};
+use angi_ins::{OpCode, extract_opcode};
 use angi_utils::log::{Log, LogLevel::DEBUG};
-use angi_utils::read_byte::{read_i64, read_str_with_len, read_u8, read_u32};
+use angi_utils::read_byte::read_u32;
 use angi_utils::read_ins;
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
 use std::fs::File;
 
 pub struct VM {
-    metadata: MetaData,
+    program: Arc<Program>,
     registers: Register,
-    const_pool: HashMap<usize, ConstantValue>,
-    thunk_table: HashMap<usize, u32>,
-    function_table: HashMap<usize, Function>,
-    global_function_table: HashMap<String, usize>,
-    bytes: Vec<u8>,
     args_queue: VecDeque<Value>,
-    registry_func: FunctionRegistry
+    limits: ExecutionLimits,
+    budget: Budget,
+    /// Cursor of the running instruction in each active frame.
+    frames: Vec<usize>,
+    /// `frames` as they were when the last execution failed, innermost first.
+    failed_frames: Vec<usize>,
 }
 
+/// Usage of the call currently running, reset when a top-level call starts.
+#[derive(Default)]
+struct Budget {
+    depth: usize,
+    executed: u64,
+    deadline: Option<Instant>,
+    allocated: usize,
+    /// Size of the arguments the host passes to the next outermost call.
+    input: usize,
+}
+
+/// Checking the clock on every instruction is too costly.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// Thunk indexes start at 1, so 0 is free to key the program's root value.
+const ROOT_THUNK_IDX: u32 = 0;
+
 impl Default for VM {
     fn default() -> Self {
-        VM {
-            registers: Register::new(),
-            const_pool: HashMap::new(),
-            thunk_table: HashMap::new(),
-            function_table: HashMap::new(),
-            global_function_table: HashMap::new(),
-            bytes: vec![],
-            metadata: MetaData::default(),
-            args_queue: VecDeque::new(),
-            registry_func: get_default_foreign_function()
-        }
+        VM::new_from_program(Arc::new(Program::default()))
     }
 }
 
+/// Cloning only bumps the shared program's refcount.
 impl Clone for VM {
     fn clone(&self) -> Self {
-        Self {
+        VM::new_from_program(self.program.clone()).with_limits(self.limits)
+    }
+}
+
+impl VM {
+    /// Fresh execution state (registers, argument queue) over a loaded program.
+    pub fn new_from_program(program: Arc<Program>) -> Self {
+        VM {
+            program,
             registers: Register::new(),
-            const_pool: self.const_pool.clone(),
-            thunk_table: self.thunk_table.clone(),
-            function_table: self.function_table.clone(),
-            global_function_table: self.global_function_table.clone(),
-            bytes: self.bytes.clone(),
-            metadata: self.metadata,
             args_queue: VecDeque::new(),
-            registry_func: self.registry_func.clone()
+            limits: ExecutionLimits::default(),
+            budget: Budget::default(),
+            frames: vec![],
+            failed_frames: vec![],
         }
     }
-}
 
-impl VM {
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> ExecutionLimits {
+        self.limits
+    }
+
+    pub fn program(&self) -> &Arc<Program> {
+        &self.program
+    }
+
+    /// Drop leftover execution state so the VM can serve another request.
+    pub fn reset(&mut self) {
+        self.registers = Register::new();
+        self.args_queue.clear();
+        self.budget = Budget::default();
+        self.frames.clear();
+        self.failed_frames.clear();
+    }
+
     pub fn new_from_bytes(bytes: Vec<u8>) -> Result<Self, VmError> {
         let mut vm = VM::default();
         vm.load(bytes)?;
         Ok(vm)
     }
 
+    pub fn new_from_bytes_with_debug_info(
+        bytes: Vec<u8>,
+        debug_info: DebugInfo,
+    ) -> Result<Self, VmError> {
+        let program = Program::load(bytes)?.with_debug_info(debug_info);
+        Ok(VM::new_from_program(Arc::new(program)))
+    }
+
     pub fn new_from_extractor(extractor: &Extractor) -> Result<Self, VmError> {
-        let mut vm = VM::default();
+        Log::write(DEBUG, "new_from_extractor");
+
+        let program = VM::program_from_extractor(extractor)?;
+        Ok(VM::new_from_program(Arc::new(program)))
+    }
 
+    /// Load the `bytecode` entry, plus the debug info entry when it was archived.
+    fn program_from_extractor(extractor: &Extractor) -> Result<Program, VmError> {
         let bytecode =
             extractor
                 .extract_blob("bytecode".into())
@@ -74,16 +129,15 @@ impl VM {
                     message: "Error in get bytecode".into(),
                 })?;
 
-        Log::write(DEBUG, "new_from_extractor");
+        let program = Program::load(bytecode)?;
 
-        vm.load(bytecode)?;
-
-        Ok(vm)
+        match extractor.extract_blob(DEBUG_INFO_ENTRY.into()) {
+            Some(bytes) => Ok(program.with_debug_info(DebugInfo::from_bytes(&bytes)?)),
+            None => Ok(program),
+        }
     }
 
     pub fn new_from_itself() -> Result<Self, VmError> {
-        let mut vm = VM::default();
-
         let exe_path = std::env::current_exe().map_err(|_| VmError::UnexpectedError {
             message: "error in get file itself".into(),
         })?;
@@ -95,13 +149,6 @@ impl VM {
         let extractor =
             Extractor::init_from_file(file).map_err(|e| VmError::ExtractorError { err: e })?;
 
-        let bytecode =
-            extractor
-                .extract_blob("bytecode".into())
-                .ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get bytecode".into(),
-                })?;
-
         Log::write(DEBUG, "new_from_itself");
 
         // let bytecode_size =
@@ -115,310 +162,203 @@ impl VM {
         //     }
         // })?;
 
-        vm.load(bytecode)?;
-
-        Ok(vm)
+        let program = VM::program_from_extractor(&extractor)?;
+        Ok(VM::new_from_program(Arc::new(program)))
     }
 
     pub fn load(&mut self, bytes: Vec<u8>) -> Result<(), VmError> {
-        self.bytes = bytes;
-        self.load_metadata()?;
-        self.load_const()?;
-        self.load_thunk_table()?;
-        self.load_function_table()?;
-        self.load_global_function_table()?;
+        self.program = Arc::new(Program::load(bytes)?);
         Ok(())
     }
 
+    pub fn get_metadata(&self) -> MetaData {
+        self.program.metadata
+    }
+
     pub fn eval_value(&mut self, str_addr: &str) -> Result<Value, VmError> {
         Log::write(DEBUG, &format!("Try to eval {}", str_addr));
 
-        let code_offset = self.metadata.code_offset;
-        let mut cursor = code_offset as usize;
-        let mut value: Value = Value::None;
+        let mut value = self.eval_root()?;
 
         for key in str_addr.split('.') {
-            value = self.handle_instruction(cursor)?;
-            if let Value::Table(table) = &value {
-                match table.get(vec![key]) {
-                    Some(Value::Thunk(thunk_idx)) => {
-                        if let Some(thunk_offset) = self.thunk_table.get(&(thunk_idx as usize)) {
-                            cursor = (*thunk_offset + code_offset) as usize;
-                        }
-                        value = Value::Thunk(thunk_idx);
-                    }
-                    Some(n) => value = n,
-                    None => {
-                        return Err(VmError::UnexpectedError {
-                            message: format!("property not found: {}", key),
-                        });
+            value = match self.force_value(value)? {
+                Value::Table(table) => table.get(vec![key]).ok_or_else(|| {
+                    VmError::UnexpectedError {
+                        message: format!("property not found: {}", key),
                     }
+                })?,
+                _ => {
+                    return Err(VmError::UnexpectedError {
+                        message: format!("property not found: {}", key),
+                    });
                 }
             };
         }
 
-        if let Value::Thunk(thunk_idx) = value {
-            value = self.eval_thunk(thunk_idx)?;
-        };
-
-        Ok(value)
+        self.force_value(value)
     }
 
     pub fn eval<T>(&mut self, str_addr: &str) -> Result<T, VmError>
     where
         T: FromValue,
     {
-        Log::write(DEBUG, &format!("Try to eval {}", str_addr));
-
-        let code_offset = self.metadata.code_offset;
-        let mut cursor = code_offset as usize;
-        let mut value: Value = Value::None;
+        T::from_value(self.eval_value(str_addr)?)
+    }
 
-        for key in str_addr.split('.') {
-            value = self.handle_instruction(cursor)?;
-            if let Value::Table(table) = &value {
-                match table.get(vec![key]) {
-                    Some(Value::Thunk(thunk_idx)) => {
-                        if let Some(thunk_offset) = self.thunk_table.get(&(thunk_idx as usize)) {
-                            cursor = (*thunk_offset + code_offset) as usize;
-                        }
-                        value = Value::Thunk(thunk_idx);
-                    }
-                    Some(n) => value = n,
-                    None => {
-                        return Err(VmError::UnexpectedError {
-                            message: format!("property not found: {}", key),
-                        });
-                    }
-                }
-            };
+    /// Value of the whole program, cached like a thunk under `ROOT_THUNK_IDX`.
+    fn eval_root(&mut self) -> Result<Value, VmError> {
+        if let Some(value) = self.program.cached_thunk(ROOT_THUNK_IDX) {
+            return Ok(value);
         }
 
-        if let Value::Thunk(thunk_idx) = value {
-            value = self.eval_thunk(thunk_idx)?;
-        };
-
-        T::from_value(value)
+        let cursor = self.program.metadata.code_offset as usize;
+        let value = self.handle_instruction_in_new_frame(cursor)?;
+        self.program.cache_thunk(ROOT_THUNK_IDX, value.clone());
+        Ok(value)
     }
 
-    fn load_metadata(&mut self) -> Result<(), VmError> {
-        let mut cursor = 0;
-        let magic_code =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get magic code".into(),
-            })?;
-
-        if magic_code != MAGIC_NUMBER {
-            return Err(VmError::UnexpectedError {
-                message: "Magic code not suitable".into(),
-            });
-        };
-
-        let version =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get version".into(),
-            })?;
-        let const_offset =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get const_offset".into(),
-            })?;
-        let const_size =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get const_size".into(),
-            })?;
-        let thunk_offset =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get thunk_offset".into(),
-            })?;
-        let thunk_size =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get thunk_size".into(),
-            })?;
-        let function_offset =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get function_offset".into(),
-            })?;
-        let function_size =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get function_size".into(),
-            })?;
-        let global_function_offset =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get function_offset".into(),
-            })?;
-        let global_function_size =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get function_size".into(),
-            })?;
-        let code_offset =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in get code_offset".into(),
-            })?;
-        let code_size =
-            read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                message: "Error in code_size".into(),
-            })?;
-
-        self.metadata = MetaData {
-            magic_code,
-            version,
-            const_offset,
-            const_size,
-            thunk_offset,
-            thunk_size,
-            function_offset,
-            function_size,
-            global_function_offset,
-            global_function_size,
-            code_offset,
-            code_size,
-        };
-        Ok(())
+    /// Drop every memoized thunk value, they are recomputed on next use. The
+    /// cache lives in the shared `Program`, so this clears it for every VM
+    /// running the same program, clones and pooled VMs included.
+    pub fn clear_thunk_cache(&mut self) {
+        self.program.clear_thunk_cache();
     }
 
-    pub fn get_metadata(&self) -> MetaData {
-        self.metadata
+    pub fn get_thunk_table(&self) -> HashMap<usize, u32> {
+        self.program.thunk_table.clone()
     }
 
-    pub fn load_const(&mut self) -> Result<(), VmError> {
-        let const_size = self.metadata.const_size;
-        let const_offset = self.metadata.const_offset;
+    pub fn get_const(&self, idx: usize) -> Option<&ConstantValue> {
+        self.program.const_pool.get(&idx)
+    }
 
-        let mut cursor = const_offset as usize;
+    /// Run code from `cursor` until RETURN. Limits apply to the outermost
+    /// call as a whole, nested calls and forced thunks share its budget.
+    pub fn handle_instruction(&mut self, cursor: usize) -> Result<Value, VmError> {
+        if self.budget.depth == 0 {
+            self.budget.executed = 0;
+            self.budget.allocated = 0;
+            self.budget.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+            self.failed_frames.clear();
+
+            let input = std::mem::take(&mut self.budget.input);
+            self.charge_memory(input)?;
+        }
 
-        for i in 1..(const_size + 1) {
-            let const_type =
-                read_u8(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get const_size".into(),
-                })?;
+        self.frames.push(cursor);
+        self.budget.depth += 1;
+        let result = self.run(cursor);
+        self.budget.depth -= 1;
 
-            match const_type {
-                0 => {
-                    let number = read_i64(&self.bytes, &mut cursor).ok_or_else(|| {
-                        VmError::UnexpectedError {
-                            message: "Error in get number value const".into(),
-                        }
-                    })?;
+        // The innermost failing frame sees the whole stack, outer ones keep it
+        if result.is_err() && self.failed_frames.is_empty() {
+            self.failed_frames = self.frames.iter().rev().copied().collect();
+        }
+        self.frames.pop();
 
-                    self.const_pool
-                        .insert(i as usize, ConstantValue::Int(number));
-                }
-                1 => {
-                    let str_len = read_u32(&self.bytes, &mut cursor).ok_or_else(|| {
-                        VmError::UnexpectedError {
-                            message: "Error in get number value const".into(),
-                        }
-                    })?;
+        result
+    }
 
-                    let string = read_str_with_len(&self.bytes, &mut cursor, str_len as usize)
-                        .ok_or_else(|| VmError::UnexpectedError {
-                            message: "Error in get string value const".into(),
-                        })?;
+    /// Angi-level stack trace of the last failed execution. Needs the
+    /// program's debug info to map instructions back to source.
+    pub fn stack_trace(&self) -> Option<StackTrace> {
+        let debug_info = self.program.debug_info()?;
+        if self.failed_frames.is_empty() {
+            return None;
+        }
 
-                    self.const_pool
-                        .insert(i as usize, ConstantValue::String(string));
-                }
-                _ => {
-                    return Err(VmError::UnexpectedError {
-                        message: format!("Unexpect const type {}, {}", const_type, i),
-                    });
+        let code_offset = self.program.metadata.code_offset as usize;
+        let frames = self
+            .failed_frames
+            .iter()
+            .map(|cursor| {
+                let ins = (cursor.saturating_sub(code_offset) / 4) as u32;
+                TraceFrame {
+                    function: debug_info.scope_name(ins).to_string(),
+                    file: debug_info.file.clone(),
+                    location: debug_info.location(ins),
                 }
-            }
-        }
+            })
+            .collect();
 
-        Ok(())
+        Some(StackTrace { frames })
     }
 
-    pub fn load_thunk_table(&mut self) -> Result<(), VmError> {
-        let thunk_size = self.metadata.thunk_size;
-        let thunk_offset = self.metadata.thunk_offset;
-
-        let mut cursor = thunk_offset as usize;
+    fn charge_instruction(&mut self) -> Result<(), VmError> {
+        self.budget.executed += 1;
 
-        for i in 1..(thunk_size + 1) {
-            let thunk_code_offset =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get thunk code offset".into(),
-                })?;
+        if let Some(max) = self.limits.max_instructions
+            && self.budget.executed > max
+        {
+            return Err(VmError::LimitExceeded { limit: Limit::Instructions(max) });
+        }
 
-            self.thunk_table.insert(i as usize, thunk_code_offset * 4);
+        if self.budget.executed.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && let (Some(deadline), Some(timeout)) = (self.budget.deadline, self.limits.timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(VmError::LimitExceeded { limit: Limit::Time(timeout) });
         }
 
         Ok(())
     }
 
-    pub fn load_function_table(&mut self) -> Result<(), VmError> {
-        let function_size = self.metadata.function_size;
-        let function_offset = self.metadata.function_offset;
-
-        let mut cursor = function_offset as usize;
-
-        for i in 1..(function_size + 1) {
-            let nargs =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get function_narg".into(),
-                })?;
-            let offset =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get function_body".into(),
-                })?;
+    fn charge_memory(&mut self, bytes: usize) -> Result<(), VmError> {
+        self.budget.allocated = self.budget.allocated.saturating_add(bytes);
 
-            self.function_table.insert(
-                i as usize,
-                Function {
-                    nargs,
-                    offset: offset * 4,
-                },
-            );
+        match self.limits.max_memory {
+            Some(max) if self.budget.allocated > max => {
+                Err(VmError::LimitExceeded { limit: Limit::Memory(max) })
+            }
+            _ => Ok(()),
         }
-
-        Ok(())
     }
 
-    pub fn load_global_function_table(&mut self) -> Result<(), VmError> {
-        let global_function_size = self.metadata.global_function_size;
-        let global_function_offset = self.metadata.global_function_offset;
-
-        let mut cursor = global_function_offset as usize;
-
-        for _ in 1..(global_function_size + 1) {
-            let const_idx =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get const_idx".into(),
-                })?;
-
-            let function_idx =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
-                    message: "Error in get function_idx".into(),
-                })?;
+    fn check_value_len(&self, len: usize) -> Result<(), VmError> {
+        match self.limits.max_value_len {
+            Some(max) if len > max => Err(VmError::LimitExceeded { limit: Limit::ValueLen(max) }),
+            _ => Ok(()),
+        }
+    }
 
-            let const_name = self.const_pool.get(&(const_idx as usize)).ok_or_else(|| {
-                VmError::UnexpectedError {
-                    message: format!("Error in get const_name {const_idx}"),
-                }
-            })?;
+    /// `check_value_len` on every string, list and table inside `value`.
+    fn check_value_lens(&self, value: &Value) -> Result<(), VmError> {
+        if self.limits.max_value_len.is_none() {
+            return Ok(());
+        }
 
-            if let ConstantValue::String(name) = const_name {
-                self.global_function_table
-                    .insert(name.clone(), function_idx as usize);
+        match value {
+            Value::String(str) => self.check_value_len(str.len()),
+            Value::List(items) => {
+                self.check_value_len(items.len())?;
+                items.iter().try_for_each(|item| self.check_value_lens(item))
             }
+            Value::Table(tree) => self.check_tree_lens(tree),
+            _ => Ok(()),
         }
-
-        Ok(())
     }
 
-    pub fn get_thunk_table(&self) -> HashMap<usize, u32> {
-        self.thunk_table.clone()
-    }
-
-    pub fn get_const(&self, idx: usize) -> Option<&ConstantValue> {
-        self.const_pool.get(&idx)
+    fn check_tree_lens(&self, tree: &Tree<Value>) -> Result<(), VmError> {
+        match tree {
+            Tree::Leaf(Some(value)) => self.check_value_lens(value),
+            Tree::Leaf(None) => Ok(()),
+            Tree::Branchs(branchs) => {
+                self.check_value_len(branchs.len())?;
+                branchs.values().try_for_each(|child| self.check_tree_lens(child))
+            }
+        }
     }
 
-    pub fn handle_instruction(&mut self, mut cursor: usize) -> Result<Value, VmError> {
+    fn run(&mut self, mut cursor: usize) -> Result<Value, VmError> {
         loop {
+            self.charge_instruction()?;
+
+            if let Some(frame) = self.frames.last_mut() {
+                *frame = cursor;
+            }
+
             let ins =
-                read_u32(&self.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
+                read_u32(&self.program.bytes, &mut cursor).ok_or_else(|| VmError::UnexpectedError {
                     message: "Error in get ins value".into(),
                 })?;
 
@@ -444,10 +384,8 @@ impl VM {
                             message: "Error in get v2 in ADD".into(),
                         }
                     })?;
-                    let v1_int = v1.val::<i64>()?;
-                    let v2_int = v2.val::<i64>()?;
-                    let result = v1_int + v2_int;
-                    self.registers.set(result_reg as usize, Value::Int(result));
+                    let result = arithmetic(opcode, v1, v2)?;
+                    self.registers.set(result_reg as usize, result);
                 }
                 OpCode::SUB => {
                     let params = OpCode::SUB.decode(ins);
@@ -465,12 +403,9 @@ impl VM {
                         }
                     })?;
 
-                    let v1_int = v1.val::<i64>()?;
-                    let v2_int = v2.val::<i64>()?;
-
-                    let result = v1_int - v2_int;
+                    let result = arithmetic(opcode, v1, v2)?;
 
-                    self.registers.set(result_reg as usize, Value::Int(result));
+                    self.registers.set(result_reg as usize, result);
                 }
                 OpCode::MUL => {
                     let params = OpCode::MUL.decode(ins);
@@ -488,12 +423,9 @@ impl VM {
                         }
                     })?;
 
-                    let v1_int = v1.val::<i64>()?;
-                    let v2_int = v2.val::<i64>()?;
+                    let result = arithmetic(opcode, v1, v2)?;
 
-                    let result = v1_int * v2_int;
-
-                    self.registers.set(result_reg as usize, Value::Int(result));
+                    self.registers.set(result_reg as usize, result);
                 }
                 OpCode::DIV => {
                     let params = OpCode::DIV.decode(ins);
@@ -511,41 +443,35 @@ impl VM {
                         }
                     })?;
 
-                    let v1_int = v1.val::<i64>()?;
-                    let v2_int = v2.val::<i64>()?;
-
-                    if v2_int == 0 {
-                        return Err(VmError::UnexpectedError {
-                            message: "Division by zero".into(),
-                        });
-                    }
-
-                    let result = v1_int / v2_int;
+                    let result = arithmetic(opcode, v1, v2)?;
 
-                    self.registers.set(result_reg as usize, Value::Int(result));
+                    self.registers.set(result_reg as usize, result);
                 }
                 OpCode::MAKETABLE => {
                     let params = OpCode::MAKETABLE.decode(ins);
+                    self.charge_memory(std::mem::size_of::<Value>())?;
                     self.registers.set_new_table(params[0] as usize);
                 }
                 OpCode::MAKELIST => {
                     let params = OpCode::MAKELIST.decode(ins);
+                    self.charge_memory(std::mem::size_of::<Value>())?;
                     self.registers.set(params[0] as usize, Value::List(vec![]));
                 }
                 OpCode::LOADCONST => {
                     let params = OpCode::LOADCONST.decode(ins);
-                    let constant = self.const_pool.get(&(params[1] as usize)).ok_or_else(|| {
+                    let constant = self.program.const_pool.get(&(params[1] as usize)).ok_or_else(|| {
                         VmError::UnexpectedError {
                             message: "Error in get constant value".into(),
                         }
                     })?;
                     match constant {
-                        ConstantValue::Int(int) => {
-                            self.registers.set(params[0] as usize, Value::Int(*int));
-                        }
                         ConstantValue::String(str) => {
-                            self.registers
-                                .set(params[0] as usize, Value::String(str.to_string()));
+                            let str = str.to_string();
+                            self.charge_memory(str.len())?;
+                            self.registers.set(params[0] as usize, Value::String(str));
+                        }
+                        constant => {
+                            self.registers.set(params[0] as usize, constant.to_value());
                         }
                     }
                 }
@@ -562,8 +488,12 @@ impl VM {
                             message: "Error in get value in SAT".into(),
                         }
                     })?;
-                    self.registers
-                        .set_attr_table(table_reg as usize, key.to_string()?, value);
+                    let key = key.to_string()?;
+                    self.charge_memory(key.len() + value.slot_size())?;
+                    self.registers.set_attr_table(table_reg as usize, key, value);
+                    if let Some(len) = self.registers.collection_len(table_reg as usize) {
+                        self.check_value_len(len)?;
+                    }
                 }
                 OpCode::ADDLIST => {
                     let params = OpCode::ADDLIST.decode(ins);
@@ -573,7 +503,11 @@ impl VM {
                             message: "Error in get value in ADL".into(),
                         }
                     })?;
+                    self.charge_memory(value.slot_size())?;
                     self.registers.add_to_list(list_reg as usize, value);
+                    if let Some(len) = self.registers.collection_len(list_reg as usize) {
+                        self.check_value_len(len)?;
+                    }
                 }
                 OpCode::MAKEFUNC => {
                     let params = OpCode::MAKEFUNC.decode(ins);
@@ -604,48 +538,26 @@ impl VM {
                 }
                 OpCode::CFOREIGN => {
                     let params = OpCode::CFOREIGN.decode(ins);
-                    let result = self.registry_func.resolve(params[1] as u32, self.args_queue.clone().into()).map_err(|e| VmError::UnexpectedError {
+                    let result = self.program.registry_func.resolve(params[1] as u32, self.args_queue.clone().into()).map_err(|e| VmError::UnexpectedError {
                             message: format!("error {:?}", e),
                         })?;
+                    self.charge_memory(result.approx_size())?;
+                    if let Value::String(str) = &result {
+                        self.check_value_len(str.len())?;
+                    }
                     self.registers.set(params[0] as usize, result);
                 }
                 OpCode::CALL => {
                     let params = OpCode::CALL.decode(ins);
-                    match self.registers.get(params[1] as usize).ok_or_else(|| {
+                    let callee = self.registers.get(params[1] as usize).ok_or_else(|| {
                         VmError::UnexpectedError {
-                            message: "Error in get value in PUS".into(),
+                            message: "Error in get value in CALL".into(),
                         }
-                    })? {
-                        Value::String(name) => {
-                            let function_idx =
-                                self.global_function_table.get(&name).ok_or_else(|| {
-                                    VmError::NotFoundFunction {
-                                        message: format!("not found {name}"),
-                                    }
-                                })?;
-                            let function =
-                                self.function_table.get(function_idx).ok_or_else(|| {
-                                    VmError::NotFoundFunction {
-                                        message: format!("not found {function_idx}"),
-                                    }
-                                })?;
-                            let cursor = (function.offset + self.metadata.code_offset) as usize;
-                            let result = self.handle_instruction(cursor)?;
-                            self.registers.set(params[0] as usize, result);
-                        }
-                        Value::Function(function_idx) => {
-                            let function = self
-                                .function_table
-                                .get(&(function_idx as usize))
-                                .ok_or_else(|| VmError::NotFoundFunction {
-                                    message: format!("not found {function_idx}"),
-                                })?;
-                            let cursor = (function.offset + self.metadata.code_offset) as usize;
-                            let result = self.handle_instruction(cursor)?;
-                            self.registers.set(params[0] as usize, result);
-                        }
-                        _ => {}
-                    }
+                    })?;
+                    let cursor = self.resolve_function_cursor(callee)?;
+                    let result = self.handle_instruction_in_new_frame(cursor)?;
+                    self.args_queue.clear();
+                    self.registers.set(params[0] as usize, result);
                 }
                 OpCode::MAKETHUNK => {
                     let params = OpCode::MAKETHUNK.decode(ins);
@@ -671,6 +583,8 @@ impl VM {
 
                     let mut v1_str = v1.to_string()?;
                     let v2_str = v2.to_string()?;
+                    self.check_value_len(v1_str.len() + v2_str.len())?;
+                    self.charge_memory(v1_str.len() + v2_str.len())?;
                     v1_str.push_str(&v2_str);
 
                     self.registers.set(reg_dist as usize, Value::String(v1_str));
@@ -690,6 +604,7 @@ impl VM {
                             message: "Error get v in GETFIELD".into(),
                         }
                     })?;
+                    let v1 = self.force_value(v1)?;
 
                     let v2 = self.registers.get(params[2] as usize).ok_or_else(|| {
                         VmError::UnexpectedError {
@@ -702,18 +617,129 @@ impl VM {
                             let value =
                                 table
                                     .get(vec![&name])
-                                    .ok_or_else(|| VmError::NotFoundFunction {
+                                    .ok_or_else(|| VmError::NotFoundAttribute {
                                         message: format!("Table not have {} attribute", name),
                                     })?;
                             self.registers.set(params[0] as usize, value);
                         }
-                        _ => {
-                            return Err(VmError::UnexpectedError {
-                                message: "Unexpect opcode".into(),
+                        (Value::List(list), Value::Int(idx)) => {
+                            let value = usize::try_from(idx)
+                                .ok()
+                                .and_then(|idx| list.get(idx).cloned())
+                                .ok_or_else(|| VmError::NotFoundAttribute {
+                                    message: format!("List not have index {}", idx),
+                                })?;
+                            self.registers.set(params[0] as usize, value);
+                        }
+                        (v, key) => {
+                            return Err(VmError::ValueTypeMismatch {
+                                message: format!("Cannot access {key} of {v}"),
                             });
                         }
                     }
                 }
+                OpCode::OPTFIELD => {
+                    let params = OpCode::OPTFIELD.decode(ins);
+                    let v1 = self.registers.get(params[1] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error get v in OPTFIELD".into(),
+                        }
+                    })?;
+                    let v1 = self.force_value(v1)?;
+
+                    let v2 = self.registers.get(params[2] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error get v in OPTFIELD".into(),
+                        }
+                    })?;
+
+                    let value = match (v1, v2) {
+                        (Value::Table(table), Value::String(name)) => {
+                            table.get(vec![&name]).unwrap_or(Value::None)
+                        }
+                        (Value::List(list), Value::Int(idx)) => usize::try_from(idx)
+                            .ok()
+                            .and_then(|idx| list.get(idx).cloned())
+                            .unwrap_or(Value::None),
+                        (Value::None, _) => Value::None,
+                        (v, _) => {
+                            return Err(VmError::ValueTypeMismatch {
+                                message: format!("Cannot access field of {v}"),
+                            });
+                        }
+                    };
+                    self.registers.set(params[0] as usize, value);
+                }
+                OpCode::MOVE => {
+                    let params = OpCode::MOVE.decode(ins);
+                    let value = self.registers.get(params[1] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error get v in MOVE".into(),
+                        }
+                    })?;
+                    self.registers.set(params[0] as usize, value);
+                }
+                OpCode::JMPSOME => {
+                    let params = OpCode::JMPSOME.decode(ins);
+                    let value = self.registers.get(params[0] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error get v in JMPSOME".into(),
+                        }
+                    })?;
+                    if !matches!(value, Value::None) {
+                        cursor = (params[1] * 4 + self.program.metadata.code_offset) as usize;
+                    }
+                }
+                OpCode::EQ | OpCode::NE | OpCode::LT | OpCode::LE => {
+                    let params = opcode.decode(ins);
+                    let v1 = self.registers.get(params[1] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: format!("Error get v1 in {:?}", opcode),
+                        }
+                    })?;
+                    let v2 = self.registers.get(params[2] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: format!("Error get v2 in {:?}", opcode),
+                        }
+                    })?;
+                    let result = compare_values(opcode, v1, v2)?;
+                    self.registers.set(params[0] as usize, Value::Bool(result));
+                }
+                OpCode::JMP => {
+                    let params = OpCode::JMP.decode(ins);
+                    cursor = (params[0] * 4 + self.program.metadata.code_offset) as usize;
+                }
+                OpCode::JMPIFNOT => {
+                    let params = OpCode::JMPIFNOT.decode(ins);
+                    let value = self.registers.get(params[0] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error get v in JMPIFNOT".into(),
+                        }
+                    })?;
+                    let is_true = match value {
+                        Value::Bool(b) => b,
+                        Value::None => false,
+                        v => {
+                            return Err(VmError::ValueTypeMismatch {
+                                message: format!("Condition must be Bool, found {v}"),
+                            })
+                        }
+                    };
+                    if !is_true {
+                        cursor = (params[1] * 4 + self.program.metadata.code_offset) as usize;
+                    }
+                }
+                OpCode::TAILCALL => {
+                    let params = OpCode::TAILCALL.decode(ins);
+                    let callee = self.registers.get(params[0] as usize).ok_or_else(|| {
+                        VmError::UnexpectedError {
+                            message: "Error in get value in TAILCALL".into(),
+                        }
+                    })?;
+                    // The pending args become the new params, the frame is reused
+                    cursor = self.resolve_function_cursor(callee)?;
+                    self.registers = Register::default();
+                }
                 _ => {
                     return Err(VmError::UnexpectedError {
                         message: "Unexpect opcode".into(),
@@ -723,6 +749,28 @@ impl VM {
         }
     }
 
+    fn resolve_function_cursor(&mut self, callee: Value) -> Result<usize, VmError> {
+        let function_idx = match self.force_value(callee)? {
+            Value::String(name) => *self.program.global_function_table.get(&name).ok_or_else(|| {
+                VmError::NotFoundFunction {
+                    message: format!("not found {name}"),
+                }
+            })?,
+            Value::Function(function_idx) => function_idx as usize,
+            v => {
+                return Err(VmError::ValueTypeMismatch {
+                    message: format!("Cannot call {v}, it is not a function"),
+                })
+            }
+        };
+        let function = self.program.function_table.get(&function_idx).ok_or_else(|| {
+            VmError::NotFoundFunction {
+                message: format!("not found {function_idx}"),
+            }
+        })?;
+        Ok((function.offset + self.program.metadata.code_offset) as usize)
+    }
+
     pub fn force<T>(&mut self, mut v: Value) -> Result<T, VmError>
     where
         T: FromValue,
@@ -734,29 +782,62 @@ impl VM {
         T::from_value(v)
     }
 
+    /// Force a thunk with call-by-need semantics: the first result is cached.
+    /// Thunks are only emitted outside function bodies, so their value never
+    /// depends on call arguments and stays valid until the program is reloaded.
     pub fn eval_thunk(&mut self, thunk_idx: u32) -> Result<Value, VmError> {
-        let code_offset = self.metadata.code_offset;
+        if let Some(value) = self.program.cached_thunk(thunk_idx) {
+            return Ok(value);
+        }
+
+        let code_offset = self.program.metadata.code_offset;
         let mut value: Value = Value::None;
-        if let Some(thunk_offset) = self.thunk_table.get(&(thunk_idx as usize)) {
+        if let Some(thunk_offset) = self.program.thunk_table.get(&(thunk_idx as usize)) {
             let cursor = (*thunk_offset + code_offset) as usize;
-            value = self.handle_instruction(cursor)?;
+            value = self.handle_instruction_in_new_frame(cursor)?;
+            self.program.cache_thunk(thunk_idx, value.clone());
         };
 
         Ok(value)
     }
 
+    fn force_value(&mut self, value: Value) -> Result<Value, VmError> {
+        match value {
+            Value::Thunk(thunk_idx) => self.eval_thunk(thunk_idx),
+            value => Ok(value),
+        }
+    }
+
+    /// Run code with a fresh register file, so the caller's registers survive.
+    fn handle_instruction_in_new_frame(&mut self, cursor: usize) -> Result<Value, VmError> {
+        let saved_registers = std::mem::take(&mut self.registers);
+        let result = self.handle_instruction(cursor);
+        self.registers = saved_registers;
+        result
+    }
+
     pub fn eval_function<T>(&mut self, function_idx: u32, args: T) -> Result<Value, VmError>
     where
         T: ToArgValue,
     {
-        let code_offset = self.metadata.code_offset;
+        let code_offset = self.program.metadata.code_offset;
         let mut value: Value = Value::None;
 
-        for arg in args.to_value() {
+        let args = args.to_value();
+
+        // Values built by the host, like a request, count against the call
+        if self.budget.depth == 0 {
+            for arg in &args {
+                self.check_value_lens(arg)?;
+            }
+            self.budget.input = args.iter().map(Value::approx_size).sum();
+        }
+
+        for arg in args {
             self.push_arg(arg);
         }
 
-        if let Some(function) = self.function_table.get(&(function_idx as usize)) {
+        if let Some(function) = self.program.function_table.get(&(function_idx as usize)) {
             let cursor = (function.offset + code_offset) as usize;
             value = self.handle_instruction(cursor)?;
         };
@@ -770,3 +851,83 @@ impl VM {
         self.args_queue.push_back(value);
     }
 }
+
+/// ADD, SUB, MUL and DIV. Two ints give an int, anything with a float
+/// gives a float. Int overflow and division by zero are errors.
+fn arithmetic(opcode: OpCode, v1: Value, v2: Value) -> Result<Value, VmError> {
+    if let (Value::Int(a), Value::Int(b)) = (&v1, &v2) {
+        let result = match opcode {
+            OpCode::ADD => a.checked_add(*b),
+            OpCode::SUB => a.checked_sub(*b),
+            OpCode::MUL => a.checked_mul(*b),
+            _ => {
+                if *b == 0 {
+                    return Err(VmError::UnexpectedError {
+                        message: "Division by zero".into(),
+                    });
+                }
+                a.checked_div(*b)
+            }
+        };
+        return result.map(Value::Int).ok_or_else(|| VmError::InstructionExecution {
+            message: format!("Integer overflow in {a} {} {b}", operator_symbol(opcode)),
+        });
+    }
+
+    let a = v1.val::<f64>()?;
+    let b = v2.val::<f64>()?;
+    let result = match opcode {
+        OpCode::ADD => a + b,
+        OpCode::SUB => a - b,
+        OpCode::MUL => a * b,
+        _ => a / b,
+    };
+    Ok(Value::Float(result))
+}
+
+fn operator_symbol(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::ADD => "+",
+        OpCode::SUB => "-",
+        OpCode::MUL => "*",
+        _ => "/",
+    }
+}
+
+fn compare_values(opcode: OpCode, v1: Value, v2: Value) -> Result<bool, VmError> {
+    let is_equal = match (&v1, &v2) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::None, Value::None) => true,
+        _ => false,
+    };
+
+    match opcode {
+        OpCode::EQ => Ok(is_equal),
+        OpCode::NE => Ok(!is_equal),
+        _ => {
+            let ordering = match (&v1, &v2) {
+                (Value::Int(a), Value::Int(b)) => a.cmp(b),
+                (Value::String(a), Value::String(b)) => a.cmp(b),
+                (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                    let (a, b) = (v1.clone().val::<f64>()?, v2.clone().val::<f64>()?);
+                    a.partial_cmp(&b).ok_or_else(|| VmError::ValueTypeMismatch {
+                        message: format!("Cannot compare {v1} with {v2}"),
+                    })?
+                }
+                _ => {
+                    return Err(VmError::ValueTypeMismatch {
+                        message: format!("Cannot compare {v1} with {v2}"),
+                    })
+                }
+            };
+            match opcode {
+                OpCode::LT => Ok(ordering.is_lt()),
+                _ => Ok(ordering.is_le()),
+            }
+        }
+    }
+}
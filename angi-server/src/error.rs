@@ -0,0 +1,27 @@
+use std::fmt;
+use std::io;
+
+use angi_runtime::error::VmError;
+
+#[derive(Debug)]
+pub enum ServeError {
+    Runtime { err: VmError },
+    Bind { addr: String, err: io::Error },
+    Io { err: io::Error },
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServeError::Runtime { err } => write!(f, "{err}"),
+            ServeError::Bind { addr, err } => write!(f, "Cannot listen on {addr}: {err}"),
+            ServeError::Io { err } => write!(f, "Server stopped: {err}"),
+        }
+    }
+}
+
+impl From<VmError> for ServeError {
+    fn from(err: VmError) -> Self {
+        ServeError::Runtime { err }
+    }
+}
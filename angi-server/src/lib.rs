@@ -0,0 +1,293 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+mod error;
+pub mod logger;
+mod reload;
+mod static_files;
+mod utils;
+
+use axum::middleware;
+use axum::{
+    Router,
+    response::Html,
+    routing::get,
+    Json,
+};
+use angi_runtime::value::{Function, List, Table, Value};
+use angi_runtime::{error::VmError, limits::ExecutionLimits, modules, pool::VmPool, vm::VM};
+use angi_archive::StaticStore;
+use colored::Colorize;
+use tower_http::services::ServeDir;
+
+use crate::static_files::static_handler;
+use crate::utils::make_vm_handler;
+
+pub use crate::error::ServeError;
+pub use crate::reload::Reloader;
+
+pub type ArcStore = Arc<StaticStore>;
+
+/// Serve the program loaded in `vm` on the port from its config, until the
+/// process is stopped. Used by the built binary and by `angi run`.
+pub async fn serve(mut vm: VM, static_store: ArcStore) -> Result<(), ServeError> {
+    let listener = listen(&mut vm).await?;
+
+    use_bundled_templates(&static_store);
+
+    axum::serve(listener, app(vm, static_store)?)
+        .await
+        .map_err(|err| ServeError::Io { err })
+}
+
+/// Like [`serve`], but the program can be swapped through `reloader` while
+/// the server runs.
+pub async fn serve_reloadable(mut vm: VM, reloader: Reloader) -> Result<(), ServeError> {
+    let listener = listen(&mut vm).await?;
+
+    reloader.reload(vm)?;
+
+    axum::serve(listener, reloader.router())
+        .await
+        .map_err(|err| ServeError::Io { err })
+}
+
+async fn listen(vm: &mut VM) -> Result<tokio::net::TcpListener, ServeError> {
+    logger::info("Start the server");
+
+    let port = vm.eval::<i64>("port")?;
+
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .map_err(|err| ServeError::Bind { addr, err })?;
+
+    logger::log_startup(
+        "My App",
+        "0.1.0",
+        port
+    );
+
+    logger::info(format!(
+        "{} {}",
+        "Listening on".bold(),
+        listener.local_addr().map_err(|err| ServeError::Io { err })?
+    ));
+
+    Ok(listener)
+}
+
+#[allow(unused_variables)]
+fn app(vm: VM, static_store: ArcStore) -> Result<Router, VmError> {
+
+    // let mut ready_vm = vm.lock().unwrap();
+    let mut ready_vm = vm.clone();
+
+    // `static` is optional in the config
+    let static_config = ready_vm.eval::<Table>("static").ok();
+    let static_dir = static_config
+        .as_ref()
+        .and_then(|static_config| static_config.get::<String>("dir"));
+    let static_prefix = static_config
+        .as_ref()
+        .and_then(|static_config| static_config.get::<String>("prefix"))
+        .and_then(|prefix| normalize_prefix(&prefix));
+    let static_max_age = config_usize(&mut ready_vm, "static.max_age").unwrap_or(0);
+
+    let pool_size = pool_size(&mut ready_vm);
+    let limits = execution_limits(&mut ready_vm);
+    let max_body_bytes = config_usize(&mut ready_vm, "server.max_body_bytes")
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+
+    drop(ready_vm);
+
+    let pool = Arc::new(VmPool::new(vm.program().clone(), pool_size).with_limits(limits));
+    logger::info(format!("VM pool size: {}", pool.size()));
+
+    let router = build_router(vm.clone(), pool, max_body_bytes).expect("Error in build router");
+
+    // Files bundled by `angi build` win, `angi run` serves the dir from disk
+    let router = if static_store.has_static_files() {
+        router.fallback(static_handler(static_store, static_prefix, static_max_age))
+    } else {
+        match (static_dir, static_prefix) {
+            (Some(dir), Some(prefix)) => router.nest_service(&prefix, ServeDir::new(dir)),
+            (Some(dir), None) => router.fallback_service(ServeDir::new(dir)),
+            (None, _) => router,
+        }
+    };
+
+    Ok(router.layer(middleware::from_fn(logger::request_logger)))
+}
+
+/// `assets/` to `/assets`, `None` for the root.
+fn normalize_prefix(prefix: &str) -> Option<String> {
+    let prefix = prefix.trim_matches('/');
+    (!prefix.is_empty()).then(|| format!("/{prefix}"))
+}
+
+/// Render templates bundled in `static_store`, falling back to the disk for
+/// those that are not.
+fn use_bundled_templates(static_store: &ArcStore) {
+    let static_store = static_store.clone();
+    modules::set_template_loader(Box::new(move |path| static_store.template(path)));
+}
+
+/// `server.pool_size` from the config, how many requests run the program at
+/// once, defaulting to the number of cores.
+fn pool_size(vm: &mut VM) -> usize {
+    vm.eval::<i64>("server.pool_size")
+        .ok()
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+}
+
+/// Request bodies larger than this are rejected unless `server.max_body_bytes` says otherwise.
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Defaults for the `server.max_*` limits, high enough for ordinary pages
+/// while still stopping a runaway handler.
+const DEFAULT_MAX_INSTRUCTIONS: u64 = 100_000_000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_MEMORY: usize = 256 * 1024 * 1024;
+const DEFAULT_MAX_VALUE_LEN: usize = 16 * 1024 * 1024;
+
+/// Limits from the `server` table of the config, a negative value lifts one.
+fn execution_limits(vm: &mut VM) -> ExecutionLimits {
+    let max_instructions = match vm.eval::<i64>("server.max_instructions") {
+        Ok(max) => u64::try_from(max).ok(),
+        Err(_) => Some(DEFAULT_MAX_INSTRUCTIONS),
+    };
+
+    let timeout = match vm.eval::<i64>("server.timeout_ms") {
+        Ok(ms) => u64::try_from(ms).ok().map(Duration::from_millis),
+        Err(_) => Some(DEFAULT_TIMEOUT),
+    };
+
+    let max_memory = config_limit(vm, "server.max_memory", DEFAULT_MAX_MEMORY);
+    let max_value_len = config_limit(vm, "server.max_value_len", DEFAULT_MAX_VALUE_LEN);
+
+    ExecutionLimits { max_instructions, timeout, max_memory, max_value_len }
+}
+
+/// `path` from the config, `default` when absent and unlimited when negative.
+fn config_limit(vm: &mut VM, path: &str, default: usize) -> Option<usize> {
+    match vm.eval::<i64>(path) {
+        Ok(value) => usize::try_from(value).ok(),
+        Err(_) => Some(default),
+    }
+}
+
+fn config_usize(vm: &mut VM, path: &str) -> Option<usize> {
+    vm.eval::<i64>(path)
+        .ok()
+        .and_then(|value| usize::try_from(value).ok())
+}
+
+fn build_router(mut vm: VM, pool: Arc<VmPool>, max_body_bytes: usize) -> Result<Router, VmError> {
+    // let mut ready_vm = vm.lock().unwrap();
+    let mut list_routes = vm.eval::<List<Table>>("routes")?;
+    list_routes.force(&mut vm);
+    let list_routes_iter = list_routes.iter().unwrap();
+
+    Ok(list_routes_iter.fold(Router::new(), |router, route| {
+        let path = route.get::<String>("path").unwrap();
+        let function = route.get::<Function>("handler").unwrap();
+        // Routes without a `method` answer every method
+        let method = route.get::<String>("method").unwrap_or_else(|| "ANY".into());
+        // let result: Table = function.call(&mut ready_vm, ()).unwrap();
+
+        // let type_of_handler = result.get::<String>("type").unwrap();
+
+        router.route(&path, make_vm_handler(&method, function, pool.clone(), max_body_bytes))
+
+        // match type_of_handler.as_str() {
+        //     "html" => {
+        //         let html = result.get::<String>("html").unwrap();
+        //         router.route(&path, make_html_handler(html))
+        //     },
+        //     "htmlTemplate" => {
+        //         let path_template = result.get::<String>("path").unwrap();
+        //         let html = std::fs::read_to_string(&path_template)
+        //         .unwrap_or_else(|_| "<h1>Template not found</h1>".to_string());
+        //
+        //         router.route(&path, make_html_handler(html))
+        //     },
+        //     "json" => {
+        //         let mut json = result.get_value("body").unwrap();
+        //         json.resolve_thunk(&mut ready_vm).unwrap();
+        //         router.route(&path, make_json_handler_v2(json))
+        //     },
+        //     _ => todo!()
+        // }
+    }))
+}
+
+#[allow(dead_code)]
+fn make_html_handler(html: String) -> axum::routing::MethodRouter {
+    get(move || {
+        async move { Html(html.clone()) }
+    })
+}
+
+#[allow(dead_code)]
+fn make_html_template_handler(path: String) -> axum::routing::MethodRouter {
+    get(move || {
+        async move { Html(path.clone()) }
+    })
+}
+
+// fn make_json_handler(json: String) -> axum::routing::MethodRouter {
+//     let v: serde_json::Value = serde_json::from_str(&json).map_err(|e| e.to_string()).unwrap();
+//     get(move || {
+//         async move { Json(v) }
+//     })
+// }
+
+#[allow(dead_code)]
+fn make_json_handler_v2(json: Value) -> axum::routing::MethodRouter {
+    // let v: serde_json::Value = serde_json::from_str(&json).map_err(|e| e.to_string()).unwrap();
+    get(move || {
+        async move { Json(json) }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vm(src: &str) -> VM {
+        VM::new_from_bytes(angi::compiler::compile(src, "test.ag").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_execution_limits_default_to_finite_values() {
+        let limits = execution_limits(&mut vm("{ port = 3000; routes = []; }"));
+        assert_eq!(limits.max_instructions, Some(DEFAULT_MAX_INSTRUCTIONS));
+        assert_eq!(limits.timeout, Some(DEFAULT_TIMEOUT));
+        assert_eq!(limits.max_memory, Some(DEFAULT_MAX_MEMORY));
+        assert_eq!(limits.max_value_len, Some(DEFAULT_MAX_VALUE_LEN));
+
+        let limits = execution_limits(&mut vm(
+            "{ port = 3000; routes = []; server = { max_memory = 1024; max_value_len = -1; }; }",
+        ));
+        assert_eq!(limits.max_memory, Some(1024));
+        assert_eq!(limits.max_value_len, None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_returns_bind_errors() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let vm = vm(&format!("{{ port = {port}; routes = []; }}"));
+
+        let err = serve(vm, Arc::new(StaticStore::empty())).await.unwrap_err();
+        assert!(matches!(err, ServeError::Bind { .. }), "{err:?}");
+        assert!(err.to_string().contains(&format!("127.0.0.1:{port}")), "{err}");
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::{Arc, RwLock};
+
+use angi_runtime::{error::VmError, vm::VM};
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tower::ServiceExt;
+
+use crate::{ArcStore, app, logger, use_bundled_templates};
+
+/// The program served by `angi run --watch`, replaced whenever the sources
+/// compile again.
+///
+/// Every request clones the router it starts with, so a reload never
+/// interrupts requests already in flight, they finish on the old program.
+#[derive(Clone)]
+pub struct Reloader {
+    state: Arc<RwLock<State>>,
+    static_store: ArcStore,
+}
+
+#[derive(Default)]
+struct State {
+    router: Option<Router>,
+    port: Option<i64>,
+    /// The last failed reload, shown over HTML pages until a reload succeeds.
+    error: Option<String>,
+}
+
+impl Reloader {
+    pub fn new(static_store: ArcStore) -> Self {
+        use_bundled_templates(&static_store);
+        Self {
+            state: Arc::default(),
+            static_store,
+        }
+    }
+
+    /// Serve `vm` from the next request on. On error the current program
+    /// keeps serving.
+    pub fn reload(&self, mut vm: VM) -> Result<(), VmError> {
+        let port = vm.eval::<i64>("port")?;
+        let router = app(vm, self.static_store.clone())?;
+
+        let mut state = self.state.write().unwrap();
+        if state.port.is_some_and(|current| current != port) {
+            logger::warn(format!("The port changed to {port}, restart to listen on it"));
+        }
+        if state.router.is_some() {
+            logger::info("Reloaded the program");
+        }
+        state.router = Some(router);
+        state.port.get_or_insert(port);
+        state.error = None;
+
+        Ok(())
+    }
+
+    /// Keep serving the last good program and show `message` over its pages.
+    pub fn fail(&self, message: String) {
+        logger::error("Reload failed, serving the last good program");
+        self.state.write().unwrap().error = Some(message);
+    }
+
+    /// A router that forwards each request to the current program.
+    pub fn router(&self) -> Router {
+        let reloader = self.clone();
+        Router::new().fallback(move |request: Request| {
+            let reloader = reloader.clone();
+            async move { reloader.handle(request).await }
+        })
+    }
+
+    async fn handle(&self, request: Request) -> Response {
+        let (router, error) = {
+            let state = self.state.read().unwrap();
+            (state.router.clone(), state.error.clone())
+        };
+
+        let Some(router) = router else {
+            return (StatusCode::SERVICE_UNAVAILABLE, "No program loaded yet").into_response();
+        };
+
+        let response = router.oneshot(request).await.into_response();
+
+        match error {
+            Some(error) if is_html(&response) => with_overlay(response, &error).await,
+            _ => response,
+        }
+    }
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"))
+}
+
+/// Append the compile error to an HTML page, as a panel fixed over the content.
+async fn with_overlay(response: Response, error: &str) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Cannot read the response body").into_response();
+    };
+
+    let mut html = String::from_utf8_lossy(&bytes).into_owned();
+    html.push_str(&overlay(error));
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html))
+}
+
+fn overlay(error: &str) -> String {
+    format!(
+        concat!(
+            "<div id=\"angi-error-overlay\" style=\"position:fixed;inset:0;z-index:2147483647;",
+            "overflow:auto;padding:2rem;background:rgba(24,24,27,.95);color:#fca5a5;",
+            "font:14px/1.5 monospace\">",
+            "<h2 style=\"color:#f87171\">Compile error, serving the last good program</h2>",
+            "<pre style=\"white-space:pre-wrap\">{}</pre></div>"
+        ),
+        escape_html(error)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use angi_archive::StaticStore;
+
+    fn program(message: &str) -> VM {
+        let src = format!(r#"
+{{
+    port = 3000;
+    routes = [
+        {{
+            path = "/page";
+            handler = (req) => html("<p>{message}</p>");
+        }},
+        {{
+            path = "/data";
+            handler = (req) => json({{ message = "{message}"; }});
+        }}
+    ];
+}}
+        "#);
+        VM::new_from_bytes(angi::compiler::compile(&src, "test.ag").unwrap()).unwrap()
+    }
+
+    fn reloader() -> Reloader {
+        Reloader::new(Arc::new(StaticStore::empty()))
+    }
+
+    fn request(path: &str) -> Request {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn get(reloader: &Reloader, path: &str) -> String {
+        body(reloader.router().oneshot(request(path)).await.unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_reload_swaps_the_program() {
+        let reloader = reloader();
+        let response = reloader.router().oneshot(request("/page")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        reloader.reload(program("one")).unwrap();
+        assert_eq!(get(&reloader, "/page").await, "<p>one</p>");
+
+        reloader.reload(program("two")).unwrap();
+        assert_eq!(get(&reloader, "/page").await, "<p>two</p>");
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_keeps_serving_with_an_overlay() {
+        let reloader = reloader();
+        reloader.reload(program("one")).unwrap();
+        reloader.fail("<bad> & broken".into());
+
+        let page = get(&reloader, "/page").await;
+        assert!(page.starts_with("<p>one</p>"), "{page}");
+        assert!(page.contains("angi-error-overlay"), "{page}");
+        assert!(page.contains("&lt;bad&gt; &amp; broken"), "{page}");
+
+        assert_eq!(get(&reloader, "/data").await, r#"{"message":"one"}"#);
+
+        reloader.reload(program("two")).unwrap();
+        assert_eq!(get(&reloader, "/page").await, "<p>two</p>");
+    }
+
+    #[tokio::test]
+    async fn test_request_in_flight_finishes_on_the_old_program() {
+        let reloader = reloader();
+        reloader.reload(program("old")).unwrap();
+
+        let in_flight = reloader.router().oneshot(request("/page"));
+        tokio::pin!(in_flight);
+        // Start the request, it picks its router on the first poll
+        tokio::select! {
+            biased;
+            _ = &mut in_flight => panic!("The request finished without yielding"),
+            _ = std::future::ready(()) => {}
+        }
+
+        reloader.reload(program("new")).unwrap();
+
+        assert_eq!(body(in_flight.await.unwrap()).await, "<p>old</p>");
+        assert_eq!(get(&reloader, "/page").await, "<p>new</p>");
+    }
+}
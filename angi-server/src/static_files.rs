@@ -0,0 +1,223 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+
+use crate::ArcStore;
+
+/// Serve the files bundled in `store` under `prefix`, with their MIME type,
+/// an ETag and `Cache-Control: public, max-age=<max_age>`.
+pub fn static_handler(
+    store: ArcStore,
+    prefix: Option<String>,
+    max_age: usize,
+) -> axum::routing::MethodRouter {
+    axum::routing::get(move |uri: Uri, headers: HeaderMap| {
+        let store = store.clone();
+        let prefix = prefix.clone();
+
+        async move {
+            let path = match prefix.as_deref() {
+                Some(prefix) => uri.path().strip_prefix(prefix).filter(|rest| rest.starts_with('/')),
+                None => Some(uri.path()),
+            };
+
+            let file = path
+                .and_then(file_name)
+                .and_then(|name| store.static_file(&name).map(|bytes| (name, bytes)));
+
+            match file {
+                Some((name, bytes)) => file_response(&name, bytes, &headers, max_age),
+                None => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+            }
+        }
+    })
+}
+
+/// `/`, `/assets/` and `/docs/a%20b.css` to the entry names `index.html`,
+/// `assets/index.html` and `docs/a b.css`.
+fn file_name(path: &str) -> Option<String> {
+    let mut name = percent_decode(path.trim_start_matches('/'))?;
+    if name.is_empty() || name.ends_with('/') {
+        name.push_str("index.html");
+    }
+    Some(name)
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn file_response(path: &str, bytes: Vec<u8>, headers: &HeaderMap, max_age: usize) -> Response {
+    let etag = etag(&bytes);
+    let cache_control = format!("public, max-age={max_age}");
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').map(str::trim).any(|tag| tag == "*" || tag == etag)
+        });
+
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let mut response = Response::new(Body::from(bytes));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(mime.as_ref()).expect("MIME types are valid header values"),
+        );
+        response
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::ETAG, HeaderValue::from_str(&etag).expect("ETag is hex"));
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&cache_control).expect("Cache-Control is ASCII"),
+    );
+
+    response
+}
+
+/// A strong validator from the content, stable across restarts of the same binary.
+fn etag(bytes: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use angi_archive::{Archiver, Extractor, StaticStore};
+    use axum::Router;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const STYLE: &[u8] = b"body { color: red; }";
+    const INDEX: &[u8] = b"<p>index</p>";
+
+    fn store(name: &str) -> ArcStore {
+        let mut archiver = Archiver::new();
+        archiver.archive(STYLE.to_vec(), "static/style.css");
+        archiver.archive(INDEX.to_vec(), "static/index.html");
+        archiver.archive(b"a b".to_vec(), "static/docs/a b.txt");
+
+        let path = std::env::temp_dir().join(format!("angi-static-{}-{name}", std::process::id()));
+        std::fs::write(&path, archiver.get_bytes().unwrap()).unwrap();
+        let extractor = Extractor::init_from_file(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        Arc::new(StaticStore::new(extractor))
+    }
+
+    async fn get(name: &str, prefix: Option<&str>, request: Request<Body>) -> Response {
+        let router = Router::new().fallback(static_handler(store(name), prefix.map(String::from), 60));
+        router.oneshot(request).await.unwrap()
+    }
+
+    fn request(path: &str) -> Request<Body> {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    fn header(response: &Response, name: header::HeaderName) -> &str {
+        response.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_serves_files_with_mime_etag_and_cache_control() {
+        let response = get("headers", None, request("/style.css")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::CONTENT_TYPE), "text/css");
+        assert_eq!(header(&response, header::CACHE_CONTROL), "public, max-age=60");
+        assert_eq!(header(&response, header::ETAG), etag(STYLE));
+        assert_eq!(body(response).await, STYLE);
+
+        let response = get("index", None, request("/")).await;
+        assert_eq!(header(&response, header::CONTENT_TYPE), "text/html");
+        assert_eq!(body(response).await, INDEX);
+
+        let response = get("missing", None, request("/missing.css")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match_returns_not_modified() {
+        let conditional = |tag: &str| {
+            Request::builder()
+                .uri("/style.css")
+                .header(header::IF_NONE_MATCH, tag)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = get("match", None, conditional(&format!("\"other\", {}", etag(STYLE)))).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(header(&response, header::ETAG), etag(STYLE));
+        assert_eq!(header(&response, header::CACHE_CONTROL), "public, max-age=60");
+        assert!(body(response).await.is_empty());
+
+        let response = get("wildcard", None, conditional("*")).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get("stale", None, conditional("\"other\"")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, STYLE);
+    }
+
+    #[tokio::test]
+    async fn test_strips_the_prefix() {
+        let prefix = Some("/assets");
+
+        let response = get("prefixed", prefix, request("/assets/style.css")).await;
+        assert_eq!(body(response).await, STYLE);
+
+        let response = get("prefix-index", prefix, request("/assets/")).await;
+        assert_eq!(body(response).await, INDEX);
+
+        let response = get("prefix-encoded", prefix, request("/assets/docs/a%20b.txt")).await;
+        assert_eq!(body(response).await, b"a b");
+
+        for path in ["/assets", "/assetsstyle.css", "/style.css"] {
+            let response = get("unprefixed", prefix, request(path)).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("/").as_deref(), Some("index.html"));
+        assert_eq!(file_name("/assets/").as_deref(), Some("assets/index.html"));
+        assert_eq!(file_name("/docs/a%20b.css").as_deref(), Some("docs/a b.css"));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+}
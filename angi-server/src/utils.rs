@@ -1,8 +1,11 @@
 use std::{collections::HashMap};
+use std::sync::Arc;
 
-use angi_runtime::{tree::Tree, value::{Function, Table, Value}, vm::VM};
+use angi_runtime::{error::VmError, limits::Limit, modules, pool::VmPool, tree::Tree, value::{FromValue, Function, Table, Value}, vm::VM};
 use axum::{Json, body::Body, extract::{Path, Query, Request}, http::{HeaderMap, StatusCode}, response::{Html, IntoResponse, Response}, routing::{any, get, post}};
 
+use crate::logger;
+
 // use crate::Avm;
 
 fn build_request_value(
@@ -32,7 +35,7 @@ fn build_request_value(
 
     match body {
         Some(b) => {
-            insert_json(&mut root, vec!["body"], b);
+            insert_json(&mut root, vec!["body".to_string()], b);
         }
         None => {
             root.insert(vec!["body"], Value::None).unwrap();
@@ -44,64 +47,67 @@ fn build_request_value(
 
 fn insert_json(
     tree: &mut Tree<Value>,
-    path: Vec<&str>,
+    path: Vec<String>,
     value: serde_json::Value,
 ) {
-    match value {
-        serde_json::Value::Null => {
-            tree.insert(path, Value::None).unwrap();
-        }
-
-        serde_json::Value::Bool(b) => {
-            tree.insert(path, Value::Bool(b)).unwrap();
-        }
-
-        serde_json::Value::Number(n) => {
-            tree.insert(path, Value::Int(n.as_i64().unwrap_or(0))).unwrap();
-        }
-
-        serde_json::Value::String(s) => {
-            tree.insert(path, Value::String(s)).unwrap();
-        }
-
+    let value = match value {
+        serde_json::Value::Null => Value::None,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(int) => Value::Int(int),
+            None => Value::Float(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_json::Value::String(s) => Value::String(s),
         serde_json::Value::Array(arr) => {
             for (i, v) in arr.into_iter().enumerate() {
                 let mut new_path = path.clone();
-                new_path.push(Box::leak(i.to_string().into_boxed_str()));
+                new_path.push(i.to_string());
                 insert_json(tree, new_path, v);
             }
+            return;
         }
-
         serde_json::Value::Object(map) => {
             for (k, v) in map {
                 let mut new_path = path.clone();
-                new_path.push(Box::leak(k.into_boxed_str()));
+                new_path.push(k);
                 insert_json(tree, new_path, v);
             }
+            return;
         }
-    }
+    };
+
+    // The tree keeps its own copy of every key
+    tree.insert(path.iter().map(String::as_str).collect(), value).unwrap();
 }
 
-pub fn make_vm_handler(method: &str, function: Function, avm: VM) -> axum::routing::MethodRouter {
+pub fn make_vm_handler(
+    method: &str,
+    function: Function,
+    pool: Arc<VmPool>,
+    max_body_bytes: usize,
+) -> axum::routing::MethodRouter {
     let handler = move |
         Path(path): Path<HashMap<String, String>>,
         Query(query): Query<HashMap<String, String>>,
         req: Request<Body>,
     | {
-        let mut vm = avm.clone();
+        let pool = pool.clone();
         let function = function.clone();
 
         async move {
 
             let headers = req.headers().clone();
 
-            let body_bytes = axum::body::to_bytes(req.into_body(), usize::MAX)
-                .await
-                .unwrap_or_default();
+            let body_bytes = match axum::body::to_bytes(req.into_body(), max_body_bytes).await {
+                Ok(bytes) => bytes,
+                Err(_) => return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Request body exceeds {} bytes", max_body_bytes),
+                ).into_response(),
+            };
 
             let body_json = serde_json::from_slice(&body_bytes).ok();
 
-            // let mut ready_vm = vm.lock().unwrap();
             let input = build_request_value(
                 path,
                 query,
@@ -109,15 +115,28 @@ pub fn make_vm_handler(method: &str, function: Function, avm: VM) -> axum::routi
                 body_json,
             );
 
-            let result = function.call::<Table, _>(&mut vm, (input,));
+            // Angi code can be CPU heavy, keep it off the async reactor
+            let response = tokio::task::spawn_blocking(move || {
+                let mut vm = pool.get();
 
-            match result {
-                Ok(val) => table_to_response(val, vm.clone()),
-                Err(e) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("VM Error: {:?}", e),
-                ).into_response(),
-            }
+                let result = function
+                    .call::<Table, _>(&mut vm, (input,))
+                    .and_then(|val| table_to_response(val, &mut vm));
+
+                match result {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let trace = vm.stack_trace().map(|trace| trace.to_string()).unwrap_or_default();
+                        logger::error(format!("{}\n{}", e, trace));
+                        vm_error_response(e)
+                    }
+                }
+            }).await;
+
+            response.unwrap_or_else(|e| {
+                logger::error(format!("Handler Error: {}", e));
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            })
         }
     };
 
@@ -185,32 +204,60 @@ pub fn make_vm_handler(method: &str, function: Function, avm: VM) -> axum::routi
 //     }
 // }
 
-pub fn table_to_response(table: Table, mut vm: VM) -> Response {
+/// Budget exhaustion is reported as unavailable, a timeout as a gateway timeout
+/// and a blown memory or value cap as insufficient storage. The body only
+/// names the status, the error and its trace go to the log.
+fn vm_error_response(err: VmError) -> Response {
+    let status = match err {
+        VmError::LimitExceeded { limit: Limit::Instructions(_) } => StatusCode::SERVICE_UNAVAILABLE,
+        VmError::LimitExceeded { limit: Limit::Time(_) } => StatusCode::GATEWAY_TIMEOUT,
+        VmError::LimitExceeded { limit: Limit::Memory(_) | Limit::ValueLen(_) } => StatusCode::INSUFFICIENT_STORAGE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, status.canonical_reason().unwrap_or_default()).into_response()
+}
+
+/// Forcing a lazy `json` body runs Angi code, its errors are returned, as
+/// are missing fields and unknown response types.
+pub fn table_to_response(table: Table, vm: &mut VM) -> Result<Response, VmError> {
         // let mut ready_vm = vm.lock().unwrap();
-        let type_of_handler = table.get::<String>("type").unwrap();
+        let type_of_handler = response_field::<String>(&table, "type")?;
 
         match type_of_handler.as_str() {
             "html" => {
-                let html = table.get::<String>("html").unwrap();
-                (StatusCode::from_u16(200).unwrap(), Html(html)).into_response()
+                let html = response_field::<String>(&table, "html")?;
+                Ok((StatusCode::OK, Html(html)).into_response())
             },
             "htmlTemplate" => {
-                let path_template = table.get::<String>("path").unwrap();
-                let html = std::fs::read_to_string(&path_template)
+                let path_template = response_field::<String>(&table, "path")?;
+                let html = modules::load_template(&path_template)
                 .unwrap_or_else(|_| "<h1>Template not found</h1>".to_string());
-                (StatusCode::from_u16(200).unwrap(), Html(html)).into_response()
+                Ok((StatusCode::OK, Html(html)).into_response())
 
             },
             "json" => {
-                let mut json = table.get_value("body").unwrap();
-                json.resolve_thunk(&mut vm).unwrap();
-                (StatusCode::from_u16(200).unwrap(), Json(json)).into_response()
+                let mut json = table.get_value("body").ok_or_else(|| missing_field("body"))?;
+                json.resolve_thunk(vm)?;
+                Ok((StatusCode::OK, Json(json)).into_response())
             },
-            _ => todo!()
+            other => Err(VmError::ValueTypeMismatch {
+                message: format!("Unknown response type \"{other}\", expected html, htmlTemplate or json"),
+            }),
         }
 
 }
 
+fn response_field<T: FromValue>(table: &Table, key: &str) -> Result<T, VmError> {
+    table.get::<T>(key).ok_or_else(|| missing_field(key))
+}
+
+fn missing_field(key: &str) -> VmError {
+    VmError::NotFoundAttribute {
+        message: format!("A handler response needs a `{key}` field"),
+    }
+}
+
 // fn map_to_table(map: HashMap<String, String>) -> Value {
 //     let mut table = Table::new();
 //     for (k, v) in map {
@@ -218,3 +265,71 @@ pub fn table_to_response(table: Table, mut vm: VM) -> Response {
 //     }
 //     Value::Table(table)
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vm(src: &str) -> VM {
+        VM::new_from_bytes(angi::compiler::compile(src, "test.ag").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_table_to_response_returns_body_errors() {
+        let mut vm = vm(r#"
+{
+    ok = json({ message = "hi"; });
+    failing = json({ message = let { nope } = { a = 1; }; in nope; });
+}
+        "#);
+
+        let table = vm.eval::<Table>("ok").unwrap();
+        assert_eq!(table_to_response(table, &mut vm).unwrap().status(), StatusCode::OK);
+
+        let table = vm.eval::<Table>("failing").unwrap();
+        assert!(table_to_response(table, &mut vm).is_err());
+    }
+
+    #[test]
+    fn test_table_to_response_rejects_malformed_responses() {
+        let mut vm = vm(r#"
+{
+    untyped = { html = "<p>hi</p>"; };
+    unknown = { type = "xml"; body = "<hi/>"; };
+    incomplete = { type = "html"; };
+}
+        "#);
+
+        for name in ["untyped", "unknown", "incomplete"] {
+            let table = vm.eval::<Table>(name).unwrap();
+            let err = table_to_response(table, &mut vm).unwrap_err();
+            assert_eq!(vm_error_response(err).status(), StatusCode::INTERNAL_SERVER_ERROR, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_build_request_value_keeps_json_keys() {
+        let body = serde_json::json!({ "user": { "name": "angi", "tags": ["a", "b"] } });
+        let Value::Table(tree) = build_request_value(HashMap::new(), HashMap::new(), HeaderMap::new(), Some(body)) else {
+            panic!("Expected a table")
+        };
+
+        let name = tree.get(vec!["body", "user", "name"]);
+        assert!(matches!(name.as_ref(), Some(Value::String(name)) if name == "angi"), "{name:?}");
+        let tag = tree.get(vec!["body", "user", "tags", "1"]);
+        assert!(matches!(tag.as_ref(), Some(Value::String(tag)) if tag == "b"), "{tag:?}");
+    }
+
+    #[tokio::test]
+    async fn test_vm_error_response_hides_the_error() {
+        let err = VmError::UnexpectedError { message: "secret.ag:3 internals".into() };
+        let response = vm_error_response(err);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "Internal Server Error");
+
+        let err = VmError::LimitExceeded { limit: Limit::Time(std::time::Duration::from_millis(5)) };
+        assert_eq!(vm_error_response(err).status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -24,6 +24,10 @@ impl Display for LogLevel {
 }
 
 impl Log {
+   pub fn is_enabled() -> bool {
+        IS_DEBUG
+   }
+
    pub fn write(log_level: LogLevel, message: &str) {
         if IS_DEBUG {
             println!("{} {}", log_level, message);
@@ -52,7 +52,7 @@ pub fn read_n_bytes_from_cursor(bytes: &[u8], cursor: usize, n: usize) -> Option
 }
 
 pub fn read_str_with_len(bytes: &[u8], cursor: &mut usize, str_len: usize) -> Option<String>{
-    let slice = &bytes[*cursor..*cursor + str_len];
+    let slice = bytes.get(*cursor..cursor.checked_add(str_len)?)?;
     let str = read_str_from_bytes(slice);
     *cursor += str_len;
     str
@@ -4,6 +4,11 @@ use crate::{log::Log, log::LogLevel::{ERROR, DEBUG}, read_from_buf_reader::u8_sl
 const PADDING: usize = 16;
 
 pub fn read_ins(ins: u32) {
+    // Skip the formatting entirely on the hot path when logging is off
+    if !Log::is_enabled() {
+        return;
+    }
+
     let str_ins = u8_slice_to_binary_string(&ins.to_be_bytes());
     let opcode = extract_opcode(ins);
     match opcode {